@@ -0,0 +1,70 @@
+use clap::Parser;
+use nostr_probe::store::{Added, Store};
+use nostr_probe::verify::{check_event_json, Failure};
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+
+/// Import JSONL events (such as the output of the fetch tools) into the local archive
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Files to import (reads stdin if none are given)
+    files: Vec<PathBuf>,
+
+    /// Archive directory
+    #[arg(short, long)]
+    archive: Option<PathBuf>,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let dir = args.archive.unwrap_or_else(Store::default_path);
+    let mut store = Store::open(&dir)?;
+
+    let readers: Vec<Box<dyn BufRead>> = if args.files.is_empty() {
+        vec![Box::new(BufReader::new(std::io::stdin()))]
+    } else {
+        let mut readers: Vec<Box<dyn BufRead>> = Vec::new();
+        for path in &args.files {
+            readers.push(Box::new(BufReader::new(std::fs::File::open(path)?)));
+        }
+        readers
+    };
+
+    let mut stored: usize = 0;
+    let mut skipped: usize = 0;
+    let mut invalid: usize = 0;
+    for reader in readers {
+        // Read bytes rather than strings so that a line of bad UTF-8 fails by itself
+        for line in reader.split(b'\n') {
+            let line = line?;
+            let checked = match std::str::from_utf8(&line) {
+                Ok(line) if line.trim().is_empty() => continue,
+                Ok(line) => check_event_json(line, 900),
+                Err(e) => Err(Failure::BadJson(format!("not UTF-8: {}", e))),
+            };
+            let event = match checked {
+                Ok(e) => e,
+                Err(failure) => {
+                    eprintln!("{}", failure);
+                    invalid += 1;
+                    continue;
+                }
+            };
+            match store.add(&event)? {
+                Added::Stored => stored += 1,
+                _ => skipped += 1,
+            }
+        }
+    }
+    store.sync()?;
+
+    eprintln!(
+        "{} stored, {} skipped (duplicate, superseded, deleted or ephemeral), {} invalid",
+        stored, skipped, invalid
+    );
+    eprintln!("{} events in {}", store.len(), dir.display());
+
+    Ok(())
+}
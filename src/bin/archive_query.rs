@@ -0,0 +1,37 @@
+use clap::Parser;
+use nostr_probe::output::{self, Source};
+use nostr_probe::store::Store;
+use nostr_types::Filter;
+use std::path::PathBuf;
+
+/// Run filters against the local archive, printing matching events
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Filter JSON (may be repeated; events matching any filter are returned)
+    #[arg(required = true)]
+    filters: Vec<String>,
+
+    /// Archive directory
+    #[arg(short, long)]
+    archive: Option<PathBuf>,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let _finish = output::finish_on_drop();
+    let args = Args::parse();
+
+    let mut filters: Vec<Filter> = Vec::new();
+    for filter in &args.filters {
+        filters.push(serde_json::from_str(filter)?);
+    }
+
+    let dir = args.archive.unwrap_or_else(Store::default_path);
+    let store = Store::open(&dir)?;
+
+    for event in store.query(&filters)? {
+        output::event(Source::default(), &event)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,475 @@
+use clap::Parser;
+use nostr_probe::conformance::Author;
+use nostr_probe::{Command, Probe};
+use nostr_types::{Event, EventKind, Filter, Id, RelayMessage, SubscriptionId, Unixtime};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{Receiver, Sender};
+
+// How long to wait for a response before giving up on it
+const RESPONSE_TIMEOUT_SECS: u64 = 30;
+
+// An ephemeral kind, which relays pass on to subscribers without storing, so that
+// benchmarking a public relay leaves nothing behind
+const DEFAULT_KIND: u32 = 20999;
+
+/// Benchmark a relay: OK latency when publishing, and time to EOSE and streaming
+/// rate for a set of representative filters
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// The relay to benchmark
+    relay: String,
+
+    /// Number of events to sign and publish
+    #[arg(short = 'n', long, default_value_t = 100)]
+    events: usize,
+
+    /// Kind of the events to publish. The default is ephemeral, so relays don't
+    /// keep them; a stored kind such as 1 also times the relay's writes.
+    #[arg(long, default_value_t = DEFAULT_KIND)]
+    kind: u32,
+
+    /// Publish the events in this directory (one JSON event per file, as for
+    /// post_from_files) instead of signing new ones
+    #[arg(long)]
+    directory: Option<PathBuf>,
+
+    /// Maximum number of events awaiting an OK at once
+    #[arg(short, long, default_value_t = 1)]
+    concurrency: usize,
+
+    /// Filter JSON to time (may be repeated). Defaults to a representative set.
+    #[arg(long)]
+    filter: Vec<String>,
+
+    /// How many times to run each filter
+    #[arg(long, default_value_t = 3)]
+    runs: usize,
+
+    /// Print the results as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Latency {
+    min_ms: f64,
+    p50_ms: f64,
+    p95_ms: f64,
+    p99_ms: f64,
+    max_ms: f64,
+    mean_ms: f64,
+}
+
+impl Latency {
+    fn from_durations(mut durations: Vec<Duration>) -> Option<Latency> {
+        if durations.is_empty() {
+            return None;
+        }
+        durations.sort();
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        let total: Duration = durations.iter().sum();
+        Some(Latency {
+            min_ms: ms(durations[0]),
+            p50_ms: ms(percentile(&durations, 50.0)),
+            p95_ms: ms(percentile(&durations, 95.0)),
+            p99_ms: ms(percentile(&durations, 99.0)),
+            max_ms: ms(durations[durations.len() - 1]),
+            mean_ms: ms(total) / durations.len() as f64,
+        })
+    }
+}
+
+// The nearest-rank percentile of some sorted durations: the smallest that at least
+// `p` percent of them are no greater than
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[derive(Debug, Default, Serialize)]
+struct PublishResult {
+    events: usize,
+    accepted: usize,
+    rejected: usize,
+    unanswered: usize,
+    elapsed_secs: f64,
+    events_per_sec: f64,
+    ok_latency: Option<Latency>,
+}
+
+#[derive(Debug, Serialize)]
+struct QueryResult {
+    filter: serde_json::Value,
+    runs: usize,
+    failed_runs: usize,
+    events: usize,
+    time_to_first_event: Option<Latency>,
+    time_to_eose: Option<Latency>,
+    events_per_sec: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    relay: String,
+    publish: PublishResult,
+    queries: Vec<QueryResult>,
+}
+
+struct Connection {
+    to_probe: Sender<Command>,
+    from_probe: Receiver<RelayMessage>,
+    join_handle: tokio::task::JoinHandle<()>,
+    next_sub: usize,
+}
+
+impl Connection {
+    fn open(url: &str) -> Connection {
+        let (to_probe, from_main) = tokio::sync::mpsc::channel::<Command>(100);
+        let (to_main, from_probe) = tokio::sync::mpsc::channel::<RelayMessage>(100);
+        let relay_url = url.to_owned();
+        let join_handle = tokio::spawn(async move {
+            let mut probe = Probe::new(from_main, to_main);
+            // Printing every frame would be timed along with the relay
+            probe.quiet = true;
+            if let Err(e) = probe
+                .connect_and_listen_with_timeout(&relay_url, RESPONSE_TIMEOUT_SECS)
+                .await
+            {
+                eprintln!("{}", e);
+            }
+        });
+        Connection {
+            to_probe,
+            from_probe,
+            join_handle,
+            next_sub: 0,
+        }
+    }
+
+    async fn close(self) {
+        let _ = self.to_probe.send(Command::Exit).await;
+        let _ = self.join_handle.await;
+    }
+
+    async fn recv(&mut self) -> Option<RelayMessage> {
+        let timeout = Duration::from_secs(RESPONSE_TIMEOUT_SECS);
+        tokio::time::timeout(timeout, self.from_probe.recv())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    // Publish the events with up to `concurrency` awaiting an OK, timing each OK
+    async fn publish(
+        &mut self,
+        events: &[Event],
+        concurrency: usize,
+    ) -> Result<PublishResult, Box<dyn std::error::Error>> {
+        let mut result = PublishResult {
+            events: events.len(),
+            ..Default::default()
+        };
+        let mut sent_at: HashMap<Id, Instant> = HashMap::new();
+        let mut latencies: Vec<Duration> = Vec::new();
+        let mut next = events.iter();
+        let start = Instant::now();
+
+        loop {
+            while sent_at.len() < concurrency.max(1) {
+                match next.next() {
+                    Some(event) => {
+                        sent_at.insert(event.id, Instant::now());
+                        self.to_probe
+                            .send(Command::PostEvent(event.clone()))
+                            .await?;
+                    }
+                    None => break,
+                }
+            }
+            if sent_at.is_empty() {
+                break;
+            }
+            match self.recv().await {
+                Some(RelayMessage::Ok(id, ok, _)) => {
+                    if let Some(at) = sent_at.remove(&id) {
+                        latencies.push(at.elapsed());
+                        if ok {
+                            result.accepted += 1;
+                        } else {
+                            result.rejected += 1;
+                        }
+                    }
+                }
+                Some(_) => {}
+                None => break,
+            }
+        }
+
+        let elapsed = start.elapsed().as_secs_f64();
+        result.unanswered = result.events - result.accepted - result.rejected;
+        result.elapsed_secs = elapsed;
+        if elapsed > 0.0 {
+            result.events_per_sec = (result.accepted + result.rejected) as f64 / elapsed;
+        }
+        result.ok_latency = Latency::from_durations(latencies);
+        Ok(result)
+    }
+
+    // Run a filter `runs` times, timing the first event and EOSE
+    async fn query(
+        &mut self,
+        filter: &Filter,
+        runs: usize,
+    ) -> Result<QueryResult, Box<dyn std::error::Error>> {
+        let mut first_event: Vec<Duration> = Vec::new();
+        let mut eose: Vec<Duration> = Vec::new();
+        let mut events: usize = 0;
+        let mut failed_runs: usize = 0;
+
+        for _ in 0..runs {
+            self.next_sub += 1;
+            let sub = SubscriptionId(format!("benchmark-{}", self.next_sub));
+            let start = Instant::now();
+            let mut count: usize = 0;
+            self.to_probe
+                .send(Command::FetchEvents(sub.clone(), vec![filter.clone()]))
+                .await?;
+            loop {
+                match self.recv().await {
+                    Some(RelayMessage::Event(s, _)) if s == sub => {
+                        if count == 0 {
+                            first_event.push(start.elapsed());
+                        }
+                        count += 1;
+                    }
+                    Some(RelayMessage::Eose(s)) if s == sub => {
+                        eose.push(start.elapsed());
+                        events += count;
+                        self.to_probe.send(Command::Close(sub)).await?;
+                        break;
+                    }
+                    Some(RelayMessage::Closed(s, _)) if s == sub => {
+                        failed_runs += 1;
+                        break;
+                    }
+                    Some(_) => {}
+                    None => {
+                        failed_runs += 1;
+                        break;
+                    }
+                }
+            }
+        }
+
+        let streaming: f64 = eose.iter().map(|d| d.as_secs_f64()).sum();
+        Ok(QueryResult {
+            filter: serde_json::to_value(filter)?,
+            runs,
+            failed_runs,
+            events,
+            time_to_first_event: Latency::from_durations(first_event),
+            time_to_eose: Latency::from_durations(eose),
+            events_per_sec: if streaming > 0.0 {
+                events as f64 / streaming
+            } else {
+                0.0
+            },
+        })
+    }
+}
+
+fn load_directory(directory: &Path) -> Result<Vec<Event>, Box<dyn std::error::Error>> {
+    let mut events: Vec<Event> = Vec::new();
+    for entry in std::fs::read_dir(directory)? {
+        let mut contents: String = String::new();
+        std::fs::File::open(entry?.path())?.read_to_string(&mut contents)?;
+        let event: Event = serde_json::from_str(&contents)?;
+        event.verify(None)?;
+        events.push(event);
+    }
+    Ok(events)
+}
+
+fn print_latency(label: &str, latency: &Option<Latency>) {
+    match latency {
+        Some(l) => println!(
+            "{:<22} {:>9.1} {:>9.1} {:>9.1} {:>9.1} {:>9.1}",
+            label, l.min_ms, l.p50_ms, l.p95_ms, l.p99_ms, l.max_ms
+        ),
+        None => println!("{:<22} {:>9}", label, "-"),
+    }
+}
+
+fn print_report(report: &Report) {
+    println!("Relay: {}", report.relay);
+    println!();
+    let p = &report.publish;
+    println!(
+        "Published {} events in {:.2}s ({:.1}/s): {} accepted, {} rejected, {} unanswered",
+        p.events, p.elapsed_secs, p.events_per_sec, p.accepted, p.rejected, p.unanswered
+    );
+    println!();
+    println!(
+        "{:<22} {:>9} {:>9} {:>9} {:>9} {:>9}",
+        "(ms)", "min", "p50", "p95", "p99", "max"
+    );
+    print_latency("OK latency", &p.ok_latency);
+    for (n, q) in report.queries.iter().enumerate() {
+        println!();
+        println!(
+            "Filter {}: {} ({} events over {} runs, {} failed, {:.1} events/s)",
+            n + 1,
+            q.filter,
+            q.events,
+            q.runs,
+            q.failed_runs,
+            q.events_per_sec
+        );
+        print_latency("time to first event", &q.time_to_first_event);
+        print_latency("time to EOSE", &q.time_to_eose);
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let author = Author::new();
+
+    // Sign everything up front so signing isn't part of the measurement
+    let events: Vec<Event> = match &args.directory {
+        Some(directory) => load_directory(directory)?,
+        None => {
+            let now = Unixtime::now().0;
+            (0..args.events)
+                .map(|n| {
+                    let content = format!(
+                        "nostr-probe benchmark event {}. It can be ignored or deleted.",
+                        n
+                    );
+                    author.sign(args.kind, now, vec![], &content)
+                })
+                .collect()
+        }
+    };
+
+    let filters: Vec<Filter> = if args.filter.is_empty() {
+        let mut ours = Filter::new();
+        ours.add_author(author.pubkey);
+        let mut notes = Filter::new();
+        notes.add_event_kind(EventKind::TextNote);
+        notes.limit = Some(100);
+        let mut recent = Filter::new();
+        recent.add_event_kind(EventKind::TextNote);
+        recent.since = Some(Unixtime(Unixtime::now().0 - 3600));
+        recent.limit = Some(500);
+        let mut metadata = Filter::new();
+        metadata.add_event_kind(EventKind::Metadata);
+        metadata.limit = Some(100);
+        vec![ours, notes, recent, metadata]
+    } else {
+        args.filter
+            .iter()
+            .map(|f| serde_json::from_str(f))
+            .collect::<Result<Vec<Filter>, _>>()?
+    };
+
+    let mut conn = Connection::open(&args.relay);
+
+    eprintln!("Publishing {} events", events.len());
+    let publish = conn.publish(&events, args.concurrency).await?;
+
+    let mut queries: Vec<QueryResult> = Vec::new();
+    for filter in &filters {
+        eprintln!("Timing {}", serde_json::to_string(filter)?);
+        queries.push(conn.query(filter, args.runs).await?);
+    }
+
+    conn.close().await;
+
+    let report = Report {
+        relay: args.relay.clone(),
+        publish,
+        queries,
+    };
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(&report);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_probe::mock_relay::{Behaviour, MockRelay};
+
+    fn ms(ms: &[u64]) -> Vec<Duration> {
+        ms.iter().map(|m| Duration::from_millis(*m)).collect()
+    }
+
+    #[test]
+    fn nearest_rank_percentiles() {
+        let one = ms(&[7]);
+        for p in [0.0, 50.0, 95.0, 99.0, 100.0] {
+            assert_eq!(percentile(&one, p), one[0]);
+        }
+
+        let two = ms(&[10, 20]);
+        assert_eq!(percentile(&two, 50.0), two[0]);
+        assert_eq!(percentile(&two, 51.0), two[1]);
+        assert_eq!(percentile(&two, 95.0), two[1]);
+
+        let ties = ms(&[5, 5, 5, 10]);
+        assert_eq!(percentile(&ties, 50.0), ties[0]);
+        assert_eq!(percentile(&ties, 75.0), ties[2]);
+        assert_eq!(percentile(&ties, 76.0), ties[3]);
+
+        let hundred: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&hundred, 50.0), Duration::from_millis(50));
+        assert_eq!(percentile(&hundred, 95.0), Duration::from_millis(95));
+        assert_eq!(percentile(&hundred, 99.0), Duration::from_millis(99));
+    }
+
+    #[test]
+    fn latency_sorts_first() {
+        let latency = Latency::from_durations(ms(&[30, 10, 20])).unwrap();
+        assert_eq!(latency.min_ms, 10.0);
+        assert_eq!(latency.max_ms, 30.0);
+        assert_eq!(latency.p50_ms, 20.0);
+        assert!(Latency::from_durations(vec![]).is_none());
+    }
+
+    #[tokio::test]
+    async fn benchmarks_a_mock_relay() {
+        let relay = MockRelay::start(vec![], Behaviour::default())
+            .await
+            .unwrap();
+        let author = Author::new();
+        let now = Unixtime::now().0;
+        let events: Vec<Event> = (0..5)
+            .map(|n| author.sign(DEFAULT_KIND, now, vec![], &n.to_string()))
+            .collect();
+
+        let mut conn = Connection::open(&relay.url);
+        let publish = conn.publish(&events, 2).await.unwrap();
+        assert_eq!(
+            (publish.accepted, publish.rejected, publish.unanswered),
+            (5, 0, 0)
+        );
+        assert!(publish.ok_latency.is_some());
+
+        let query = conn.query(&author.filter(DEFAULT_KIND), 3).await.unwrap();
+        assert_eq!((query.runs, query.failed_runs, query.events), (3, 0, 15));
+        assert!(query.time_to_first_event.is_some());
+        assert!(query.time_to_eose.is_some());
+        conn.close().await;
+    }
+}
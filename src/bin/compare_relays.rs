@@ -0,0 +1,322 @@
+use clap::Parser;
+use futures_util::future::join_all;
+use nostr_probe::outbox::normalize_relay_url;
+use nostr_probe::store::replaceable_address;
+use nostr_types::{Event, Filter, Id};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+/// Run the same filters against two or more relays at once and report which
+/// events each relay is missing, and which replaceable events differ in version
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// The relays to compare
+    #[arg(required = true, num_args = 2..)]
+    relays: Vec<String>,
+
+    /// Filter JSON (may be repeated). Filters without a limit are paged through.
+    #[arg(short, long, required = true)]
+    filter: Vec<String>,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct RelayReport {
+    relay: String,
+    error: Option<String>,
+    events: usize,
+    only_here: Vec<String>,
+    missing_here: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Version {
+    id: String,
+    created_at: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct VersionReport {
+    address: String,
+    /// The newest version on each relay, or None if the relay has none
+    versions: BTreeMap<String, Option<Version>>,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    relays: Vec<RelayReport>,
+    different_versions: Vec<VersionReport>,
+}
+
+// The relays to compare, normalized so that one relay given twice (however it is
+// spelled) is only fetched and reported once
+fn distinct_relays(given: &[String]) -> Result<Vec<String>, String> {
+    let mut relays: Vec<String> = Vec::new();
+    for relay in given {
+        let url =
+            normalize_relay_url(relay).ok_or_else(|| format!("Not a relay url: {}", relay))?;
+        if relays.contains(&url) {
+            eprintln!("{} is given more than once", url);
+        } else {
+            relays.push(url);
+        }
+    }
+    if relays.len() < 2 {
+        return Err("Give at least two different relays to compare".to_owned());
+    }
+    Ok(relays)
+}
+
+fn compare(relays: &[String], results: Vec<Result<Vec<Event>, String>>) -> Report {
+    let answered: Vec<(usize, &Vec<Event>)> = results
+        .iter()
+        .enumerate()
+        .filter_map(|(i, r)| r.as_ref().ok().map(|events| (i, events)))
+        .collect();
+
+    // Which relays have each regular event, and the newest version of each
+    // replaceable address on each relay
+    let mut holders: HashMap<Id, BTreeSet<usize>> = HashMap::new();
+    let mut newest: BTreeMap<String, BTreeMap<usize, &Event>> = BTreeMap::new();
+    for (i, events) in &answered {
+        for event in events.iter() {
+            match replaceable_address(event) {
+                Some(address) => {
+                    let entry = newest.entry(address).or_default();
+                    let replace = match entry.get(i) {
+                        Some(e) => {
+                            (e.created_at.0, e.id.as_hex_string())
+                                < (event.created_at.0, event.id.as_hex_string())
+                        }
+                        None => true,
+                    };
+                    if replace {
+                        entry.insert(*i, event);
+                    }
+                }
+                None => {
+                    holders.entry(event.id).or_default().insert(*i);
+                }
+            }
+        }
+    }
+
+    let mut reports: Vec<RelayReport> = Vec::new();
+    for (i, result) in results.iter().enumerate() {
+        let mut report = RelayReport {
+            relay: relays[i].clone(),
+            error: None,
+            events: 0,
+            only_here: Vec::new(),
+            missing_here: Vec::new(),
+        };
+        match result {
+            Err(e) => report.error = Some(e.clone()),
+            Ok(events) => {
+                report.events = events.len();
+                for (id, have) in &holders {
+                    if !have.contains(&i) {
+                        report.missing_here.push(id.as_hex_string());
+                    } else if have.len() == 1 && answered.len() > 1 {
+                        report.only_here.push(id.as_hex_string());
+                    }
+                }
+                report.only_here.sort();
+                report.missing_here.sort();
+            }
+        }
+        reports.push(report);
+    }
+
+    let mut different_versions: Vec<VersionReport> = Vec::new();
+    for (address, per_relay) in &newest {
+        let ids: HashSet<Option<Id>> = answered
+            .iter()
+            .map(|(i, _)| per_relay.get(i).map(|e| e.id))
+            .collect();
+        if ids.len() > 1 {
+            let versions = answered
+                .iter()
+                .map(|(i, _)| {
+                    let version = per_relay.get(i).map(|e| Version {
+                        id: e.id.as_hex_string(),
+                        created_at: e.created_at.0,
+                    });
+                    (relays[*i].clone(), version)
+                })
+                .collect();
+            different_versions.push(VersionReport {
+                address: address.clone(),
+                versions,
+            });
+        }
+    }
+
+    Report {
+        relays: reports,
+        different_versions,
+    }
+}
+
+fn print_report(report: &Report) {
+    for r in &report.relays {
+        match &r.error {
+            Some(e) => println!("{}: FAILED: {}", r.relay, e),
+            None => println!(
+                "{}: {} events, {} only here, {} missing",
+                r.relay,
+                r.events,
+                r.only_here.len(),
+                r.missing_here.len()
+            ),
+        }
+        for id in &r.only_here {
+            println!("  only here: {}", id);
+        }
+        for id in &r.missing_here {
+            println!("  missing:   {}", id);
+        }
+    }
+    if !report.different_versions.is_empty() {
+        println!();
+        println!("Replaceable events that differ:");
+        for v in &report.different_versions {
+            println!("  {}", v.address);
+            for (relay, version) in &v.versions {
+                match version {
+                    Some(version) => {
+                        println!("    {}: {} at {}", relay, version.id, version.created_at)
+                    }
+                    None => println!("    {}: (none)", relay),
+                }
+            }
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let filters: Vec<Filter> = args
+        .filter
+        .iter()
+        .map(|f| serde_json::from_str(f))
+        .collect::<Result<Vec<Filter>, _>>()?;
+
+    let relays = distinct_relays(&args.relays).map_err(std::io::Error::other)?;
+
+    let fetches = relays.iter().map(|relay| {
+        let filters = filters.clone();
+        async move {
+            nostr_probe::fetch_all(relay, filters)
+                .await
+                .map_err(|e| e.to_string())
+        }
+    });
+    let results = join_all(fetches).await;
+
+    let report = compare(&relays, results);
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(&report);
+    }
+
+    let differs = report
+        .relays
+        .iter()
+        .any(|r| r.error.is_some() || !r.missing_here.is_empty())
+        || !report.different_versions.is_empty();
+    if differs {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_probe::conformance::Author;
+
+    fn relays(urls: &[&str]) -> Vec<String> {
+        urls.iter().map(|u| u.to_string()).collect()
+    }
+
+    #[test]
+    fn relays_are_deduplicated() {
+        let given = relays(&["wss://A.example/", "wss://b.example", "wss://a.example"]);
+        assert_eq!(
+            distinct_relays(&given).unwrap(),
+            ["wss://a.example", "wss://b.example"]
+        );
+        assert!(distinct_relays(&relays(&["wss://a.example", "wss://A.example/"])).is_err());
+        assert!(distinct_relays(&relays(&["wss://a.example", "https://b.example"])).is_err());
+    }
+
+    #[test]
+    fn reports_missing_events_and_differing_versions() {
+        let author = Author::new();
+        let both = author.sign(1, 10, vec![], "both");
+        let only_a = author.sign(1, 20, vec![], "only on a");
+        let only_b = author.sign(1, 30, vec![], "only on b");
+        let old_profile = author.sign(0, 10, vec![], "old");
+        let new_profile = author.sign(0, 20, vec![], "new");
+        let relay_list = author.sign(10002, 10, vec![], "");
+
+        let a = vec![
+            both.clone(),
+            only_a.clone(),
+            old_profile.clone(),
+            new_profile.clone(),
+            relay_list.clone(),
+        ];
+        let b = vec![both, only_b.clone(), old_profile.clone(), relay_list];
+        let names = relays(&["wss://a.example", "wss://b.example", "wss://c.example"]);
+        let report = compare(&names, vec![Ok(a), Ok(b), Err("timed out".to_owned())]);
+
+        let (ra, rb, rc) = (&report.relays[0], &report.relays[1], &report.relays[2]);
+        assert_eq!(ra.only_here, [only_a.id.as_hex_string()]);
+        assert_eq!(ra.missing_here, [only_b.id.as_hex_string()]);
+        assert_eq!(rb.only_here, [only_b.id.as_hex_string()]);
+        assert_eq!(rb.missing_here, [only_a.id.as_hex_string()]);
+        assert_eq!((ra.events, rb.events), (5, 4));
+        assert_eq!(rc.error.as_deref(), Some("timed out"));
+        assert!(rc.only_here.is_empty() && rc.missing_here.is_empty());
+
+        // Only the profile differs; the relay list is the same on both
+        assert_eq!(report.different_versions.len(), 1);
+        let differs = &report.different_versions[0];
+        assert_eq!(
+            differs.address,
+            format!("0:{}:", author.pubkey.as_hex_string())
+        );
+        let version = |relay: &str| differs.versions[relay].as_ref().map(|v| v.id.clone());
+        assert_eq!(
+            version("wss://a.example"),
+            Some(new_profile.id.as_hex_string())
+        );
+        assert_eq!(
+            version("wss://b.example"),
+            Some(old_profile.id.as_hex_string())
+        );
+        assert!(!differs.versions.contains_key("wss://c.example"));
+    }
+
+    #[test]
+    fn a_version_missing_from_one_relay_differs() {
+        let author = Author::new();
+        let profile = author.sign(0, 10, vec![], "profile");
+        let names = relays(&["wss://a.example", "wss://b.example"]);
+        let report = compare(&names, vec![Ok(vec![profile]), Ok(vec![])]);
+
+        assert_eq!(report.different_versions.len(), 1);
+        let versions = &report.different_versions[0].versions;
+        assert!(versions["wss://a.example"].is_some());
+        assert!(versions["wss://b.example"].is_none());
+    }
+}
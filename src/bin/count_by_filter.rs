@@ -1,35 +1,57 @@
+use clap::Parser;
+use nostr_probe::filter::{filters_to_json, parse_filter_json, parse_with_or, FilterArgs};
+use nostr_probe::output::{self, Source};
 use nostr_probe::{Command, Probe};
-use nostr_types::{Filter, RelayMessage, SubscriptionId};
-use std::env;
+use nostr_types::{RelayMessage, SubscriptionId};
+use serde_json::Value;
+
+/// Count events matching filters given as JSON, built from flags, or both. Start
+/// another filter built from flags with --or. All the filters are sent in one COUNT.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// The relay to ask
+    relay_url: String,
+
+    /// Filter JSON (may be repeated)
+    filters: Vec<String>,
+
+    #[command(flatten)]
+    filter: FilterArgs,
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut args = env::args();
-    let _ = args.next(); // program name
-    let relay_url = match args.next() {
-        Some(u) => u,
-        None => panic!("Usage: fetch_by_filter <RelayURL> <FilterJSON>"),
-    };
-    let filter: Filter = match args.next() {
-        Some(filter) => match serde_json::from_str(&filter) {
-            Ok(f) => f,
-            Err(e) => panic!("{}", e),
-        },
-        None => panic!("Usage: fetch_by_filter <RelayURL> <FilterJSON>"),
-    };
+    let _finish = output::finish_on_drop();
+    let (args, more) = parse_with_or::<Args>();
+    let relay_url = args.relay_url.clone();
+
+    let mut filters: Vec<Value> = args
+        .filters
+        .iter()
+        .map(|f| parse_filter_json(f))
+        .collect::<Result<_, _>>()?;
+    filters.extend(filters_to_json(&args.filter, &more)?);
+    if filters.is_empty() {
+        return Err("Give a filter as JSON or with flags (see --help)".into());
+    }
 
     let (to_probe, from_main) = tokio::sync::mpsc::channel::<Command>(100);
     let (to_main, mut from_probe) = tokio::sync::mpsc::channel::<RelayMessage>(100);
+    let inner_relay_url = relay_url.clone();
     let join_handle = tokio::spawn(async move {
         let mut probe = Probe::new(from_main, to_main);
-        if let Err(e) = probe.connect_and_listen(&relay_url).await {
+        if let Err(e) = probe.connect_and_listen(&inner_relay_url).await {
             eprintln!("{}", e);
         }
     });
 
     let our_sub_id = SubscriptionId("count_by_filter".to_string());
+    // Sent as written so that fields Filter doesn't know (like search) survive
+    let mut wire: Vec<Value> = vec!["COUNT".into(), our_sub_id.0.clone().into()];
+    wire.extend(filters);
     to_probe
-        .send(Command::CountEvents(our_sub_id.clone(), vec![filter]))
+        .send(Command::Raw(Value::Array(wire).to_string()))
         .await?;
 
     loop {
@@ -42,12 +64,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
             RelayMessage::Event(sub, e) => {
                 if sub == our_sub_id {
-                    println!("{}", serde_json::to_string(&e)?);
+                    output::event(Source::new(&relay_url, &sub.0), &e)?;
                 }
             }
             RelayMessage::Count(sub, result) => {
                 if sub == our_sub_id {
-                    println!("{}", serde_json::to_string(&result)?);
+                    let count = serde_json::to_value(&result)?;
+                    output::count(Source::new(&relay_url, &sub.0), &count)?;
                 }
             }
             RelayMessage::Closed(sub, _) => {
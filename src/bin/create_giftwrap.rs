@@ -1,4 +1,4 @@
-use nostr_types::{PreEvent, PublicKey, Signer, Unixtime};
+use nostr_types::{PreEvent, Signer, Unixtime};
 use std::env;
 use std::io::Read;
 
@@ -8,13 +8,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let _ = args.next(); // program name
 
     let pubkey = match args.next() {
-        Some(key) => match PublicKey::try_from_bech32_string(&key, true) {
-            Ok(key) => key,
-            Err(_) => match PublicKey::try_from_hex_string(&key, true) {
-                Ok(key) => key,
-                Err(_) => panic!("Could not parse public key"),
-            },
-        },
+        Some(key) => nostr_probe::nip19::parse_pubkey(&key)?.pubkey,
         None => panic!("Usage: create_giftwrap <RecipientPubkey> < JSON_PRE_EVENT"),
     };
 
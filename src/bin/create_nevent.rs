@@ -1,27 +1,39 @@
-use nostr_types::{Id, NEvent, NostrBech32, UncheckedUrl};
+use nostr_types::{NEvent, NostrBech32, UncheckedUrl};
 use std::env;
 
 fn main() {
     let mut args = env::args();
     let _ = args.next(); // program name
 
-    let hex = match args.next() {
-        Some(u) => u,
-        None => panic!("Usage: create_nevent <idhex> <relay_url> [<relay_url>]"),
+    let usage = |msg| -> ! {
+        panic!(
+            "{}\nUsage: create_nevent <id> <relay_url> [<relay_url>]",
+            msg
+        );
     };
-    let id = Id::try_from_hex_string(&hex).unwrap();
 
+    let arg = match args.next() {
+        Some(s) => match nostr_probe::nip19::parse_id(&s) {
+            Ok(arg) => arg,
+            Err(_) => usage("Event id not parsed (expected hex, note or nevent)"),
+        },
+        None => usage("Event id missing"),
+    };
+
+    // Relays given on the command line first, then any hints the id carried
     let mut relays: Vec<UncheckedUrl> = Vec::new();
-    while let Some(urlstr) = args.next() {
+    for urlstr in args.chain(arg.relays) {
         let url = UncheckedUrl::from_str(&urlstr);
-        relays.push(url);
+        if !relays.iter().any(|r| r.as_str() == url.as_str()) {
+            relays.push(url);
+        }
     }
 
     let ep = NEvent {
-        id,
+        id: arg.id,
         relays,
-        kind: None,
-        author: None,
+        kind: arg.kind,
+        author: arg.author,
     };
 
     let nurl = NostrBech32::NEvent(ep);
@@ -1,57 +1,53 @@
+use nostr_probe::output::{self, Source};
+use nostr_probe::store::Store;
 use nostr_probe::{Command, Probe};
-use nostr_types::{Filter, RelayMessage, SubscriptionId};
+use nostr_types::{Filter, RelayMessage};
 use std::env;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let _finish = output::finish_on_drop();
     let mut args = env::args();
     let _ = args.next(); // program name
     let relay_url = match args.next() {
         Some(u) => u,
-        None => panic!("Usage: dump_relay <RelayURL>"),
+        None => panic!("Usage: dump_relay <RelayURL> [<ArchiveDir>]"),
+    };
+
+    // If given an archive, events are stored there instead of printed
+    let mut store = match args.next() {
+        Some(dir) => Some(Store::open(dir.as_ref())?),
+        None => None,
     };
 
     let filter = Filter::new();
 
     let (to_probe, from_main) = tokio::sync::mpsc::channel::<Command>(100);
     let (to_main, mut from_probe) = tokio::sync::mpsc::channel::<RelayMessage>(100);
+    let inner_relay_url = relay_url.clone();
     let join_handle = tokio::spawn(async move {
         let mut probe = Probe::new(from_main, to_main);
-        if let Err(e) = probe.connect_and_listen(&relay_url).await {
+        if let Err(e) = probe.connect_and_listen(&inner_relay_url).await {
             eprintln!("{}", e);
         }
     });
 
-    let our_sub_id = SubscriptionId("dump".to_string());
-    to_probe
-        .send(Command::FetchEvents(our_sub_id.clone(), vec![filter]))
-        .await?;
-
-    loop {
-        match from_probe.recv().await.unwrap() {
-            RelayMessage::Eose(sub) => {
-                if sub == our_sub_id {
-                    to_probe.send(Command::Exit).await?;
-                    break;
-                }
-            }
-            RelayMessage::Event(sub, e) => {
-                if sub == our_sub_id {
-                    println!("{}", serde_json::to_string(&e)?);
+    nostr_probe::req_paged("dump", filter, &to_probe, &mut from_probe, |sub, e| {
+        match &mut store {
+            Some(store) => {
+                if e.verify(None).is_ok() {
+                    store.add(&e)?;
                 }
             }
-            RelayMessage::Closed(sub, _) => {
-                if sub == our_sub_id {
-                    to_probe.send(Command::Exit).await?;
-                    break;
-                }
-            }
-            RelayMessage::Notice(_) => {
-                to_probe.send(Command::Exit).await?;
-                break;
-            }
-            _ => {}
+            None => output::event(Source::new(&relay_url, sub), &e)?,
         }
+        Ok(())
+    })
+    .await?;
+
+    if let Some(store) = &mut store {
+        store.sync()?;
+        eprintln!("{} events in archive", store.len());
     }
 
     Ok(join_handle.await?)
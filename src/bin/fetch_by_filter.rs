@@ -1,35 +1,73 @@
+use clap::Parser;
+use nostr_probe::filter::{filters_to_json, parse_filter_json, parse_with_or, FilterArgs};
+use nostr_probe::output::{self, Source};
 use nostr_probe::{Command, Probe};
 use nostr_types::{Filter, RelayMessage, SubscriptionId};
-use std::env;
+use serde_json::Value;
+
+/// Fetch events matching filters given as JSON, built from flags, or both. Start
+/// another filter built from flags with --or. All the filters are sent in one REQ.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// The relay to ask
+    relay_url: String,
+
+    /// Filter JSON (may be repeated)
+    filters: Vec<String>,
+
+    #[command(flatten)]
+    filter: FilterArgs,
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut args = env::args();
-    let _ = args.next(); // program name
-    let relay_url = match args.next() {
-        Some(u) => u,
-        None => panic!("Usage: fetch_by_filter <RelayURL> <FilterJSON>"),
-    };
-    let filter: Filter = match args.next() {
-        Some(filter) => match serde_json::from_str(&filter) {
-            Ok(f) => f,
-            Err(e) => panic!("{}", e),
-        },
-        None => panic!("Usage: fetch_by_filter <RelayURL> <FilterJSON>"),
-    };
+    let _finish = output::finish_on_drop();
+    let (args, more) = parse_with_or::<Args>();
+    let relay_url = args.relay_url.clone();
+
+    let mut filters: Vec<Value> = args
+        .filters
+        .iter()
+        .map(|f| parse_filter_json(f))
+        .collect::<Result<_, _>>()?;
+    filters.extend(filters_to_json(&args.filter, &more)?);
+    if filters.is_empty() {
+        return Err("Give a filter as JSON or with flags (see --help)".into());
+    }
 
     let (to_probe, from_main) = tokio::sync::mpsc::channel::<Command>(100);
     let (to_main, mut from_probe) = tokio::sync::mpsc::channel::<RelayMessage>(100);
+    let inner_relay_url = relay_url.clone();
     let join_handle = tokio::spawn(async move {
         let mut probe = Probe::new(from_main, to_main);
-        if let Err(e) = probe.connect_and_listen(&relay_url).await {
+        if let Err(e) = probe.connect_and_listen(&inner_relay_url).await {
             eprintln!("{}", e);
         }
     });
 
+    // With one filter and no limit the user wants everything, so page backwards
+    // through the relay. Paging can't carry a search, which relays rank anyway.
+    let pageable = filters.len() == 1 && filters[0].get("limit").is_none();
+    if pageable && filters[0].get("search").is_none() {
+        let filter: Filter = serde_json::from_value(filters.remove(0))?;
+        nostr_probe::req_paged(
+            "fetch_by_filter",
+            filter,
+            &to_probe,
+            &mut from_probe,
+            |sub, e| output::event(Source::new(&relay_url, sub), &e),
+        )
+        .await?;
+        return Ok(join_handle.await?);
+    }
+
+    // Sent as written so that fields Filter doesn't know (like search) survive
     let our_sub_id = SubscriptionId("fetch_by_filter".to_string());
+    let mut wire: Vec<Value> = vec!["REQ".into(), our_sub_id.0.clone().into()];
+    wire.extend(filters);
     to_probe
-        .send(Command::FetchEvents(our_sub_id.clone(), vec![filter]))
+        .send(Command::Raw(Value::Array(wire).to_string()))
         .await?;
 
     loop {
@@ -42,7 +80,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
             RelayMessage::Event(sub, e) => {
                 if sub == our_sub_id {
-                    println!("{}", serde_json::to_string(&e)?);
+                    output::event(Source::new(&relay_url, &sub.0), &e)?;
                 }
             }
             RelayMessage::Closed(sub, _) => {
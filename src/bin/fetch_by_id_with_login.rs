@@ -1,36 +1,45 @@
-use nostr_probe::{Command, Probe};
-use nostr_types::{Filter, Id, RelayMessage};
+use nostr_probe::{nip19, Command, Probe};
+use nostr_types::{Filter, RelayMessage};
 use std::env;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let _finish = nostr_probe::output::finish_on_drop();
     let mut args = env::args();
     let _ = args.next(); // program name
     let relay_url = match args.next() {
         Some(u) => u,
-        None => panic!("Usage: fetch_by_id_with_login <RelayURL> <IdHex>"),
+        None => panic!("Usage: fetch_by_id_with_login <RelayURL> <Id>"),
     };
-    let id: Id = match args.next() {
-        Some(id) => Id::try_from_hex_string(&id)?,
-        None => panic!("Usage: fetch_by_id_with_login <RelayURL> <IdHex>"),
+    let id = match args.next() {
+        Some(id) => nip19::parse_id(&id)?,
+        None => panic!("Usage: fetch_by_id_with_login <RelayURL> <Id>"),
     };
 
     let signer = nostr_probe::load_signer()?;
 
-    let (to_probe, from_main) = tokio::sync::mpsc::channel::<Command>(100);
-    let (to_main, from_probe) = tokio::sync::mpsc::channel::<RelayMessage>(100);
-    let inner_relay_url = relay_url.clone();
-    let join_handle = tokio::spawn(async move {
-        let mut probe = Probe::new(from_main, to_main);
-        if let Err(e) = probe.connect_and_listen(&inner_relay_url).await {
-            eprintln!("{}", e);
-        }
-    });
-
     let mut filter = Filter::new();
-    filter.add_id(id);
+    filter.add_id(id.id);
 
-    nostr_probe::req(&relay_url, signer, filter, to_probe, from_probe).await?;
+    // Try the relay, then any relays hinted in an nevent, until one has it
+    for relay_url in nip19::with_hints(&relay_url, &id.relays) {
+        let (to_probe, from_main) = tokio::sync::mpsc::channel::<Command>(100);
+        let (to_main, from_probe) = tokio::sync::mpsc::channel::<RelayMessage>(100);
+        let inner_relay_url = relay_url.clone();
+        let join_handle = tokio::spawn(async move {
+            let mut probe = Probe::new(from_main, to_main);
+            if let Err(e) = probe.connect_and_listen(&inner_relay_url).await {
+                eprintln!("{}", e);
+            }
+        });
+
+        let found =
+            nostr_probe::req(&relay_url, &signer, filter.clone(), to_probe, from_probe).await?;
+        join_handle.await?;
+        if found > 0 {
+            break;
+        }
+    }
 
-    Ok(join_handle.await?)
+    Ok(())
 }
@@ -1,9 +1,12 @@
-use nostr_probe::{Command, Probe};
-use nostr_types::{EventKind, Filter, PublicKey, RelayMessage, SubscriptionId};
+use nostr_probe::output::{self, Source};
+use nostr_probe::{nip19, Command, Probe};
+use nostr_types::{EventKind, Filter, Id, RelayMessage};
+use std::collections::HashSet;
 use std::env;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let _finish = output::finish_on_drop();
     let mut args = env::args();
     let _ = args.next(); // program name
     let relay_url = match args.next() {
@@ -15,63 +18,46 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         None => panic!("Usage: fetch_by_kind_and_author <RelayURL> <KindNumber> <PubKey>"),
     };
     let kind: EventKind = kind_number.into();
-    let author_key = match args.next() {
-        Some(key) => match PublicKey::try_from_bech32_string(&key, true) {
-            Ok(key) => key,
-            Err(_) => match PublicKey::try_from_hex_string(&key, true) {
-                Ok(key) => key,
-                Err(_) => panic!("Could not parse public key"),
-            },
-        },
+    let author = match args.next() {
+        Some(key) => nip19::parse_pubkey(&key)?,
         None => panic!("Usage: fetch_by_kind_and_author <RelayURL> <KindNumber> <PubKey>"),
     };
 
-    let (to_probe, from_main) = tokio::sync::mpsc::channel::<Command>(100);
-    let (to_main, mut from_probe) = tokio::sync::mpsc::channel::<RelayMessage>(100);
-    let join_handle = tokio::spawn(async move {
-        let mut probe = Probe::new(from_main, to_main);
-        if let Err(e) = probe.connect_and_listen(&relay_url).await {
-            eprintln!("{}", e);
-        }
-    });
-
     let filter = Filter {
         kinds: vec![kind],
-        authors: vec![author_key],
+        authors: vec![author.pubkey],
         ..Default::default()
     };
 
-    let our_sub_id = SubscriptionId("fetch_by_kind_and_author".to_string());
-    to_probe
-        .send(Command::FetchEvents(our_sub_id.clone(), vec![filter]))
-        .await?;
-
-    loop {
-        match from_probe.recv().await.unwrap() {
-            RelayMessage::Eose(sub) => {
-                if sub == our_sub_id {
-                    to_probe.send(Command::Exit).await?;
-                    break;
-                }
-            }
-            RelayMessage::Event(sub, e) => {
-                if sub == our_sub_id {
-                    println!("{}", serde_json::to_string(&e)?);
-                }
+    // Page through the relay, then any relays hinted in an nprofile
+    let mut seen: HashSet<Id> = HashSet::new();
+    for relay_url in nip19::with_hints(&relay_url, &author.relays) {
+        let (to_probe, from_main) = tokio::sync::mpsc::channel::<Command>(100);
+        let (to_main, mut from_probe) = tokio::sync::mpsc::channel::<RelayMessage>(100);
+        let relay_url2 = relay_url.clone();
+        let join_handle = tokio::spawn(async move {
+            let mut probe = Probe::new(from_main, to_main);
+            if let Err(e) = probe.connect_and_listen(&relay_url2).await {
+                eprintln!("{}", e);
             }
-            RelayMessage::Closed(sub, _) => {
-                if sub == our_sub_id {
-                    to_probe.send(Command::Exit).await?;
-                    break;
+        });
+
+        nostr_probe::req_paged(
+            "fetch_by_kind_and_author",
+            filter.clone(),
+            &to_probe,
+            &mut from_probe,
+            |sub, e| {
+                if seen.insert(e.id) {
+                    output::event(Source::new(&relay_url, sub), &e)?;
                 }
-            }
-            RelayMessage::Notice(_) => {
-                to_probe.send(Command::Exit).await?;
-                break;
-            }
-            _ => {}
-        }
+                Ok(())
+            },
+        )
+        .await?;
+
+        join_handle.await?;
     }
 
-    Ok(join_handle.await?)
+    Ok(())
 }
@@ -1,9 +1,10 @@
-use nostr_probe::{Command, Probe};
-use nostr_types::{EventKind, Filter, PublicKey, RelayMessage};
+use nostr_probe::{nip19, Command, Probe};
+use nostr_types::{EventKind, Filter, RelayMessage};
 use std::env;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let _finish = nostr_probe::output::finish_on_drop();
     let mut args = env::args();
     let _ = args.next(); // program name
     let relay_url = match args.next() {
@@ -15,36 +16,34 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         None => panic!("Usage: fetch_by_kind_and_author <RelayURL> <KindNumber> <PubKey>"),
     };
     let kind: EventKind = kind_number.into();
-    let author_key = match args.next() {
-        Some(key) => match PublicKey::try_from_bech32_string(&key, true) {
-            Ok(key) => key,
-            Err(_) => match PublicKey::try_from_hex_string(&key, true) {
-                Ok(key) => key,
-                Err(_) => panic!("Could not parse public key"),
-            },
-        },
+    let author = match args.next() {
+        Some(key) => nip19::parse_pubkey(&key)?,
         None => panic!("Usage: fetch_by_kind_and_author <RelayURL> <KindNumber> <PubKey>"),
     };
 
     let signer = nostr_probe::load_signer()?;
 
-    let (to_probe, from_main) = tokio::sync::mpsc::channel::<Command>(100);
-    let (to_main, from_probe) = tokio::sync::mpsc::channel::<RelayMessage>(100);
-    let relay_url2 = relay_url.clone();
-    let join_handle = tokio::spawn(async move {
-        let mut probe = Probe::new(from_main, to_main);
-        if let Err(e) = probe.connect_and_listen(&relay_url2).await {
-            eprintln!("{}", e);
-        }
-    });
-
     let filter = Filter {
         kinds: vec![kind],
-        authors: vec![author_key],
+        authors: vec![author.pubkey],
         ..Default::default()
     };
 
-    nostr_probe::req(&relay_url, signer, filter, to_probe, from_probe).await?;
+    // Ask the relay, then any relays hinted in an nprofile
+    for relay_url in nip19::with_hints(&relay_url, &author.relays) {
+        let (to_probe, from_main) = tokio::sync::mpsc::channel::<Command>(100);
+        let (to_main, from_probe) = tokio::sync::mpsc::channel::<RelayMessage>(100);
+        let relay_url2 = relay_url.clone();
+        let join_handle = tokio::spawn(async move {
+            let mut probe = Probe::new(from_main, to_main);
+            if let Err(e) = probe.connect_and_listen(&relay_url2).await {
+                eprintln!("{}", e);
+            }
+        });
+
+        nostr_probe::req(&relay_url, &signer, filter.clone(), to_probe, from_probe).await?;
+        join_handle.await?;
+    }
 
-    Ok(join_handle.await?)
+    Ok(())
 }
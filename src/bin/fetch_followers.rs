@@ -0,0 +1,37 @@
+use clap::Parser;
+use nostr_probe::{contacts, nip19};
+
+/// Find who follows a user: contact lists (kind 3) that mention them, checked
+/// against each follower's newest list
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// The user, as an npub, nprofile or hex public key
+    user: String,
+
+    /// Relays to ask (may be repeated). Defaults to the nprofile's relay hints and
+    /// some well known relays.
+    #[arg(short, long)]
+    relay: Vec<String>,
+
+    /// Contact lists to ask each relay for, newest first. Popular accounts are in
+    /// more lists than it is reasonable to download.
+    #[arg(short, long, default_value_t = 5000)]
+    limit: usize,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let user = nip19::parse_pubkey(&args.user)?;
+    let relays = contacts::relays_to_ask(&args.relay, &user.relays);
+
+    let followers = contacts::followers(user.pubkey, &relays, args.limit).await;
+    for (pubkey, _) in &followers {
+        println!("{}", serde_json::json!({ "pubkey": pubkey }));
+    }
+    eprintln!("{} followers found", followers.len());
+
+    Ok(())
+}
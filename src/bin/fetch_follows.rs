@@ -0,0 +1,41 @@
+use clap::Parser;
+use nostr_probe::{contacts, nip19};
+
+/// List who a user follows, from their newest contact list (kind 3), with petnames
+/// and relay hints
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// The user, as an npub, nprofile or hex public key
+    user: String,
+
+    /// Relays to ask (may be repeated). Defaults to the nprofile's relay hints and
+    /// some well known relays.
+    #[arg(short, long)]
+    relay: Vec<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let user = nip19::parse_pubkey(&args.user)?;
+    let relays = contacts::relays_to_ask(&args.relay, &user.relays);
+
+    let list = match contacts::contact_list(user.pubkey, &relays).await {
+        Some(list) => list,
+        None => return Err("No contact list found".into()),
+    };
+    let follows = contacts::parse_contact_list(&list);
+    for contact in &follows {
+        println!("{}", serde_json::to_string(contact)?);
+    }
+    eprintln!(
+        "Follows {} (contact list {} from {})",
+        follows.len(),
+        list.id.as_hex_string(),
+        nostr_probe::format_unixtime(list.created_at.0)
+    );
+
+    Ok(())
+}
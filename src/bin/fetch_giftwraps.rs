@@ -4,6 +4,7 @@ use std::env;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let _finish = nostr_probe::output::finish_on_drop();
     let mut args = env::args();
     let _ = args.next(); // program name
     let relay_url = match args.next() {
@@ -31,7 +32,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
     filter.add_tag_value('p', key.as_str().to_owned());
 
-    nostr_probe::req(&relay_url, signer, filter, to_probe, from_probe).await?;
+    nostr_probe::req(&relay_url, &signer, filter, to_probe, from_probe).await?;
 
     Ok(join_handle.await?)
 }
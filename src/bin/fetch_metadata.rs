@@ -1,65 +1,32 @@
-use nostr_probe::{Command, Probe};
-use nostr_types::{EventKind, Filter, PublicKey, RelayMessage, SubscriptionId};
+use nostr_probe::nip19;
+use nostr_probe::output::{self, Source};
+use nostr_types::{EventKind, Filter};
 use std::env;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let _finish = output::finish_on_drop();
     let mut args = env::args();
     let _ = args.next(); // program name
     let relay_url = match args.next() {
         Some(u) => u,
-        None => panic!("Usage: fetch_metadata <RelayURL> <PubKeyHex>"),
+        None => panic!("Usage: fetch_metadata <RelayURL> <PubKey>"),
     };
-    let pubkey: PublicKey = match args.next() {
-        Some(id) => PublicKey::try_from_hex_string(&id, true)?,
-        None => panic!("Usage: fetch_metadata <RelayURL> <PubKeyHex>"),
+    let user = match args.next() {
+        Some(key) => nip19::parse_pubkey(&key)?,
+        None => panic!("Usage: fetch_metadata <RelayURL> <PubKey>"),
     };
 
-    let (to_probe, from_main) = tokio::sync::mpsc::channel::<Command>(100);
-    let (to_main, mut from_probe) = tokio::sync::mpsc::channel::<RelayMessage>(100);
-    let join_handle = tokio::spawn(async move {
-        let mut probe = Probe::new(from_main, to_main);
-        if let Err(e) = probe.connect_and_listen(&relay_url).await {
-            eprintln!("{}", e);
-        }
-    });
-
-    let our_sub_id = SubscriptionId("fetch_metadata".to_string());
     let mut filter = Filter::new();
-    filter.add_author(pubkey);
+    filter.add_author(user.pubkey);
     filter.add_event_kind(EventKind::Metadata);
     filter.limit = Some(1);
 
-    to_probe
-        .send(Command::FetchEvents(our_sub_id.clone(), vec![filter]))
-        .await?;
-
-    loop {
-        match from_probe.recv().await.unwrap() {
-            RelayMessage::Eose(sub) => {
-                if sub == our_sub_id {
-                    to_probe.send(Command::Exit).await?;
-                    break;
-                }
-            }
-            RelayMessage::Event(sub, e) => {
-                if sub == our_sub_id {
-                    println!("{}", serde_json::to_string(&e)?);
-                }
-            }
-            RelayMessage::Closed(sub, _) => {
-                if sub == our_sub_id {
-                    to_probe.send(Command::Exit).await?;
-                    break;
-                }
-            }
-            RelayMessage::Notice(_) => {
-                to_probe.send(Command::Exit).await?;
-                break;
-            }
-            _ => {}
-        }
+    // Ask the relay and any relays hinted in an nprofile, keeping the newest answer
+    let relays = nip19::with_hints(&relay_url, &user.relays);
+    if let Some((relay, event)) = nostr_probe::outbox::newest(&relays, filter).await? {
+        output::event(Source::relay(&relay), &event)?;
     }
 
-    Ok(join_handle.await?)
+    Ok(())
 }
@@ -0,0 +1,58 @@
+use clap::Parser;
+use nostr_probe::{contacts, nip19};
+use nostr_types::PublicKey;
+use std::collections::HashSet;
+
+/// List a user's mutuals: the people they follow who follow them back
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// The user, as an npub, nprofile or hex public key
+    user: String,
+
+    /// Relays to ask (may be repeated). Defaults to the nprofile's relay hints and
+    /// some well known relays.
+    #[arg(short, long)]
+    relay: Vec<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let user = nip19::parse_pubkey(&args.user)?;
+    let relays = contacts::relays_to_ask(&args.relay, &user.relays);
+
+    let follows = match contacts::contact_list(user.pubkey, &relays).await {
+        Some(list) => contacts::parse_contact_list(&list),
+        None => return Err("No contact list found".into()),
+    };
+    // Rather than look for everyone who follows the user, check the newest list of
+    // each person they follow
+    let target = user.pubkey.as_hex_string();
+    let pubkeys: Vec<PublicKey> = follows
+        .iter()
+        .filter_map(|c| PublicKey::try_from_hex_string(&c.pubkey, true).ok())
+        .collect();
+    let followers: HashSet<String> = contacts::contact_lists(&pubkeys, &relays)
+        .await
+        .into_iter()
+        .filter(|(_, list)| {
+            contacts::parse_contact_list(list)
+                .iter()
+                .any(|c| c.pubkey == target)
+        })
+        .map(|(pubkey, _)| pubkey)
+        .collect();
+
+    let mutuals: Vec<&contacts::Contact> = follows
+        .iter()
+        .filter(|c| followers.contains(&c.pubkey))
+        .collect();
+    for contact in &mutuals {
+        println!("{}", serde_json::to_string(contact)?);
+    }
+    eprintln!("{} mutuals out of {} follows", mutuals.len(), follows.len());
+
+    Ok(())
+}
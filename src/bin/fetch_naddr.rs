@@ -0,0 +1,119 @@
+use clap::Parser;
+use futures_util::future::join_all;
+use nostr_probe::output::{self, Source};
+use nostr_probe::store::replaceable_address;
+use nostr_probe::{nip19, outbox};
+use nostr_types::{Event, Filter};
+
+/// Fetch an addressable event from an naddr (or kind:pubkey:d coordinates), asking
+/// the naddr's relay hints and any relays given
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// The event's address, as an naddr or kind:pubkey:d
+    address: String,
+
+    /// More relays to ask
+    relays: Vec<String>,
+
+    /// Print every version seen on each relay instead of only the newest
+    #[arg(short, long)]
+    all_versions: bool,
+}
+
+// Newest first, breaking ties the way NIP-01 does (lowest id wins)
+fn newest_first(a: &Event, b: &Event) -> std::cmp::Ordering {
+    b.created_at
+        .0
+        .cmp(&a.created_at.0)
+        .then_with(|| a.id.as_hex_string().cmp(&b.id.as_hex_string()))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let _finish = output::finish_on_drop();
+    let args = Args::parse();
+
+    let address = nip19::parse_address(&args.address)?;
+
+    let mut relays: Vec<String> = address.relays.clone();
+    for url in &args.relays {
+        match outbox::normalize_relay_url(url) {
+            Some(url) => {
+                if !relays.contains(&url) {
+                    relays.push(url);
+                }
+            }
+            None => eprintln!("Skipping {}: not a websocket URL", url),
+        }
+    }
+    if relays.is_empty() {
+        return Err("The address has no relay hints, give at least one relay".into());
+    }
+
+    let mut filter = Filter {
+        kinds: vec![address.kind],
+        authors: vec![address.author],
+        ..Default::default()
+    };
+    filter.add_tag_value('d', address.d.clone());
+
+    let kind: u32 = address.kind.into();
+    let coordinates = format!("{}:{}:{}", kind, address.author.as_hex_string(), address.d);
+
+    let fetches = relays.iter().map(|relay| {
+        let filter = filter.clone();
+        async move {
+            nostr_probe::fetch_all(relay, vec![filter])
+                .await
+                .map_err(|e| e.to_string())
+        }
+    });
+    let results = join_all(fetches).await;
+
+    let mut newest: Option<(&String, Event)> = None;
+    for (relay, result) in relays.iter().zip(results) {
+        let mut versions = match result {
+            Ok(events) => events,
+            Err(e) => {
+                eprintln!("{}: FAILED: {}", relay, e);
+                continue;
+            }
+        };
+        // Relays are not always careful about the d tag, so check it ourselves
+        versions.retain(|e| replaceable_address(e).as_ref() == Some(&coordinates));
+        versions.sort_by(newest_first);
+        eprintln!("{}: {} versions", relay, versions.len());
+
+        if args.all_versions {
+            for event in &versions {
+                eprintln!("  {} at {}", event.id.as_hex_string(), event.created_at.0);
+                output::event(Source::relay(relay), event)?;
+            }
+        }
+        if let Some(event) = versions.into_iter().next() {
+            let replace = match &newest {
+                Some((_, n)) => newest_first(&event, n).is_lt(),
+                None => true,
+            };
+            if replace {
+                newest = Some((relay, event));
+            }
+        }
+    }
+
+    match newest {
+        Some((relay, event)) => {
+            if !args.all_versions {
+                output::event(Source::relay(relay), &event)?;
+            }
+            eprintln!(
+                "Newest: {} at {}",
+                event.id.as_hex_string(),
+                event.created_at.0
+            );
+            Ok(())
+        }
+        None => Err("No relay had the event".into()),
+    }
+}
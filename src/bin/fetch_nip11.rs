@@ -1,41 +1,46 @@
-use reqwest::blocking::Client;
-use reqwest::redirect::Policy;
+use nostr_probe::nip11::Nip11;
+use nostr_probe::report;
 use std::env;
-use std::time::Duration;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut args = env::args();
     let _ = args.next(); // program name
     let relay_url = match args.next() {
         Some(u) => u,
-        None => panic!("Usage: fetch_nip11 <RelayURL>"),
+        None => panic!("Usage: fetch_nip11 <RelayURL> [check]"),
     };
-
-    let (host, uri) = nostr_probe::url_to_host_and_uri(&relay_url);
-
-    let scheme = match uri.scheme() {
-        Some(refscheme) => match refscheme.as_str() {
-            "wss" => "https",
-            "ws" => "http",
-            u => panic!("Unknown scheme {}", u),
-        },
-        None => panic!("Relay URL has no scheme."),
+    let check = match args.next().as_deref() {
+        None => false,
+        Some("check") => true,
+        Some(other) => panic!("Unknown argument {}, expected check", other),
     };
 
-    let client = Client::builder()
-        .redirect(Policy::none())
-        .connect_timeout(Some(Duration::from_secs(60)))
-        .timeout(Some(Duration::from_secs(60)))
-        .connection_verbose(true)
-        .build()?;
-    let response = client
-        .get(format!("{}://{}", scheme, host))
-        .header("Host", host)
-        .header("Accept", "application/nostr+json")
-        .send()?;
-    let json = response.text()?;
-    let value: serde_json::Value = serde_json::from_str(&json)?;
+    let value = nostr_probe::fetch_nip11(&relay_url)?;
     println!("{}", serde_json::to_string_pretty(&value)?);
 
+    let (nip11, problems) = Nip11::parse(&value);
+    for problem in &problems {
+        eprintln!("PROBLEM: {}", problem);
+    }
+    for nip in nip11.unknown_nips() {
+        eprintln!(
+            "NOTE: supported_nips lists NIP {}, which this tool doesn't know",
+            nip
+        );
+    }
+
+    if check {
+        let limitation = nip11.limitation.unwrap_or_default();
+        let runtime = tokio::runtime::Runtime::new()?;
+        let results = runtime.block_on(nostr_probe::nip11::check_limitations(
+            &relay_url,
+            &limitation,
+        ));
+        report::print_text(&results);
+        if !problems.is_empty() || report::any_failed(&results) {
+            std::process::exit(1);
+        }
+    }
+
     Ok(())
 }
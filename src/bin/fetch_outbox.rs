@@ -0,0 +1,135 @@
+use clap::Parser;
+use futures_util::future::join_all;
+use nostr_probe::nip19;
+use nostr_probe::outbox::{self, BOOTSTRAP_RELAYS};
+use nostr_probe::output::{self, Source};
+use nostr_types::{Event, Filter, Id};
+use std::collections::HashSet;
+
+/// Fetch a user's events from the relays they publish to, found from their NIP-65
+/// relay list
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// The user, as an npub, nprofile or hex public key
+    user: String,
+
+    /// Relays to look for the relay list on (may be repeated). The nprofile's
+    /// relay hints are also used.
+    #[arg(short, long)]
+    bootstrap: Vec<String>,
+
+    /// Filter JSON selecting the events to fetch. The user is added as the author.
+    #[arg(short, long, default_value = r#"{"kinds":[1],"limit":20}"#)]
+    filter: String,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let _finish = output::finish_on_drop();
+    let args = Args::parse();
+
+    let user = nip19::parse_pubkey(&args.user)?;
+    let pubkey = user.pubkey;
+    let mut filter: Filter = serde_json::from_str(&args.filter)?;
+    filter.add_author(pubkey);
+
+    let mut seeds: Vec<String> = Vec::new();
+    let bootstrap: Vec<String> = if args.bootstrap.is_empty() {
+        BOOTSTRAP_RELAYS.iter().map(|r| r.to_string()).collect()
+    } else {
+        args.bootstrap.clone()
+    };
+    for url in user.relays.iter().chain(bootstrap.iter()) {
+        if let Some(url) = outbox::normalize_relay_url(url) {
+            if !seeds.contains(&url) {
+                seeds.push(url);
+            }
+        }
+    }
+
+    eprintln!("Looking up the relay list on {}", seeds.join(", "));
+    let write_relays: Vec<String> = match outbox::find_relay_list(pubkey, &seeds).await {
+        Ok(Some(relay_list)) => {
+            let write: Vec<String> = outbox::parse_relay_list(&relay_list)
+                .into_iter()
+                .filter(|e| e.write)
+                .map(|e| e.url)
+                .collect();
+            if write.is_empty() {
+                eprintln!("The relay list has no write relays, using the lookup relays");
+                seeds.clone()
+            } else {
+                write
+            }
+        }
+        Ok(None) => {
+            eprintln!("No relay list found, using the lookup relays");
+            seeds.clone()
+        }
+        Err(e) => {
+            eprintln!(
+                "Could not look up the relay list ({}), using the lookup relays",
+                e
+            );
+            seeds.clone()
+        }
+    };
+    eprintln!("Fetching from {}", write_relays.join(", "));
+
+    let fetches = write_relays.iter().map(|relay| {
+        let filter = filter.clone();
+        async move {
+            nostr_probe::fetch_all(relay, vec![filter])
+                .await
+                .map_err(|e| e.to_string())
+        }
+    });
+    let results = join_all(fetches).await;
+
+    // Merge, remembering how much each relay contributed
+    let mut seen: HashSet<Id> = HashSet::new();
+    let mut events: Vec<(&String, Event)> = Vec::new();
+    let mut summary: Vec<String> = Vec::new();
+    for (relay, result) in write_relays.iter().zip(results) {
+        match result {
+            Ok(relay_events) => {
+                let total = relay_events.len();
+                let mut new = 0;
+                for event in relay_events {
+                    if seen.insert(event.id) {
+                        events.push((relay, event));
+                        new += 1;
+                    }
+                }
+                summary.push(format!("{}: {} events ({} new)", relay, total, new));
+            }
+            Err(e) => summary.push(format!("{}: FAILED: {}", relay, e)),
+        }
+    }
+
+    // Each relay applies the limit by itself, so apply it again to the merged events
+    events.sort_by(|(_, a), (_, b)| b.created_at.0.cmp(&a.created_at.0));
+    let distinct = events.len();
+    if let Some(limit) = filter.limit {
+        events.truncate(limit);
+    }
+    for (relay, event) in &events {
+        output::event(Source::relay(relay), event)?;
+    }
+
+    for line in summary {
+        eprintln!("{}", line);
+    }
+    if events.len() < distinct {
+        eprintln!(
+            "{} distinct events, showing the newest {}",
+            distinct,
+            events.len()
+        );
+    } else {
+        eprintln!("{} distinct events", distinct);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,295 @@
+use clap::Parser;
+use nostr_probe::{nip10, nip19, outbox};
+use nostr_types::{Event, EventKind, Filter, Id};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/// Reconstruct the reply thread a note belongs to: fetch its root and every reply
+/// (recursively, by `#e`), and print the conversation as a tree
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// The note, as a note, nevent or hex id
+    note: String,
+
+    /// Relays to ask (the nevent's relay hints are also used)
+    relays: Vec<String>,
+
+    /// Kinds that count as replies (may be repeated)
+    #[arg(short, long, default_value = "1")]
+    kind: Vec<u32>,
+
+    /// How many levels of replies to follow
+    #[arg(short, long, default_value_t = 20)]
+    max_depth: usize,
+
+    /// Print the thread as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+// Ids per #e filter
+const CHUNK: usize = 200;
+
+#[derive(Debug, Serialize)]
+struct Node {
+    id: String,
+    pubkey: Option<String>,
+    created_at: Option<i64>,
+    content: Option<String>,
+    problems: Vec<String>,
+    replies: Vec<Node>,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    root: Node,
+    orphans: Vec<Node>,
+}
+
+struct Thread {
+    root_id: String,
+    events: HashMap<String, Event>,
+    positions: HashMap<String, nip10::Thread>,
+    children: HashMap<String, Vec<String>>,
+}
+
+impl Thread {
+    // Returns false if we already had the event
+    fn add(&mut self, event: Event) -> bool {
+        let id = event.id.as_hex_string();
+        if self.events.contains_key(&id) {
+            return false;
+        }
+        let position = nip10::parse(&event);
+        if let Some(parent) = position.parent() {
+            self.children
+                .entry(parent.clone())
+                .or_default()
+                .push(id.clone());
+        }
+        self.positions.insert(id.clone(), position);
+        self.events.insert(id, event);
+        true
+    }
+
+    fn problems(&self, id: &str) -> Vec<String> {
+        let position = match self.positions.get(id) {
+            Some(p) => p,
+            None => return vec![],
+        };
+        let mut problems = position.problems.clone();
+        if id == self.root_id {
+            if position.root.is_some() {
+                problems.push("the root is itself a reply".to_owned());
+            }
+        } else if position.root.as_ref() != Some(&self.root_id) {
+            problems.push(match &position.root {
+                Some(r) => format!("root tag points to {}", r),
+                None => "no root tag".to_owned(),
+            });
+        }
+        problems
+    }
+
+    fn node(&self, id: &str, visited: &mut HashSet<String>) -> Node {
+        visited.insert(id.to_owned());
+        let event = self.events.get(id);
+        let mut replies: Vec<Node> = Vec::new();
+        for child in self.children.get(id).into_iter().flatten() {
+            if !visited.contains(child) {
+                replies.push(self.node(child, visited));
+            }
+        }
+        replies.sort_by_key(|n| n.created_at);
+        Node {
+            id: id.to_owned(),
+            pubkey: event.map(|e| e.pubkey.as_hex_string()),
+            created_at: event.map(|e| e.created_at.0),
+            content: event.map(|e| e.content.clone()),
+            problems: self.problems(id),
+            replies,
+        }
+    }
+
+    fn report(&self) -> Report {
+        let mut visited: HashSet<String> = HashSet::new();
+        let root = self.node(&self.root_id, &mut visited);
+
+        // Replies whose parent we never found, each with whatever hangs off it
+        let mut orphans: Vec<Node> = Vec::new();
+        let mut ids: Vec<&String> = self.events.keys().collect();
+        ids.sort_by_key(|id| self.events[*id].created_at.0);
+        for id in ids {
+            if visited.contains(id) {
+                continue;
+            }
+            let parent = self.positions.get(id).and_then(|p| p.parent());
+            let orphaned = match parent {
+                Some(p) => !self.events.contains_key(p) && *p != self.root_id,
+                None => true,
+            };
+            if orphaned {
+                let mut node = self.node(id, &mut visited);
+                let missing = match parent {
+                    Some(p) => format!("orphaned: parent {} not found", p),
+                    None => "orphaned: no parent".to_owned(),
+                };
+                node.problems.insert(0, missing);
+                orphans.push(node);
+            }
+        }
+        Report { root, orphans }
+    }
+}
+
+async fn fetch_by_id(relays: &[String], id: &str) -> Option<Event> {
+    let id = Id::try_from_hex_string(id).ok()?;
+    let mut filter = Filter::new();
+    filter.add_id(id);
+    nostr_probe::fetch_all_from(relays, vec![filter])
+        .await
+        .0
+        .into_iter()
+        .find(|e| e.id == id)
+}
+
+// Ids come from tags, so they may not be valid hex of the usual length
+fn short(id: &str) -> &str {
+    id.get(..8).unwrap_or(id)
+}
+
+fn print_node(node: &Node, depth: usize) {
+    let indent = "  ".repeat(depth);
+    let snippet: String = node
+        .content
+        .as_deref()
+        .unwrap_or("")
+        .lines()
+        .next()
+        .unwrap_or("")
+        .chars()
+        .take(60)
+        .collect();
+    match (&node.pubkey, node.created_at) {
+        (Some(pubkey), Some(created_at)) => println!(
+            "{}{} {} {} {}",
+            indent,
+            short(&node.id),
+            short(pubkey),
+            nostr_probe::format_unixtime(created_at),
+            snippet
+        ),
+        _ => println!("{}{} (not found)", indent, short(&node.id)),
+    }
+    for problem in &node.problems {
+        println!("{}  ! {}", indent, problem);
+    }
+    for reply in &node.replies {
+        print_node(reply, depth + 1);
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let note = nip19::parse_id(&args.note)?;
+    let mut relays: Vec<String> = note.relays.clone();
+    for url in &args.relays {
+        match outbox::normalize_relay_url(url) {
+            Some(url) => {
+                if !relays.contains(&url) {
+                    relays.push(url);
+                }
+            }
+            None => eprintln!("Skipping {}: not a websocket URL", url),
+        }
+    }
+    if relays.is_empty() {
+        return Err("The note has no relay hints, give at least one relay".into());
+    }
+    let kinds: Vec<EventKind> = args.kind.iter().map(|k| (*k).into()).collect();
+
+    let note_id = note.id.as_hex_string();
+    let target = match fetch_by_id(&relays, &note_id).await {
+        Some(e) => e,
+        None => return Err(format!("No relay had {}", note_id).into()),
+    };
+    let root_id = nip10::parse(&target)
+        .root
+        .unwrap_or_else(|| note_id.clone());
+
+    let mut thread = Thread {
+        root_id: root_id.clone(),
+        events: HashMap::new(),
+        positions: HashMap::new(),
+        children: HashMap::new(),
+    };
+
+    thread.add(target);
+    if root_id != note_id {
+        match fetch_by_id(&relays, &root_id).await {
+            Some(root) => {
+                thread.add(root);
+            }
+            None => eprintln!("The root {} was not found", root_id),
+        }
+    }
+
+    // Follow replies outwards, one level per round
+    let mut known: HashSet<String> = thread.events.keys().cloned().collect();
+    known.insert(root_id.clone());
+    let mut frontier: Vec<String> = known.iter().cloned().collect();
+    for depth in 0..args.max_depth {
+        if frontier.is_empty() {
+            break;
+        }
+        let filters: Vec<Filter> = frontier
+            .chunks(CHUNK)
+            .map(|ids| {
+                let mut filter = Filter {
+                    kinds: kinds.clone(),
+                    ..Default::default()
+                };
+                for id in ids {
+                    filter.add_tag_value('e', id.clone());
+                }
+                filter
+            })
+            .collect();
+        frontier.clear();
+        for event in nostr_probe::fetch_all_from(&relays, filters).await.0 {
+            // Only replies, not notes that merely mention the thread
+            let position = nip10::parse(&event);
+            let in_thread = [&position.root, &position.reply]
+                .iter()
+                .any(|r| r.as_ref().is_some_and(|r| known.contains(r)));
+            if !in_thread {
+                continue;
+            }
+            let id = event.id.as_hex_string();
+            if thread.add(event) {
+                frontier.push(id);
+            }
+        }
+        known.extend(frontier.iter().cloned());
+        eprintln!("Depth {}: {} new replies", depth + 1, frontier.len());
+    }
+
+    let report = thread.report();
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_node(&report.root, 0);
+        if !report.orphans.is_empty() {
+            println!();
+            println!("Orphaned replies:");
+            for orphan in &report.orphans {
+                print_node(orphan, 1);
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,74 @@
+use clap::Parser;
+use nostr_probe::contacts::{self, Contact, EditOptions};
+use nostr_probe::nip19;
+
+/// Follow people by adding them to your newest contact list (kind 3), keeping
+/// everything else in it
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// The people to follow, as npub, nprofile or hex public keys. An nprofile's
+    /// first relay hint is kept with the follow.
+    #[arg(required = true)]
+    users: Vec<String>,
+
+    /// A petname for the person followed (only when following one person)
+    #[arg(long)]
+    petname: Option<String>,
+
+    /// Relays to fetch your list from and publish it to (may be repeated). Defaults
+    /// to the write relays in your relay list.
+    #[arg(short, long)]
+    relay: Vec<String>,
+
+    /// Edit the newest list found even if some relays could not be checked for a
+    /// newer one
+    #[arg(long)]
+    allow_unchecked: bool,
+
+    /// Start a new list if none is found
+    #[arg(long)]
+    new_list: bool,
+
+    /// Publish even if the list shrinks drastically
+    #[arg(long)]
+    allow_shrink: bool,
+
+    /// Don't ask before signing
+    #[arg(short, long)]
+    yes: bool,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    if args.petname.is_some() && args.users.len() > 1 {
+        return Err("A petname can only be given when following one person".into());
+    }
+    let mut people: Vec<Contact> = Vec::new();
+    for user in &args.users {
+        let user = nip19::parse_pubkey(user)?;
+        people.push(Contact {
+            pubkey: user.pubkey.as_hex_string(),
+            relay: user.relays.first().cloned(),
+            petname: args.petname.clone(),
+        });
+    }
+
+    let options = EditOptions {
+        relays: args.relay.clone(),
+        allow_unchecked: args.allow_unchecked,
+        new_list: args.new_list,
+        allow_shrink: args.allow_shrink,
+        yes: args.yes,
+    };
+    contacts::edit_contact_list(&options, |tags| {
+        let mut changed = false;
+        for person in &people {
+            changed |= contacts::follow(tags, person);
+        }
+        changed
+    })
+    .await
+}
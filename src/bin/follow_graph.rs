@@ -0,0 +1,125 @@
+use clap::Parser;
+use nostr_probe::{contacts, nip19};
+use nostr_types::PublicKey;
+use std::collections::{HashMap, HashSet};
+
+/// Walk contact lists (kind 3) outwards from a user, following follows-of-follows
+/// to a given depth, and print the graph as an edge list or in DOT
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// The user, as an npub, nprofile or hex public key
+    user: String,
+
+    /// Relays to ask (may be repeated). Defaults to the nprofile's relay hints and
+    /// some well known relays.
+    #[arg(short, long)]
+    relay: Vec<String>,
+
+    /// How many steps of follows to walk
+    #[arg(short, long, default_value_t = 2, value_parser = clap::value_parser!(u64).range(1..=4))]
+    depth: u64,
+
+    /// Follow at most this many contacts from each list
+    #[arg(long, default_value_t = 50)]
+    fan_out: usize,
+
+    /// Stop adding people to the graph after this many
+    #[arg(long, default_value_t = 2000)]
+    max_nodes: usize,
+
+    /// Output format
+    #[arg(short, long, default_value = "edges", value_parser = ["edges", "dot"])]
+    format: String,
+}
+
+fn dot_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let user = nip19::parse_pubkey(&args.user)?;
+    let relays = contacts::relays_to_ask(&args.relay, &user.relays);
+
+    let root = user.pubkey.as_hex_string();
+    let mut nodes: Vec<String> = vec![root.clone()];
+    let mut in_graph: HashSet<String> = HashSet::from([root.clone()]);
+    let mut petnames: HashMap<String, String> = HashMap::new();
+    let mut edges: Vec<(String, String)> = Vec::new();
+    let mut skipped: usize = 0;
+
+    let mut frontier: Vec<PublicKey> = vec![user.pubkey];
+    for depth in 0..args.depth {
+        if frontier.is_empty() {
+            break;
+        }
+        let lists = contacts::contact_lists(&frontier, &relays).await;
+        let mut next: Vec<PublicKey> = Vec::new();
+        for author in &frontier {
+            let from = author.as_hex_string();
+            let list = match lists.get(&from) {
+                Some(list) => list,
+                None => continue,
+            };
+            for contact in contacts::parse_contact_list(list)
+                .into_iter()
+                .take(args.fan_out)
+            {
+                if !in_graph.contains(&contact.pubkey) {
+                    if in_graph.len() >= args.max_nodes {
+                        skipped += 1;
+                        continue;
+                    }
+                    in_graph.insert(contact.pubkey.clone());
+                    nodes.push(contact.pubkey.clone());
+                    next.push(PublicKey::try_from_hex_string(&contact.pubkey, true)?);
+                }
+                if let Some(petname) = &contact.petname {
+                    petnames
+                        .entry(contact.pubkey.clone())
+                        .or_insert(petname.clone());
+                }
+                edges.push((from.clone(), contact.pubkey));
+            }
+        }
+        eprintln!(
+            "Depth {}: {} of {} contact lists found, {} new people",
+            depth + 1,
+            lists.len(),
+            frontier.len(),
+            next.len()
+        );
+        frontier = next;
+    }
+    if skipped > 0 {
+        eprintln!(
+            "Left out {} follows after reaching {} people",
+            skipped, args.max_nodes
+        );
+    }
+
+    if args.format == "dot" {
+        println!("digraph follows {{");
+        for node in &nodes {
+            let label = match petnames.get(node) {
+                Some(petname) => petname.clone(),
+                None => node[..8].to_owned(),
+            };
+            println!("  {} [label={}];", dot_string(node), dot_string(&label));
+        }
+        for (from, to) in &edges {
+            println!("  {} -> {};", dot_string(from), dot_string(to));
+        }
+        println!("}}");
+    } else {
+        for (from, to) in &edges {
+            println!("{} {}", from, to);
+        }
+    }
+    eprintln!("{} people, {} follows", nodes.len(), edges.len());
+
+    Ok(())
+}
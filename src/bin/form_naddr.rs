@@ -1,4 +1,4 @@
-use nostr_types::{EventKind, NAddr, NostrUrl, PublicKey, UncheckedUrl};
+use nostr_types::{EventKind, NAddr, NostrUrl, UncheckedUrl};
 use std::env;
 
 fn main() {
@@ -6,7 +6,7 @@ fn main() {
     let _ = args.next(); // program name
 
     let usage = |msg| -> ! {
-        panic!("{}\nUsage: form_event_addr <kind_number> <author_pubkeyhex> <d-identifier> [<relay_url> ...]", msg);
+        panic!("{}\nUsage: form_event_addr <kind_number> <author_pubkey> <d-identifier> [<relay_url> ...]", msg);
     };
 
     let kind: EventKind = match args.next() {
@@ -20,8 +20,10 @@ fn main() {
     };
 
     let author = match args.next() {
-        Some(hex) => PublicKey::try_from_hex_string(&hex, true)
-            .unwrap_or_else(|_| usage("Public key not parsed")),
+        Some(key) => match nostr_probe::nip19::parse_pubkey(&key) {
+            Ok(arg) => arg.pubkey,
+            Err(_) => usage("Public key not parsed"),
+        },
         None => usage("Public key missing"),
     };
 
@@ -0,0 +1,434 @@
+use clap::Parser;
+use futures_util::{SinkExt, StreamExt};
+use nostr_probe::Ws;
+use nostr_types::{EventKind, KeySigner, PreEvent, PrivateKey, Signer, Tag, Unixtime};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tungstenite::protocol::frame::coding::{Data, OpCode};
+use tungstenite::protocol::frame::Frame;
+use tungstenite::Message;
+
+// Hard cap on the size of a single oversized frame
+const MAX_FRAME_BYTES: u64 = 16 * 1024 * 1024;
+
+// How long to wait for the relay to answer the known-good REQ
+const HEALTH_TIMEOUT_SECS: u64 = 10;
+
+/// Send a relay mutated and malformed protocol messages, checking after each one
+/// that it still answers a known-good REQ. Inputs that leave the relay
+/// unresponsive are saved to a corpus directory.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// The relay to fuzz. Only fuzz relays you run or have permission to test.
+    relay: String,
+
+    /// Number of mutated messages to send
+    #[arg(short = 'n', long, default_value_t = 200)]
+    iterations: usize,
+
+    /// Random seed, so a run can be repeated exactly
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Where to save inputs that made the relay unresponsive
+    #[arg(long, default_value = "fuzz-corpus")]
+    corpus: PathBuf,
+
+    /// Size of oversized frames, in bytes
+    #[arg(long, default_value_t = 1024 * 1024,
+          value_parser = clap::value_parser!(u64).range(1..=MAX_FRAME_BYTES))]
+    frame_bytes: u64,
+
+    /// Pause between inputs, in milliseconds
+    #[arg(long, default_value_t = 50)]
+    delay_ms: u64,
+
+    /// Stop at the first input that makes the relay unresponsive
+    #[arg(long)]
+    stop_on_crash: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Mutation {
+    BadJson,
+    WrongArity,
+    WrongTypes,
+    HugeNumbers,
+    InvalidHex,
+    DeepTags,
+    UnknownVerb,
+    NonUtf8,
+    Oversized,
+}
+
+const MUTATIONS: &[Mutation] = &[
+    Mutation::BadJson,
+    Mutation::WrongArity,
+    Mutation::WrongTypes,
+    Mutation::HugeNumbers,
+    Mutation::InvalidHex,
+    Mutation::DeepTags,
+    Mutation::UnknownVerb,
+    Mutation::NonUtf8,
+    Mutation::Oversized,
+];
+
+impl Mutation {
+    fn name(&self) -> &'static str {
+        match self {
+            Mutation::BadJson => "bad-json",
+            Mutation::WrongArity => "wrong-arity",
+            Mutation::WrongTypes => "wrong-types",
+            Mutation::HugeNumbers => "huge-numbers",
+            Mutation::InvalidHex => "invalid-hex",
+            Mutation::DeepTags => "deep-tags",
+            Mutation::UnknownVerb => "unknown-verb",
+            Mutation::NonUtf8 => "non-utf8",
+            Mutation::Oversized => "oversized",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    sent: usize,
+    healthy: usize,
+    disconnected: usize,
+    unresponsive: usize,
+}
+
+// Well-formed client messages to mutate
+fn seeds(signer: &KeySigner) -> Vec<Value> {
+    let pre_event = PreEvent {
+        pubkey: signer.public_key(),
+        created_at: Unixtime::now(),
+        kind: EventKind::TextNote,
+        tags: vec![Tag::new(&["t", "nostr-probe-fuzz"])],
+        content: "nostr-probe fuzzing event. It can be ignored or deleted.".to_owned(),
+    };
+    let event = signer.sign_event(pre_event).unwrap();
+    let event = serde_json::to_value(&event).unwrap();
+    vec![
+        json!(["EVENT", event]),
+        json!(["REQ", "fuzz", {"kinds": [1], "limit": 5}]),
+        json!(["REQ", "fuzz", {"authors": [event["pubkey"]], "#t": ["a"], "since": 0}]),
+        json!(["COUNT", "fuzz", {"kinds": [1]}]),
+        json!(["CLOSE", "fuzz"]),
+        json!(["AUTH", event]),
+    ]
+}
+
+// A random leaf-ish position within a JSON value
+fn pick_mut<'a>(value: &'a mut Value, rng: &mut StdRng) -> &'a mut Value {
+    let descend = rng.gen_bool(0.7);
+    match value {
+        Value::Array(a) if descend && !a.is_empty() => {
+            let i = rng.gen_range(0..a.len());
+            pick_mut(&mut a[i], rng)
+        }
+        Value::Object(o) if descend && !o.is_empty() => {
+            let i = rng.gen_range(0..o.len());
+            let key = o.keys().nth(i).cloned().unwrap();
+            pick_mut(o.get_mut(&key).unwrap(), rng)
+        }
+        v => v,
+    }
+}
+
+fn is_hex(s: &str) -> bool {
+    s.len() >= 64 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// How many strings in a value look like ids, pubkeys or signatures
+fn count_hex(value: &Value) -> usize {
+    match value {
+        Value::String(s) if is_hex(s) => 1,
+        Value::Array(a) => a.iter().map(count_hex).sum(),
+        Value::Object(o) => o.values().map(count_hex).sum(),
+        _ => 0,
+    }
+}
+
+// The nth such string
+fn nth_hex<'a>(value: &'a mut Value, n: &mut usize) -> Option<&'a mut String> {
+    match value {
+        Value::String(s) if is_hex(s) => {
+            if *n == 0 {
+                return Some(s);
+            }
+            *n -= 1;
+            None
+        }
+        Value::Array(a) => a.iter_mut().find_map(|v| nth_hex(v, n)),
+        Value::Object(o) => o.values_mut().find_map(|v| nth_hex(v, n)),
+        _ => None,
+    }
+}
+
+fn mutate(seed: &Value, mutation: Mutation, rng: &mut StdRng, frame_bytes: usize) -> Message {
+    let mut value = seed.clone();
+    match mutation {
+        Mutation::BadJson => {
+            let mut text = value.to_string();
+            let at = rng.gen_range(0..text.len());
+            match rng.gen_range(0..3) {
+                0 => text.truncate(at),
+                1 => {
+                    let junk = ["{", "]", ",", "\"", ":", "\\", "nul"];
+                    text.insert_str(at, junk[rng.gen_range(0..junk.len())]);
+                }
+                _ => {
+                    text.remove(at);
+                }
+            }
+            return Message::Text(text);
+        }
+        Mutation::WrongArity => {
+            if let Value::Array(a) = &mut value {
+                if rng.gen_bool(0.5) && a.len() > 1 {
+                    let n = rng.gen_range(1..a.len());
+                    a.truncate(n);
+                } else {
+                    for _ in 0..rng.gen_range(1..4) {
+                        a.push(json!("extra"));
+                    }
+                }
+            }
+        }
+        Mutation::WrongTypes => {
+            let replacements = [
+                json!(null),
+                json!(true),
+                json!(-1),
+                json!(1.5),
+                json!(""),
+                json!([]),
+                json!({}),
+            ];
+            *pick_mut(&mut value, rng) = replacements[rng.gen_range(0..replacements.len())].clone();
+        }
+        Mutation::HugeNumbers => {
+            let numbers = [
+                "18446744073709551616",
+                "-9223372036854775809",
+                "1e400",
+                "-0",
+                "123456789012345678901234567890",
+            ];
+            let number = numbers[rng.gen_range(0..numbers.len())];
+            // Such numbers can't be represented in a Value, so splice them into the text
+            *pick_mut(&mut value, rng) = json!("@@NUMBER@@");
+            return Message::Text(value.to_string().replace("\"@@NUMBER@@\"", number));
+        }
+        Mutation::InvalidHex => {
+            let count = count_hex(&value);
+            if count > 0 {
+                let mut n = rng.gen_range(0..count);
+                let target = nth_hex(&mut value, &mut n).unwrap();
+                match rng.gen_range(0..3) {
+                    0 => target.replace_range(0..1, "z"),
+                    1 => target.truncate(target.len() - 1),
+                    _ => *target = target.to_uppercase(),
+                }
+            } else {
+                *pick_mut(&mut value, rng) = json!("not-hex-zz");
+            }
+        }
+        Mutation::DeepTags => {
+            let depth = rng.gen_range(100..10_000);
+            let deep = format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+            *pick_mut(&mut value, rng) = json!("@@DEEP@@");
+            return Message::Text(value.to_string().replace("\"@@DEEP@@\"", &deep));
+        }
+        Mutation::UnknownVerb => {
+            if let Value::Array(a) = &mut value {
+                let verbs = ["", "event", "EVENT ", "REQQ", "\u{0000}", "🦀"];
+                a[0] = json!(verbs[rng.gen_range(0..verbs.len())]);
+            }
+        }
+        Mutation::NonUtf8 => {
+            let mut bytes = value.to_string().into_bytes();
+            let at = rng.gen_range(0..bytes.len());
+            bytes.insert(at, [0xff, 0xc0, 0x80, 0xfe][rng.gen_range(0..4)]);
+            // A text frame, since relays just ignore binary ones. Message::Text can't
+            // hold invalid UTF-8, so the frame is built by hand.
+            return Message::Frame(Frame::message(bytes, OpCode::Data(Data::Text), true));
+        }
+        Mutation::Oversized => {
+            *pick_mut(&mut value, rng) = json!("x".repeat(frame_bytes));
+        }
+    }
+    Message::Text(value.to_string())
+}
+
+// Send a known-good REQ and wait for EOSE, CLOSED or an event for it
+async fn healthy(websocket: &mut Ws) -> bool {
+    let req = json!(["REQ", "fuzz-health", {"kinds": [1], "limit": 1}]).to_string();
+    if websocket.send(Message::Text(req)).await.is_err() {
+        return false;
+    }
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(HEALTH_TIMEOUT_SECS);
+    loop {
+        let message = match tokio::time::timeout_at(deadline, websocket.next()).await {
+            Ok(Some(Ok(m))) => m,
+            _ => return false,
+        };
+        if let Message::Text(s) = message {
+            if let Ok(Value::Array(a)) = serde_json::from_str::<Value>(&s) {
+                if a.get(1) == Some(&json!("fuzz-health")) {
+                    let close = json!(["CLOSE", "fuzz-health"]).to_string();
+                    let _ = websocket.send(Message::Text(close)).await;
+                    return true;
+                }
+            }
+        }
+    }
+}
+
+// Drain whatever the relay says in reply to a fuzzed input
+async fn drain(websocket: &mut Ws) -> Result<(), ()> {
+    loop {
+        match tokio::time::timeout(Duration::from_millis(500), websocket.next()).await {
+            Err(_) => return Ok(()),
+            Ok(Some(Ok(Message::Close(_)))) | Ok(Some(Err(_))) | Ok(None) => return Err(()),
+            Ok(Some(Ok(Message::Text(s)))) => eprintln!("  relay: {}", truncated(&s)),
+            Ok(Some(Ok(_))) => {}
+        }
+    }
+}
+
+fn truncated(s: &str) -> String {
+    if s.len() > 200 {
+        let mut end = 200;
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        format!("{}... ({} bytes)", &s[..end], s.len())
+    } else {
+        s.to_owned()
+    }
+}
+
+fn save(
+    corpus: &Path,
+    iteration: usize,
+    mutation: Mutation,
+    message: &Message,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    std::fs::create_dir_all(corpus)?;
+    let path = corpus.join(format!("{:05}-{}.bin", iteration, mutation.name()));
+    std::fs::write(&path, message.clone().into_data())?;
+    Ok(path)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let seed = args.seed.unwrap_or_else(rand::random);
+    eprintln!("Seed: {}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let private_key = PrivateKey::generate();
+    let signer = KeySigner::from_private_key(private_key, "pass", 16)?;
+    let seeds = seeds(&signer);
+
+    let mut websocket = nostr_probe::connect_websocket(&args.relay, 15).await?;
+    if !healthy(&mut websocket).await {
+        return Err("The relay does not answer a plain REQ, so it can't be fuzzed".into());
+    }
+
+    let mut counts: BTreeMap<Mutation, Counts> = BTreeMap::new();
+    let mut crashes: Vec<PathBuf> = Vec::new();
+
+    for iteration in 0..args.iterations {
+        let mutation = MUTATIONS[rng.gen_range(0..MUTATIONS.len())];
+        let seed = &seeds[rng.gen_range(0..seeds.len())];
+        let message = mutate(seed, mutation, &mut rng, args.frame_bytes as usize);
+        let entry = counts.entry(mutation).or_default();
+        entry.sent += 1;
+
+        let shown = match &message {
+            Message::Text(s) => truncated(s),
+            Message::Frame(f) => format!("<{} bytes of invalid UTF-8 text>", f.payload().len()),
+            m => format!("<{} binary bytes>", m.len()),
+        };
+        eprintln!("[{}] {}: {}", iteration, mutation.name(), shown);
+
+        let sent = websocket.send(message.clone()).await.is_ok();
+        let survived = sent && drain(&mut websocket).await.is_ok() && healthy(&mut websocket).await;
+
+        if survived {
+            entry.healthy += 1;
+        } else {
+            // The relay dropped us, which is fine as long as it still serves others
+            let fresh = match nostr_probe::connect_websocket(&args.relay, 15).await {
+                Ok(mut ws) => {
+                    if healthy(&mut ws).await {
+                        Some(ws)
+                    } else {
+                        None
+                    }
+                }
+                Err(_) => None,
+            };
+            match fresh {
+                Some(ws) => {
+                    entry.disconnected += 1;
+                    eprintln!("  relay dropped the connection");
+                    websocket = ws;
+                }
+                None => {
+                    entry.unresponsive += 1;
+                    let path = save(&args.corpus, iteration, mutation, &message)?;
+                    eprintln!("  RELAY UNRESPONSIVE, input saved to {}", path.display());
+                    crashes.push(path);
+                    if args.stop_on_crash {
+                        break;
+                    }
+                    // Give it a chance to come back before carrying on
+                    tokio::time::sleep(Duration::from_secs(HEALTH_TIMEOUT_SECS)).await;
+                    match nostr_probe::connect_websocket(&args.relay, 15).await {
+                        Ok(ws) => websocket = ws,
+                        Err(e) => {
+                            eprintln!("  could not reconnect, stopping: {}", e);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        tokio::time::sleep(Duration::from_millis(args.delay_ms)).await;
+    }
+
+    let _ = websocket.send(Message::Close(None)).await;
+
+    println!(
+        "{:<14} {:>6} {:>8} {:>12} {:>12}",
+        "mutation", "sent", "healthy", "disconnected", "unresponsive"
+    );
+    for (mutation, c) in &counts {
+        println!(
+            "{:<14} {:>6} {:>8} {:>12} {:>12}",
+            mutation.name(),
+            c.sent,
+            c.healthy,
+            c.disconnected,
+            c.unresponsive
+        );
+    }
+    for path in &crashes {
+        println!("Crashing input: {}", path.display());
+    }
+
+    if !crashes.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,362 @@
+use clap::Parser;
+use nostr_probe::conformance::Author;
+use nostr_probe::{Command, Probe};
+use nostr_types::{Event, Filter, Id, RelayMessage, SubscriptionId, Unixtime};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio::sync::mpsc::{Receiver, Sender};
+
+// Hard caps, so that this cannot be turned into a flood tool
+const MAX_EVENTS_PER_SEC: u64 = 50;
+const MAX_STEP_SECS: u64 = 30;
+const MAX_TOTAL_EVENTS: u64 = 1000;
+const MAX_SUBSCRIPTIONS: u64 = 100;
+const MAX_CONNECTIONS: u64 = 20;
+
+// How long to wait for a response before deciding the relay is ignoring us
+const RESPONSE_TIMEOUT_SECS: u64 = 10;
+
+/// Find out how much load a relay tolerates before it starts limiting us, by
+/// ramping up events per second, subscriptions per connection and concurrent
+/// connections until the relay pushes back or a hard cap is reached.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// The relay to probe
+    relay: String,
+
+    /// Highest publishing rate to try, in events per second
+    #[arg(long, default_value_t = 20,
+          value_parser = clap::value_parser!(u64).range(1..=MAX_EVENTS_PER_SEC))]
+    max_rate: u64,
+
+    /// How long to hold each publishing rate, in seconds
+    #[arg(long, default_value_t = 5,
+          value_parser = clap::value_parser!(u64).range(1..=MAX_STEP_SECS))]
+    step_secs: u64,
+
+    /// Most subscriptions to open on one connection
+    #[arg(long, default_value_t = 50,
+          value_parser = clap::value_parser!(u64).range(1..=MAX_SUBSCRIPTIONS))]
+    max_subscriptions: u64,
+
+    /// Most connections to open at once
+    #[arg(long, default_value_t = 10,
+          value_parser = clap::value_parser!(u64).range(1..=MAX_CONNECTIONS))]
+    max_connections: u64,
+
+    /// Kind of the events published. Defaults to an ephemeral kind so the relay
+    /// does not keep them.
+    #[arg(long, default_value_t = 20_555)]
+    kind: u32,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+struct Connection {
+    to_probe: Sender<Command>,
+    from_probe: Receiver<RelayMessage>,
+    join_handle: tokio::task::JoinHandle<()>,
+}
+
+impl Connection {
+    fn open(url: &str) -> Connection {
+        let (to_probe, from_main) = tokio::sync::mpsc::channel::<Command>(100);
+        let (to_main, from_probe) = tokio::sync::mpsc::channel::<RelayMessage>(100);
+        let relay_url = url.to_owned();
+        let join_handle = tokio::spawn(async move {
+            let mut probe = Probe::new(from_main, to_main);
+            if let Err(e) = probe.connect_and_listen_with_timeout(&relay_url, 60).await {
+                eprintln!("{}", e);
+            }
+        });
+        Connection {
+            to_probe,
+            from_probe,
+            join_handle,
+        }
+    }
+
+    async fn close(self) {
+        let _ = self.to_probe.send(Command::Exit).await;
+        let _ = self.join_handle.await;
+    }
+
+    // Open a subscription and wait for EOSE, returning what went wrong if it didn't come
+    async fn subscribe(&mut self, sub: SubscriptionId, filter: Filter) -> Option<String> {
+        if self
+            .to_probe
+            .send(Command::FetchEvents(sub.clone(), vec![filter]))
+            .await
+            .is_err()
+        {
+            return Some("disconnected".to_owned());
+        }
+        let timeout = Duration::from_secs(RESPONSE_TIMEOUT_SECS);
+        loop {
+            match tokio::time::timeout(timeout, self.from_probe.recv()).await {
+                Ok(Some(RelayMessage::Eose(s))) if s == sub => return None,
+                Ok(Some(RelayMessage::Closed(s, reason))) if s == sub => {
+                    return Some(format!("CLOSED: {}", reason))
+                }
+                Ok(Some(RelayMessage::Notice(notice))) => {
+                    return Some(format!("NOTICE: {}", notice))
+                }
+                Ok(Some(_)) => {}
+                Ok(None) => return Some("disconnected".to_owned()),
+                Err(_) => return Some(format!("no EOSE within {} seconds", RESPONSE_TIMEOUT_SECS)),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Step {
+    rate: u64,
+    sent: u64,
+    accepted: u64,
+    rate_limited: u64,
+    rejected: u64,
+    notices: u64,
+    unanswered: u64,
+    disconnected: bool,
+    first_limit: Option<String>,
+}
+
+impl Step {
+    fn limited(&self) -> bool {
+        self.rate_limited > 0 || self.notices > 0 || self.disconnected
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Report {
+    relay: String,
+    publish_steps: Vec<Step>,
+    publish_limited_at_rate: Option<u64>,
+    subscriptions_opened: u64,
+    subscriptions_limit: Option<String>,
+    connections_opened: u64,
+    connections_limit: Option<String>,
+}
+
+// Publish events evenly spaced at `rate` per second, counting how the relay responds
+async fn publish_step(conn: &mut Connection, events: Vec<Event>, rate: u64) -> Step {
+    let mut step = Step {
+        rate,
+        ..Default::default()
+    };
+    let mut pending: HashSet<Id> = HashSet::new();
+    let mut events = events.into_iter();
+
+    let mut ticker = tokio::time::interval(Duration::from_secs_f64(1.0 / rate as f64));
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut sending = true;
+    let grace = tokio::time::sleep(Duration::from_secs(RESPONSE_TIMEOUT_SECS));
+    tokio::pin!(grace);
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick(), if sending => {
+                match events.next() {
+                    Some(event) => {
+                        pending.insert(event.id);
+                        if conn.to_probe.send(Command::PostEvent(event)).await.is_err() {
+                            step.disconnected = true;
+                            step.first_limit.get_or_insert("disconnected".to_owned());
+                            break;
+                        }
+                        step.sent += 1;
+                    }
+                    None => {
+                        sending = false;
+                        grace.as_mut().reset(
+                            tokio::time::Instant::now()
+                                + Duration::from_secs(RESPONSE_TIMEOUT_SECS),
+                        );
+                    }
+                }
+            },
+            _ = &mut grace, if !sending => break,
+            message = conn.from_probe.recv() => {
+                match message {
+                    Some(RelayMessage::Ok(id, ok, reason)) => {
+                        if !pending.remove(&id) {
+                            continue;
+                        }
+                        if ok {
+                            step.accepted += 1;
+                        } else if nostr_probe::reason_prefix(&reason) == Some("rate-limited") {
+                            step.rate_limited += 1;
+                            step.first_limit.get_or_insert(format!("OK: {}", reason));
+                        } else {
+                            step.rejected += 1;
+                        }
+                    }
+                    Some(RelayMessage::Closed(_, reason)) => {
+                        if nostr_probe::reason_prefix(&reason) == Some("rate-limited") {
+                            step.rate_limited += 1;
+                            step.first_limit.get_or_insert(format!("CLOSED: {}", reason));
+                        }
+                    }
+                    Some(RelayMessage::Notice(notice)) => {
+                        step.notices += 1;
+                        step.first_limit.get_or_insert(format!("NOTICE: {}", notice));
+                    }
+                    Some(_) => {}
+                    None => {
+                        step.disconnected = true;
+                        step.first_limit.get_or_insert("disconnected".to_owned());
+                        break;
+                    }
+                }
+                if !sending && pending.is_empty() {
+                    break;
+                }
+            },
+        }
+    }
+
+    step.unanswered = pending.len() as u64;
+    step
+}
+
+fn print_report(report: &Report) {
+    println!("Relay: {}", report.relay);
+    println!();
+    println!(
+        "{:>6} {:>6} {:>8} {:>12} {:>8} {:>7} {:>10}  limited by",
+        "rate/s", "sent", "accepted", "rate-limited", "rejected", "notices", "unanswered"
+    );
+    for step in &report.publish_steps {
+        println!(
+            "{:>6} {:>6} {:>8} {:>12} {:>8} {:>7} {:>10}  {}",
+            step.rate,
+            step.sent,
+            step.accepted,
+            step.rate_limited,
+            step.rejected,
+            step.notices,
+            step.unanswered,
+            step.first_limit.as_deref().unwrap_or("-")
+        );
+    }
+    println!();
+    match report.publish_limited_at_rate {
+        Some(rate) => println!("Publishing was limited at {} events/s", rate),
+        None => println!("Publishing was not limited up to the highest rate tried"),
+    }
+    match &report.subscriptions_limit {
+        Some(why) => println!(
+            "Subscriptions were limited after {} on one connection ({})",
+            report.subscriptions_opened, why
+        ),
+        None => println!(
+            "{} subscriptions on one connection were allowed",
+            report.subscriptions_opened
+        ),
+    }
+    match &report.connections_limit {
+        Some(why) => println!(
+            "Connections were limited after {} at once ({})",
+            report.connections_opened, why
+        ),
+        None => println!(
+            "{} connections at once were allowed",
+            report.connections_opened
+        ),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let author = Author::new();
+
+    let mut report = Report {
+        relay: args.relay.clone(),
+        ..Default::default()
+    };
+
+    // Ramp up the publishing rate, doubling until the relay pushes back
+    let mut conn = Connection::open(&args.relay);
+    let mut total: u64 = 0;
+    let mut rate: u64 = 1;
+    loop {
+        let count = (rate * args.step_secs).min(MAX_TOTAL_EVENTS - total);
+        if count == 0 {
+            eprintln!("Reached the cap of {} events", MAX_TOTAL_EVENTS);
+            break;
+        }
+        let events: Vec<Event> = (total..total + count)
+            .map(|n| {
+                let content = format!("nostr-probe load test event {}", n);
+                author.sign(args.kind, Unixtime::now().0, vec![], &content)
+            })
+            .collect();
+        total += count;
+
+        eprintln!("Publishing {} events at {}/s", count, rate);
+        let step = publish_step(&mut conn, events, rate).await;
+        let limited = step.limited();
+        let disconnected = step.disconnected;
+        report.publish_steps.push(step);
+        if limited {
+            report.publish_limited_at_rate = Some(rate);
+            break;
+        }
+        if rate == args.max_rate {
+            break;
+        }
+        rate = (rate * 2).min(args.max_rate);
+        if disconnected {
+            break;
+        }
+    }
+    conn.close().await;
+
+    // Open subscriptions on one connection, keeping them all open
+    let mut conn = Connection::open(&args.relay);
+    for n in 0..args.max_subscriptions {
+        let sub = SubscriptionId(format!("load-{}", n));
+        match conn.subscribe(sub, author.filter(args.kind)).await {
+            None => report.subscriptions_opened += 1,
+            Some(why) => {
+                report.subscriptions_limit = Some(why);
+                break;
+            }
+        }
+    }
+    conn.close().await;
+
+    // Open connections, each proving itself alive with a subscription
+    let mut conns: Vec<Connection> = Vec::new();
+    for n in 0..args.max_connections {
+        let mut conn = Connection::open(&args.relay);
+        let sub = SubscriptionId(format!("load-conn-{}", n));
+        let outcome = conn.subscribe(sub, author.filter(args.kind)).await;
+        conns.push(conn);
+        match outcome {
+            None => report.connections_opened += 1,
+            Some(why) => {
+                report.connections_limit = Some(why);
+                break;
+            }
+        }
+    }
+    for conn in conns {
+        conn.close().await;
+    }
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(&report);
+    }
+
+    Ok(())
+}
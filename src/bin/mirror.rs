@@ -0,0 +1,482 @@
+use clap::Parser;
+use futures_util::future::join_all;
+use nostr_probe::{Command, Pager, Probe};
+use nostr_types::{Event, Filter, Id, RelayMessage, Unixtime};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::time::{Duration, Instant};
+
+/// Copy events matching a filter from one relay to one or more other relays
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Relay to copy events from
+    #[arg(short, long)]
+    from: String,
+
+    /// Relay to copy events to (may be repeated)
+    #[arg(short, long, required = true)]
+    to: Vec<String>,
+
+    /// Filter JSON selecting which events to copy
+    #[arg(long, default_value = "{}")]
+    filter: String,
+
+    /// Maximum number of events awaiting an OK from each destination
+    #[arg(short, long, default_value_t = 20)]
+    concurrency: usize,
+
+    /// Seconds to wait for a destination's OK to an event before counting it as
+    /// unanswered
+    #[arg(long, default_value_t = 10)]
+    ok_timeout: u64,
+
+    /// Checkpoint file, written after each page that every destination answered in
+    /// full, and resumed from if it exists
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Checkpoint {
+    from: String,
+    filter: Filter,
+    until: Option<Unixtime>,
+    copied: usize,
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    accepted: usize,
+    duplicate: usize,
+    rejected: BTreeMap<String, usize>,
+    notices: usize,
+    unanswered: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    concurrency: usize,
+    ok_timeout: Duration,
+}
+
+impl Limits {
+    // How long a connection may sit idle. The source is idle while destinations post
+    // a page, and destinations are idle while the source fetches one, so this must
+    // outlast posting a whole page to a destination that never answers.
+    fn idle_timeout(&self, page_size: usize) -> Duration {
+        let rounds = page_size.div_ceil(self.concurrency.max(1)) as u32;
+        self.ok_timeout * (rounds + 1)
+    }
+}
+
+// How many events to ask the source for at a time, if the filter doesn't say
+const PAGE_SIZE: usize = 500;
+
+// What became of one page of events at a destination
+#[derive(Debug, Default)]
+struct Posted {
+    // Events the destination accepted or already had
+    landed: HashSet<Id>,
+    // Events it never answered
+    unanswered: usize,
+}
+
+struct Destination {
+    url: String,
+    to_probe: Sender<Command>,
+    from_probe: Receiver<RelayMessage>,
+    stats: Stats,
+    alive: bool,
+}
+
+impl Destination {
+    fn connect(url: &str, idle_timeout: Duration) -> (Destination, tokio::task::JoinHandle<()>) {
+        let (to_probe, from_main) = tokio::sync::mpsc::channel::<Command>(100);
+        let (to_main, from_probe) = tokio::sync::mpsc::channel::<RelayMessage>(100);
+        let join_handle = spawn_probe(url, idle_timeout, from_main, to_main);
+        let destination = Destination {
+            url: url.to_owned(),
+            to_probe,
+            from_probe,
+            stats: Stats::default(),
+            alive: true,
+        };
+        (destination, join_handle)
+    }
+
+    // Post events keeping at most `concurrency` of them waiting on an OK. An event
+    // whose OK doesn't come within `ok_timeout` is given up on.
+    async fn post(&mut self, events: &[Event], limits: Limits) -> Posted {
+        let mut posted = Posted::default();
+        if !self.alive {
+            self.stats.unanswered += events.len();
+            posted.unanswered = events.len();
+            return posted;
+        }
+
+        // Each event waiting on an OK, with when we stop waiting
+        let mut in_flight: HashMap<Id, Instant> = HashMap::new();
+        let mut queue = events.iter();
+
+        loop {
+            while self.alive && in_flight.len() < limits.concurrency {
+                match queue.next() {
+                    Some(event) => {
+                        in_flight.insert(event.id, Instant::now() + limits.ok_timeout);
+                        let command = Command::PostEvent(event.clone());
+                        if self.to_probe.send(command).await.is_err() {
+                            // The probe has exited; we find out below
+                            break;
+                        }
+                    }
+                    None => break,
+                }
+            }
+            if in_flight.is_empty() {
+                break;
+            }
+
+            let deadline = *in_flight.values().min().unwrap();
+            let message = match tokio::time::timeout_at(deadline, self.from_probe.recv()).await {
+                Ok(message) => message,
+                Err(_) => {
+                    let now = Instant::now();
+                    let before = in_flight.len();
+                    in_flight.retain(|_, d| *d > now);
+                    let expired = before - in_flight.len();
+                    eprintln!(
+                        "{}: no OK for {} events, giving up on them",
+                        self.url, expired
+                    );
+                    self.stats.unanswered += expired;
+                    posted.unanswered += expired;
+                    continue;
+                }
+            };
+            match message {
+                Some(RelayMessage::Ok(id, ok, reason)) => {
+                    if in_flight.remove(&id).is_some() && self.record(ok, &reason) {
+                        posted.landed.insert(id);
+                    }
+                }
+                Some(RelayMessage::Notice(_)) => self.stats.notices += 1,
+                Some(_) => {}
+                None => {
+                    eprintln!("{} has disconnected", self.url);
+                    self.alive = false;
+                    let lost = in_flight.len() + queue.len();
+                    self.stats.unanswered += lost;
+                    posted.unanswered += lost;
+                    in_flight.clear();
+                }
+            }
+        }
+
+        posted
+    }
+
+    // Count an OK, returning whether the destination now has the event
+    fn record(&mut self, ok: bool, reason: &str) -> bool {
+        let prefix = nostr_probe::reason_prefix(reason);
+        if prefix == Some("duplicate") {
+            self.stats.duplicate += 1;
+            true
+        } else if ok {
+            self.stats.accepted += 1;
+            true
+        } else {
+            let key = prefix.unwrap_or("(no reason)").to_owned();
+            *self.stats.rejected.entry(key).or_insert(0) += 1;
+            false
+        }
+    }
+}
+
+fn spawn_probe(
+    url: &str,
+    idle_timeout: Duration,
+    from_main: Receiver<Command>,
+    to_main: Sender<RelayMessage>,
+) -> tokio::task::JoinHandle<()> {
+    let relay_url = url.to_owned();
+    tokio::spawn(async move {
+        let mut probe = Probe::new(from_main, to_main);
+        probe.idle_timeout = Some(idle_timeout);
+        if let Err(e) = probe.connect_and_listen(&relay_url).await {
+            eprintln!("{}", e);
+        }
+    })
+}
+
+fn load_checkpoint(path: &Path) -> Result<Option<Checkpoint>, Box<dyn std::error::Error>> {
+    match std::fs::read_to_string(path) {
+        Ok(s) => Ok(Some(serde_json::from_str(&s)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+fn save_checkpoint(path: &Path, checkpoint: &Checkpoint) -> Result<(), Box<dyn std::error::Error>> {
+    // Write then rename so an interrupted write never leaves a corrupt checkpoint
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, serde_json::to_string_pretty(checkpoint)?)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+// What the whole copy came to
+struct Report {
+    // Events that failed verification and were not copied
+    invalid: usize,
+    // Events that every destination up at the time accepted or already had
+    copied: usize,
+    destinations: Vec<(String, Stats)>,
+}
+
+async fn mirror(args: &Args) -> Result<Report, Box<dyn std::error::Error>> {
+    let mut filter: Filter = serde_json::from_str(&args.filter)?;
+    let mut copied: usize = 0;
+
+    if let Some(path) = &args.checkpoint {
+        if let Some(checkpoint) = load_checkpoint(path)? {
+            let same_filter =
+                serde_json::to_string(&checkpoint.filter)? == serde_json::to_string(&filter)?;
+            if checkpoint.from != args.from || !same_filter {
+                return Err(Box::new(std::io::Error::other(format!(
+                    "Checkpoint {} was for a different source or filter",
+                    path.display()
+                ))));
+            }
+            eprintln!(
+                "Resuming from checkpoint: {} copied, until={:?}",
+                checkpoint.copied,
+                checkpoint.until.map(|u| u.0)
+            );
+            filter.until = checkpoint.until;
+            copied = checkpoint.copied;
+        }
+    }
+
+    let limits = Limits {
+        concurrency: args.concurrency,
+        ok_timeout: Duration::from_secs(args.ok_timeout),
+    };
+    let original_filter: Filter = serde_json::from_str(&args.filter)?;
+
+    // Bounded pages bound how long posting one can take
+    let page_size = *filter.limit.get_or_insert(PAGE_SIZE);
+    let idle_timeout = limits.idle_timeout(page_size);
+
+    let (to_probe, from_main) = tokio::sync::mpsc::channel::<Command>(100);
+    let (to_main, mut from_probe) = tokio::sync::mpsc::channel::<RelayMessage>(100);
+    let source_handle = spawn_probe(&args.from, idle_timeout, from_main, to_main);
+
+    let mut destinations: Vec<Destination> = Vec::new();
+    let mut destination_handles = Vec::new();
+    for url in &args.to {
+        let (destination, join_handle) = Destination::connect(url, idle_timeout);
+        destinations.push(destination);
+        destination_handles.push(join_handle);
+    }
+
+    let mut invalid: usize = 0;
+    // Once a page doesn't fully land, the checkpoint stays before it so that a resume
+    // copies it again
+    let mut held = false;
+    let mut saved_until = filter.until;
+    let mut pager = Pager::new("mirror", filter);
+    while let Some(events) = pager.next_page(&to_probe, &mut from_probe).await? {
+        // Skip anything that does not verify rather than ask relays to reject it
+        let fetched = events.len();
+        let events: Vec<Event> = events
+            .into_iter()
+            .filter(|e| e.verify(None).is_ok())
+            .collect();
+        invalid += fetched - events.len();
+
+        let was_alive: Vec<bool> = destinations.iter().map(|d| d.alive).collect();
+        let posted = join_all(destinations.iter_mut().map(|d| d.post(&events, limits))).await;
+
+        // Destinations that were already gone don't hold up the others
+        let up: Vec<&Posted> = posted
+            .iter()
+            .zip(&was_alive)
+            .filter(|(_, alive)| **alive)
+            .map(|(posted, _)| posted)
+            .collect();
+        if !up.is_empty() {
+            copied += events
+                .iter()
+                .filter(|e| up.iter().all(|p| p.landed.contains(&e.id)))
+                .count();
+        }
+
+        let answered = !up.is_empty() && up.iter().all(|p| p.unanswered == 0);
+        if let Some(path) = &args.checkpoint {
+            if !answered && !held {
+                eprintln!(
+                    "Some events went unanswered; the checkpoint stays at until={:?} so that \
+                     resuming copies them again",
+                    saved_until.map(|u| u.0)
+                );
+            }
+            held |= !answered;
+            if !held {
+                saved_until = pager.filter().until;
+                let checkpoint = Checkpoint {
+                    from: args.from.clone(),
+                    filter: original_filter.clone(),
+                    until: saved_until,
+                    copied,
+                };
+                save_checkpoint(path, &checkpoint)?;
+            }
+        }
+        eprintln!("{} events copied", copied);
+
+        if destinations.iter().all(|d| !d.alive) {
+            eprintln!("All destinations have disconnected");
+            break;
+        }
+    }
+
+    let _ = to_probe.send(Command::Exit).await;
+    source_handle.await?;
+
+    for destination in &destinations {
+        let _ = destination.to_probe.send(Command::Exit).await;
+    }
+    for join_handle in destination_handles {
+        join_handle.await?;
+    }
+
+    Ok(Report {
+        invalid,
+        copied,
+        destinations: destinations.into_iter().map(|d| (d.url, d.stats)).collect(),
+    })
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let report = mirror(&args).await?;
+
+    if report.invalid > 0 {
+        println!(
+            "{} events failed verification and were not copied",
+            report.invalid
+        );
+    }
+    println!("{} events copied to every destination", report.copied);
+    for (url, stats) in &report.destinations {
+        println!("{}", url);
+        println!("  accepted:   {}", stats.accepted);
+        println!("  duplicate:  {}", stats.duplicate);
+        for (reason, count) in &stats.rejected {
+            println!("  rejected ({}): {}", reason, count);
+        }
+        println!("  notices:    {}", stats.notices);
+        println!("  unanswered: {}", stats.unanswered);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_probe::conformance::Author;
+    use nostr_probe::mock_relay::{Behaviour, MockRelay};
+
+    fn args(from: &MockRelay, to: &MockRelay, checkpoint: &Path, more: &[&str]) -> Args {
+        let mut argv = vec![
+            "mirror",
+            "--from",
+            from.url.as_str(),
+            "--to",
+            to.url.as_str(),
+        ];
+        argv.extend(["--checkpoint", checkpoint.to_str().unwrap()]);
+        argv.extend(more);
+        Args::parse_from(argv)
+    }
+
+    // Notes a second apart, so that paging has to walk back through them
+    fn notes(n: i64) -> Vec<Event> {
+        let author = Author::new();
+        (0..n)
+            .map(|i| author.sign(1, 1_700_000_000 + i, vec![], &format!("note {}", i)))
+            .collect()
+    }
+
+    fn temp_checkpoint() -> PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push(format!(
+            "nostr-probe-mirror-test-{}.json",
+            hex::encode(rand::random::<[u8; 8]>())
+        ));
+        path
+    }
+
+    #[tokio::test]
+    async fn pages_through_to_a_slow_destination() {
+        let source = MockRelay::start(notes(12), Behaviour::default())
+            .await
+            .unwrap();
+        let slow = Behaviour {
+            ok_delay: Duration::from_millis(300),
+            ..Default::default()
+        };
+        let destination = MockRelay::start(vec![], slow).await.unwrap();
+        let path = temp_checkpoint();
+
+        let more = [
+            "--filter",
+            r#"{"limit":5}"#,
+            "--concurrency",
+            "2",
+            "--ok-timeout",
+            "2",
+        ];
+        let report = mirror(&args(&source, &destination, &path, &more))
+            .await
+            .unwrap();
+
+        assert_eq!(report.copied, 12);
+        assert_eq!(destination.events().len(), 12);
+        let stats = &report.destinations[0].1;
+        assert_eq!((stats.accepted, stats.unanswered), (12, 0));
+
+        let checkpoint = load_checkpoint(&path).unwrap().unwrap();
+        assert_eq!(checkpoint.copied, 12);
+        assert!(checkpoint.until.is_some());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unanswered_events_hold_the_checkpoint() {
+        let source = MockRelay::start(notes(3), Behaviour::default())
+            .await
+            .unwrap();
+        let silent = Behaviour {
+            silent: true,
+            ..Default::default()
+        };
+        let destination = MockRelay::start(vec![], silent).await.unwrap();
+        let path = temp_checkpoint();
+
+        let report = mirror(&args(&source, &destination, &path, &["--ok-timeout", "1"]))
+            .await
+            .unwrap();
+
+        assert_eq!(report.copied, 0);
+        assert_eq!(report.destinations[0].1.unanswered, 3);
+        assert!(load_checkpoint(&path).unwrap().is_none());
+    }
+}
@@ -0,0 +1,415 @@
+use clap::{ArgGroup, Parser};
+use nostr_probe::filter::FilterMatcher;
+use nostr_probe::negentropy::{self, NegMessage};
+use nostr_probe::{Command, Pager, Probe};
+use nostr_types::{Event, Filter, Id, RelayMessage, SubscriptionId};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::time::{Duration, Instant};
+
+// How many ids to put in one filter when downloading
+const IDS_PER_FILTER: usize = 500;
+
+// How long to wait for a relay's OK to an event we post
+const OK_TIMEOUT_SECS: u64 = 10;
+
+/// Compare a relay against a local JSONL archive or another relay using NIP-77
+/// negentropy, optionally copying the differences across.
+///
+/// Relays that do not support negentropy are compared by downloading everything
+/// matching the filter instead.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+#[command(group(ArgGroup::new("local").required(true).args(["file", "other"])))]
+struct Args {
+    /// The relay to reconcile against
+    relay: String,
+
+    /// Local JSONL archive to compare with the relay
+    #[arg(long)]
+    file: Option<PathBuf>,
+
+    /// Another relay to compare with the relay
+    #[arg(long)]
+    other: Option<String>,
+
+    /// Filter JSON restricting which events are compared
+    #[arg(long, default_value = "{}")]
+    filter: String,
+
+    /// Copy events the relay has into the local side (appending to the file, or
+    /// posting to the other relay)
+    #[arg(long)]
+    download: bool,
+
+    /// Copy events the local side has into the relay
+    #[arg(long)]
+    upload: bool,
+
+    /// Print the differing ids
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+#[derive(Debug, Default)]
+struct Posted {
+    accepted: usize,
+    rejected: usize,
+    // Events whose OK never came, or that we couldn't send
+    unanswered: usize,
+}
+
+struct Connection {
+    url: String,
+    to_probe: Sender<Command>,
+    from_probe: Receiver<RelayMessage>,
+    from_probe_neg: Receiver<NegMessage>,
+    join_handle: tokio::task::JoinHandle<()>,
+}
+
+impl Connection {
+    fn open(url: &str) -> Connection {
+        let (to_probe, from_main) = tokio::sync::mpsc::channel::<Command>(100);
+        let (to_main, from_probe) = tokio::sync::mpsc::channel::<RelayMessage>(100);
+        let (to_main_neg, from_probe_neg) = tokio::sync::mpsc::channel::<NegMessage>(100);
+        let relay_url = url.to_owned();
+        let join_handle = tokio::spawn(async move {
+            let mut probe = Probe::new(from_main, to_main);
+            probe.to_main_neg = Some(to_main_neg);
+            if let Err(e) = probe.connect_and_listen(&relay_url).await {
+                eprintln!("{}", e);
+            }
+        });
+        Connection {
+            url: url.to_owned(),
+            to_probe,
+            from_probe,
+            from_probe_neg,
+            join_handle,
+        }
+    }
+
+    async fn close(self) -> Result<(), Box<dyn std::error::Error>> {
+        let _ = self.to_probe.send(Command::Exit).await;
+        Ok(self.join_handle.await?)
+    }
+
+    // The ids (and events, if we had to fetch them) the relay has, given what we have
+    async fn diff(
+        &mut self,
+        filter: &Filter,
+        ours: &HashMap<Id, u64>,
+    ) -> Result<(Vec<Id>, Vec<Id>, HashMap<Id, Event>), Box<dyn std::error::Error>> {
+        let items = ours.iter().map(|(id, t)| (*t, *id));
+        let outcome = negentropy::reconcile(
+            SubscriptionId("negentropy".to_owned()),
+            filter.clone(),
+            items,
+            &self.to_probe,
+            &mut self.from_probe,
+            &mut self.from_probe_neg,
+            15,
+        )
+        .await?;
+
+        match outcome {
+            Ok(r) => {
+                eprintln!("{}: reconciled with negentropy", self.url);
+                Ok((r.have, r.need, HashMap::new()))
+            }
+            Err(reason) => {
+                eprintln!(
+                    "{}: negentropy unavailable ({}), falling back to a plain diff",
+                    self.url, reason
+                );
+                let theirs = self.fetch_matching(filter).await?;
+                let have = ours
+                    .keys()
+                    .filter(|id| !theirs.contains_key(*id))
+                    .copied()
+                    .collect();
+                let need = theirs
+                    .keys()
+                    .filter(|id| !ours.contains_key(*id))
+                    .copied()
+                    .collect();
+                Ok((have, need, theirs))
+            }
+        }
+    }
+
+    // Fetch every event matching the filter
+    async fn fetch_matching(
+        &mut self,
+        filter: &Filter,
+    ) -> Result<HashMap<Id, Event>, Box<dyn std::error::Error>> {
+        let mut events: HashMap<Id, Event> = HashMap::new();
+        let mut pager = Pager::new("negentropy-fetch-all", filter.clone());
+        while let Some(page) = pager
+            .next_page(&self.to_probe, &mut self.from_probe)
+            .await?
+        {
+            for event in page {
+                events.insert(event.id, event);
+            }
+        }
+        Ok(events)
+    }
+
+    // Fetch events by id. A CLOSED, NOTICE or disconnect fails the fetch rather than
+    // leave it looking complete.
+    async fn fetch(&mut self, ids: &[Id]) -> Result<Vec<Event>, Box<dyn std::error::Error>> {
+        let mut events: Vec<Event> = Vec::new();
+        for (n, chunk) in ids.chunks(IDS_PER_FILTER).enumerate() {
+            let mut filter = Filter::new();
+            for id in chunk {
+                filter.add_id(*id);
+            }
+            let our_sub_id = SubscriptionId(format!("negentropy-fetch-{}", n));
+            self.to_probe
+                .send(Command::FetchEvents(our_sub_id.clone(), vec![filter]))
+                .await?;
+            loop {
+                match self.from_probe.recv().await {
+                    Some(RelayMessage::Eose(sub)) if sub == our_sub_id => {
+                        self.to_probe.send(Command::Close(our_sub_id)).await?;
+                        break;
+                    }
+                    Some(RelayMessage::Closed(sub, reason)) if sub == our_sub_id => {
+                        return Err(Box::new(std::io::Error::other(format!(
+                            "{}: CLOSED: {}",
+                            self.url, reason
+                        ))));
+                    }
+                    Some(RelayMessage::Notice(notice)) => {
+                        return Err(Box::new(std::io::Error::other(format!(
+                            "{}: NOTICE: {}",
+                            self.url, notice
+                        ))));
+                    }
+                    Some(RelayMessage::Event(sub, e)) => {
+                        if sub == our_sub_id && e.verify(None).is_ok() {
+                            events.push(*e);
+                        }
+                    }
+                    Some(_) => {}
+                    None => {
+                        return Err(Box::new(std::io::Error::other(format!(
+                            "{}: the connection ended before the fetch did",
+                            self.url
+                        ))));
+                    }
+                }
+            }
+        }
+        Ok(events)
+    }
+
+    // Post events one at a time, giving up on an OK after OK_TIMEOUT_SECS. If the
+    // relay goes away, the events not yet answered are counted as unanswered.
+    async fn post(&mut self, events: &[Event]) -> Posted {
+        let mut posted = Posted::default();
+        for (n, event) in events.iter().enumerate() {
+            let command = Command::PostEvent(event.clone());
+            if self.to_probe.send(command).await.is_err() {
+                eprintln!("{} has disconnected", self.url);
+                posted.unanswered += events.len() - n;
+                return posted;
+            }
+            let deadline = Instant::now() + Duration::from_secs(OK_TIMEOUT_SECS);
+            loop {
+                match tokio::time::timeout_at(deadline, self.from_probe.recv()).await {
+                    Ok(Some(RelayMessage::Ok(id, ok, _))) if id == event.id => {
+                        if ok {
+                            posted.accepted += 1;
+                        } else {
+                            posted.rejected += 1;
+                        }
+                        break;
+                    }
+                    Ok(Some(_)) => {}
+                    Ok(None) => {
+                        eprintln!("{} has disconnected", self.url);
+                        posted.unanswered += events.len() - n;
+                        return posted;
+                    }
+                    Err(_) => {
+                        eprintln!(
+                            "{}: no OK for {} within {} seconds",
+                            self.url,
+                            event.id.as_hex_string(),
+                            OK_TIMEOUT_SECS
+                        );
+                        posted.unanswered += 1;
+                        break;
+                    }
+                }
+            }
+        }
+        posted
+    }
+}
+
+// Negentropy orders by created_at as a u64, so events from before 1970 can't take part
+fn timestamps<'a>(events: impl Iterator<Item = &'a Event>) -> HashMap<Id, u64> {
+    let mut timestamps: HashMap<Id, u64> = HashMap::new();
+    let mut negative: usize = 0;
+    for event in events {
+        match u64::try_from(event.created_at.0) {
+            Ok(t) => {
+                timestamps.insert(event.id, t);
+            }
+            Err(_) => negative += 1,
+        }
+    }
+    if negative > 0 {
+        eprintln!("Leaving out {} events dated before 1970", negative);
+    }
+    timestamps
+}
+
+fn print_ids(label: &str, ids: &[Id], verbose: bool) {
+    println!("{}: {}", label, ids.len());
+    if verbose {
+        for id in ids {
+            println!("  {}", id.as_hex_string());
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let filter: Filter = serde_json::from_str(&args.filter)?;
+    let matcher = FilterMatcher::new(&filter);
+
+    if let Some(path) = &args.file {
+        // Load the local archive, keeping only what the filter selects
+        let mut local: HashMap<Id, Event> = HashMap::new();
+        let mut bad_lines: usize = 0;
+        if path.exists() {
+            let file = std::fs::File::open(path)?;
+            for line in BufReader::new(file).split(b'\n') {
+                let line = line?;
+                if line.iter().all(|b| b.is_ascii_whitespace()) {
+                    continue;
+                }
+                match serde_json::from_slice::<Event>(&line) {
+                    Ok(event) => {
+                        if matcher.matches(&event) {
+                            local.insert(event.id, event);
+                        }
+                    }
+                    Err(_) => bad_lines += 1,
+                }
+            }
+        }
+        if bad_lines > 0 {
+            eprintln!(
+                "Skipped {} lines of {} that are not events",
+                bad_lines,
+                path.display()
+            );
+        }
+        let ours = timestamps(local.values());
+
+        let mut relay = Connection::open(&args.relay);
+
+        let (have, need, fetched) = relay.diff(&filter, &ours).await?;
+        print_ids("Only in file", &have, args.verbose);
+        print_ids("Only on relay", &need, args.verbose);
+
+        if args.download && !need.is_empty() {
+            let events: Vec<Event> = if fetched.is_empty() {
+                relay.fetch(&need).await?
+            } else {
+                need.iter()
+                    .filter_map(|id| fetched.get(id).cloned())
+                    .collect()
+            };
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?;
+            for event in &events {
+                writeln!(file, "{}", serde_json::to_string(event)?)?;
+            }
+            println!("Downloaded {} events into {}", events.len(), path.display());
+        }
+
+        if args.upload && !have.is_empty() {
+            let events: Vec<Event> = have
+                .iter()
+                .filter_map(|id| local.get(id).cloned())
+                .collect();
+            let posted = relay.post(&events).await;
+            println!(
+                "Uploaded {} events ({} rejected, {} unanswered)",
+                posted.accepted, posted.rejected, posted.unanswered
+            );
+        }
+
+        relay.close().await
+    } else if let Some(other_url) = &args.other {
+        // Negentropy needs the created_at of every id on our side, so download what
+        // the other relay has and reconcile that against the relay. Only the relay's
+        // differences then need to come over the wire. Each connection is only
+        // opened when needed, so it doesn't sit idle until the relay drops it.
+        let mut other = Connection::open(other_url);
+        let other_events = other.fetch_matching(&filter).await?;
+        other.close().await?;
+        eprintln!("{}: {} events", other_url, other_events.len());
+        let ours = timestamps(other_events.values());
+
+        let mut relay = Connection::open(&args.relay);
+        let (only_other, only_relay, relay_fetched) = relay.diff(&filter, &ours).await?;
+        print_ids(
+            &format!("Only on {}", args.relay),
+            &only_relay,
+            args.verbose,
+        );
+        print_ids(&format!("Only on {}", other_url), &only_other, args.verbose);
+
+        if args.upload && !only_other.is_empty() {
+            let events: Vec<Event> = only_other
+                .iter()
+                .filter_map(|id| other_events.get(id).cloned())
+                .collect();
+            let posted = relay.post(&events).await;
+            println!(
+                "Copied {} events to {} ({} rejected, {} unanswered)",
+                posted.accepted, args.relay, posted.rejected, posted.unanswered
+            );
+        }
+
+        let mut downloads: Vec<Event> = Vec::new();
+        if args.download && !only_relay.is_empty() {
+            downloads = if relay_fetched.is_empty() {
+                relay.fetch(&only_relay).await?
+            } else {
+                only_relay
+                    .iter()
+                    .filter_map(|id| relay_fetched.get(id).cloned())
+                    .collect()
+            };
+        }
+        relay.close().await?;
+
+        if !downloads.is_empty() {
+            let mut other = Connection::open(other_url);
+            let posted = other.post(&downloads).await;
+            other.close().await?;
+            println!(
+                "Copied {} events to {} ({} rejected, {} unanswered)",
+                posted.accepted, other_url, posted.rejected, posted.unanswered
+            );
+        }
+
+        Ok(())
+    } else {
+        unreachable!("clap requires --file or --other")
+    }
+}
@@ -0,0 +1,78 @@
+use clap::Parser;
+use nostr_probe::store::Store;
+use nostr_probe::{Command, Probe};
+use nostr_types::{Filter, RelayMessage};
+use std::path::PathBuf;
+
+/// Post events from the local archive that match a filter to a relay
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Relay to post to
+    relay_url: String,
+
+    /// Filter JSON selecting which events to post
+    filter: String,
+
+    /// Archive directory
+    #[arg(short, long)]
+    archive: Option<PathBuf>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let filter: Filter = serde_json::from_str(&args.filter)?;
+    let dir = args.archive.unwrap_or_else(Store::default_path);
+    let store = Store::open(&dir)?;
+
+    // Oldest first, so that replaceable events end up at their newest version
+    let mut events = store.query(&[filter])?;
+    events.reverse();
+
+    let (to_probe, from_main) = tokio::sync::mpsc::channel::<Command>(100);
+    let (to_main, mut from_probe) = tokio::sync::mpsc::channel::<RelayMessage>(100);
+    let relay_url = args.relay_url.clone();
+    let join_handle = tokio::spawn(async move {
+        let mut probe = Probe::new(from_main, to_main);
+        if let Err(e) = probe.connect_and_listen(&relay_url).await {
+            eprintln!("{}", e);
+        }
+    });
+
+    let mut accepted: usize = 0;
+    let mut rejected: usize = 0;
+    'events: for event in &events {
+        to_probe.send(Command::PostEvent(event.clone())).await?;
+
+        // Wait for OK
+        loop {
+            match from_probe.recv().await {
+                Some(RelayMessage::Ok(id, ok, _)) => {
+                    if id == event.id {
+                        if ok {
+                            accepted += 1;
+                        } else {
+                            rejected += 1;
+                        }
+                        break;
+                    }
+                }
+                Some(_) => {}
+                None => break 'events,
+            }
+        }
+    }
+
+    let _ = to_probe.send(Command::Exit).await;
+
+    eprintln!(
+        "{} of {} events accepted, {} rejected",
+        accepted,
+        events.len(),
+        rejected
+    );
+
+    Ok(join_handle.await?)
+}
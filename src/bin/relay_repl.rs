@@ -0,0 +1,161 @@
+//! Talk to a relay interactively, sending lines of JSON as typed and printing what
+//! comes back.
+//!
+//! Lines sent are kept as a history across sessions, and can be listed and sent
+//! again with `!!` and `!N`. There is no line editing or arrow-key recall; run it
+//! under a wrapper such as `rlwrap relay_repl <RelayURL>` for that.
+
+use nostr_probe::{Command, Probe};
+use nostr_types::RelayMessage;
+use std::env;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+
+const HELP: &str = "\
+Type relay messages as JSON, e.g. [\"REQ\",\"sub1\",{\"kinds\":[1],\"limit\":2}]
+They are sent exactly as typed. Replies from the relay are printed unparsed.
+  !!          send the previous line again
+  !N          send line N of the history again
+  :history    show the history
+  :help       show this help
+  :quit       disconnect (as does end of input)";
+
+fn history_path() -> Option<PathBuf> {
+    let mut path = dirs::data_dir()?;
+    path.push("nostr-probe");
+    std::fs::create_dir_all(&path).ok()?;
+    path.push("repl_history");
+    Some(path)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = env::args();
+    let _ = args.next(); // program name
+    let relay_url = match args.next() {
+        Some(u) => u,
+        None => panic!("Usage: relay_repl <RelayURL>"),
+    };
+
+    // Load the history, and keep appending to it
+    let history_path = history_path();
+    let mut history: Vec<String> = match &history_path {
+        Some(path) => std::fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .map(|l| l.to_owned())
+            .collect(),
+        None => Vec::new(),
+    };
+    let mut history_file = match &history_path {
+        Some(path) => std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .ok(),
+        None => None,
+    };
+
+    let (to_probe, from_main) = tokio::sync::mpsc::channel::<Command>(100);
+    let (to_main, _from_probe) = tokio::sync::mpsc::channel::<RelayMessage>(100);
+    let (to_main_raw, mut from_probe_raw) = tokio::sync::mpsc::channel::<String>(100);
+    let join_handle = tokio::spawn(async move {
+        let mut probe = Probe::new(from_main, to_main);
+        probe.to_main_raw = Some(to_main_raw);
+        // Stay connected while the user is thinking
+        probe
+            .connect_and_listen_with_timeout(&relay_url, 24 * 60 * 60)
+            .await
+            .map_err(|e| e.to_string())
+    });
+
+    // Read stdin on its own thread, since it blocks
+    let (to_main_line, mut from_stdin) = tokio::sync::mpsc::channel::<String>(100);
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            match line {
+                Ok(line) => {
+                    if to_main_line.blocking_send(line).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    });
+
+    eprintln!("{}", HELP);
+
+    loop {
+        tokio::select! {
+            line = from_stdin.recv() => {
+                let line = match line {
+                    Some(l) => l.trim().to_owned(),
+                    None => break,
+                };
+                let wire = match line.as_str() {
+                    "" => continue,
+                    ":quit" => break,
+                    ":help" => {
+                        eprintln!("{}", HELP);
+                        continue;
+                    }
+                    ":history" => {
+                        for (n, h) in history.iter().enumerate() {
+                            println!("{:>4}  {}", n + 1, h);
+                        }
+                        continue;
+                    }
+                    "!!" => match history.last() {
+                        Some(h) => h.clone(),
+                        None => {
+                            eprintln!("History is empty");
+                            continue;
+                        }
+                    },
+                    l if l.starts_with('!') => {
+                        let entry = l[1..]
+                            .parse::<usize>()
+                            .ok()
+                            .and_then(|n| history.get(n.wrapping_sub(1)));
+                        match entry {
+                            Some(h) => h.clone(),
+                            None => {
+                                eprintln!("No such history entry: {}", l);
+                                continue;
+                            }
+                        }
+                    }
+                    l => l.to_owned(),
+                };
+
+                if serde_json::from_str::<serde_json::Value>(&wire).is_err() {
+                    eprintln!("(not valid JSON, sending anyway)");
+                }
+                if history.last() != Some(&wire) {
+                    if let Some(file) = &mut history_file {
+                        let _ = writeln!(file, "{}", wire);
+                    }
+                    history.push(wire.clone());
+                }
+                if to_probe.send(Command::Raw(wire)).await.is_err() {
+                    break;
+                }
+            },
+            frame = from_probe_raw.recv() => {
+                match frame {
+                    Some(frame) => println!("{}", frame),
+                    None => {
+                        eprintln!("Connection closed");
+                        break;
+                    }
+                }
+            },
+        }
+    }
+
+    let _ = to_probe.send(Command::Exit).await;
+
+    // The stdin thread may still be blocked on a read, but returning ends it
+    join_handle.await?.map_err(|e| e.into())
+}
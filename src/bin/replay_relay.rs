@@ -0,0 +1,152 @@
+use clap::Parser;
+use futures_util::{SinkExt, StreamExt};
+use nostr_probe::recording::{self, Exchange};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tungstenite::Message;
+
+/// A relay that answers clients with the responses recorded in a session file.
+///
+/// Each incoming message is matched to a recorded request, first exactly and then
+/// ignoring the subscription id (which is then rewritten in the responses).
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Session file recorded with NOSTR_PROBE_RECORD
+    session: PathBuf,
+
+    /// Address to listen on
+    #[arg(short, long, default_value = "127.0.0.1:7777")]
+    listen: String,
+
+    /// Which recorded connection to serve (defaults to the first)
+    #[arg(short, long)]
+    connection: Option<String>,
+
+    /// Reproduce the recorded delays between a request and its responses
+    #[arg(short, long)]
+    timing: bool,
+}
+
+// Find the recorded exchange for a message, preferring ones not yet used
+fn find(exchanges: &[Exchange], used: &[bool], text: &str) -> Option<(usize, bool)> {
+    let stripped = recording::without_sub_id(text);
+    let exact = |e: &Exchange| e.request.as_deref() == Some(text);
+    let similar = |e: &Exchange| {
+        stripped.is_some() && e.request.as_deref().and_then(recording::without_sub_id) == stripped
+    };
+    let unused = |i: &usize| !used[*i];
+    let indices = || 0..exchanges.len();
+
+    if let Some(i) = indices().filter(unused).find(|i| exact(&exchanges[*i])) {
+        return Some((i, true));
+    }
+    if let Some(i) = indices().filter(unused).find(|i| similar(&exchanges[*i])) {
+        return Some((i, false));
+    }
+    if let Some(i) = indices().find(|i| exact(&exchanges[*i])) {
+        return Some((i, true));
+    }
+    indices()
+        .find(|i| similar(&exchanges[*i]))
+        .map(|i| (i, false))
+}
+
+async fn serve(
+    stream: tokio::net::TcpStream,
+    exchanges: Arc<Vec<Exchange>>,
+    timing: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut websocket = tokio_tungstenite::accept_async(stream).await?;
+    let mut used: Vec<bool> = vec![false; exchanges.len()];
+
+    // Anything the relay said before the client did, such as an AUTH challenge
+    if let Some(first) = exchanges.first() {
+        if first.request.is_none() {
+            used[0] = true;
+            for (_, response) in &first.responses {
+                websocket.send(Message::Text(response.clone())).await?;
+            }
+        }
+    }
+
+    while let Some(message) = websocket.next().await {
+        let text = match message? {
+            Message::Text(s) => s,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        eprintln!("Client: {}", text);
+
+        let (index, exact) = match find(&exchanges, &used, &text) {
+            Some(found) => found,
+            None => {
+                let notice = serde_json::json!(["NOTICE", "replay: no recorded response"]);
+                websocket.send(Message::Text(notice.to_string())).await?;
+                continue;
+            }
+        };
+        used[index] = true;
+        let exchange = &exchanges[index];
+
+        // Answer on the client's subscription id if it used a different one
+        let rewrite = match (
+            exact,
+            exchange.request.as_deref().and_then(recording::sub_id),
+            recording::sub_id(&text),
+        ) {
+            (false, Some(from), Some(to)) if from != to => Some((from, to)),
+            _ => None,
+        };
+
+        let mut elapsed: u64 = 0;
+        for (delay, response) in &exchange.responses {
+            if timing && *delay > elapsed {
+                tokio::time::sleep(Duration::from_millis(delay - elapsed)).await;
+                elapsed = *delay;
+            }
+            let response = match &rewrite {
+                Some((from, to)) => recording::rewrite_sub_id(response, from, to),
+                None => response.clone(),
+            };
+            websocket.send(Message::Text(response)).await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let frames = recording::load(&args.session)?;
+    let connection = match &args.connection {
+        Some(c) => c.clone(),
+        None => match recording::connections(&frames).into_iter().next() {
+            Some(c) => c,
+            None => return Err("The session file has no frames".into()),
+        },
+    };
+    let exchanges = Arc::new(recording::exchanges(&frames, &connection));
+    eprintln!(
+        "Serving {} recorded exchanges from connection {} on ws://{}",
+        exchanges.len(),
+        connection,
+        args.listen
+    );
+
+    let listener = tokio::net::TcpListener::bind(&args.listen).await?;
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        eprintln!("Connection from {}", peer);
+        let exchanges = exchanges.clone();
+        let timing = args.timing;
+        tokio::spawn(async move {
+            if let Err(e) = serve(stream, exchanges, timing).await {
+                eprintln!("{}: {}", peer, e);
+            }
+        });
+    }
+}
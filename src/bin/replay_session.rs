@@ -0,0 +1,136 @@
+use clap::Parser;
+use futures_util::{SinkExt, StreamExt};
+use nostr_probe::recording;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use tungstenite::Message;
+
+/// Re-send the requests recorded in a session file to a relay and show how its
+/// responses differ from the recorded ones
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Session file recorded with NOSTR_PROBE_RECORD
+    session: PathBuf,
+
+    /// The relay to replay the requests to
+    relay: String,
+
+    /// Which recorded connection to replay (defaults to the first)
+    #[arg(short, long)]
+    connection: Option<String>,
+
+    /// How long to wait for more responses once the relay goes quiet, in milliseconds
+    #[arg(short, long, default_value_t = 2000)]
+    quiet_ms: u64,
+}
+
+// Whether a response finishes the given request, so we needn't wait any longer
+fn finishes(request: &str, response: &str) -> bool {
+    let request: serde_json::Value = serde_json::from_str(request).unwrap_or_default();
+    let response: serde_json::Value = serde_json::from_str(response).unwrap_or_default();
+    let verb = request[0].as_str().unwrap_or("");
+    let reply = response[0].as_str().unwrap_or("");
+    match verb {
+        "REQ" => (reply == "EOSE" || reply == "CLOSED") && response[1] == request[1],
+        "COUNT" => (reply == "COUNT" || reply == "CLOSED") && response[1] == request[1],
+        "EVENT" | "AUTH" => reply == "OK" && response[1] == request[1]["id"],
+        _ => false,
+    }
+}
+
+// The lines only in `a`, treating both as multisets
+fn only_in(a: &[String], b: &[String]) -> Vec<String> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for line in b {
+        *counts.entry(line.as_str()).or_default() += 1;
+    }
+    let mut only: Vec<String> = Vec::new();
+    for line in a {
+        match counts.get_mut(line.as_str()) {
+            Some(n) if *n > 0 => *n -= 1,
+            _ => only.push(line.clone()),
+        }
+    }
+    only
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let frames = recording::load(&args.session)?;
+    let connection = match &args.connection {
+        Some(c) => c.clone(),
+        None => match recording::connections(&frames).into_iter().next() {
+            Some(c) => c,
+            None => return Err("The session file has no frames".into()),
+        },
+    };
+    let exchanges = recording::exchanges(&frames, &connection);
+
+    let mut websocket = nostr_probe::connect_websocket(&args.relay, 15).await?;
+    let quiet = Duration::from_millis(args.quiet_ms);
+
+    let mut differing: usize = 0;
+    let mut replayed: usize = 0;
+    for exchange in &exchanges {
+        let request = match &exchange.request {
+            Some(r) => r,
+            None => continue, // what the old relay said unprompted
+        };
+        replayed += 1;
+        websocket.send(Message::Text(request.clone())).await?;
+
+        let mut responses: Vec<String> = Vec::new();
+        loop {
+            match tokio::time::timeout(quiet, websocket.next()).await {
+                Ok(Some(Ok(Message::Text(s)))) => {
+                    let done = finishes(request, &s);
+                    responses.push(s);
+                    if done {
+                        break;
+                    }
+                }
+                Ok(Some(Ok(Message::Close(_)))) | Ok(Some(Err(_))) | Ok(None) => {
+                    responses.push("(connection closed)".to_owned());
+                    break;
+                }
+                Ok(Some(Ok(_))) => {}
+                Err(_) => break,
+            }
+        }
+
+        let recorded: Vec<String> = exchange.responses.iter().map(|(_, r)| r.clone()).collect();
+        let removed = only_in(&recorded, &responses);
+        let added = only_in(&responses, &recorded);
+        if removed.is_empty() && added.is_empty() {
+            println!("= {}", request);
+        } else {
+            differing += 1;
+            println!("! {}", request);
+            for line in removed {
+                println!("  - {}", line);
+            }
+            for line in added {
+                println!("  + {}", line);
+            }
+        }
+
+        if responses.last().map(|r| r.as_str()) == Some("(connection closed)") {
+            break;
+        }
+    }
+
+    let _ = websocket.send(Message::Close(None)).await;
+
+    eprintln!(
+        "{} requests replayed, {} with different responses",
+        replayed, differing
+    );
+    if differing > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,179 @@
+use clap::Parser;
+use nostr_probe::filter::FilterArgs;
+use nostr_probe::nip11::Nip11;
+use nostr_probe::output::{self, Source};
+use nostr_probe::{Command, Probe};
+use nostr_types::{RelayMessage, SubscriptionId};
+use serde_json::Value;
+
+/// Full text search (NIP-50) on a relay that supports it. Results are printed in
+/// the order the relay ranks them.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// The relay to search
+    relay_url: String,
+
+    /// What to search for
+    query: String,
+
+    /// Only results in this language (ISO 639-1 code)
+    #[arg(long)]
+    language: Option<String>,
+
+    /// Only results from authors with a NIP-05 at this domain
+    #[arg(long)]
+    domain: Option<String>,
+
+    /// Include results the relay considers spam
+    #[arg(long)]
+    include_spam: bool,
+
+    /// Only results with this sentiment
+    #[arg(long, value_parser = ["negative", "neutral", "positive"])]
+    sentiment: Option<String>,
+
+    /// Event kind (may be repeated)
+    #[arg(long)]
+    kind: Vec<u32>,
+
+    /// Author, as hex, npub or nprofile (may be repeated)
+    #[arg(long)]
+    author: Vec<String>,
+
+    /// At most this many results
+    #[arg(long, default_value_t = 20)]
+    limit: usize,
+
+    /// Search even if the relay's NIP-11 document doesn't list NIP-50
+    #[arg(long)]
+    no_check: bool,
+}
+
+impl Args {
+    // The query with the NIP-50 extensions appended
+    fn search(&self) -> String {
+        let mut search = self.query.trim().to_owned();
+        if let Some(language) = &self.language {
+            search.push_str(&format!(" language:{}", language));
+        }
+        if let Some(domain) = &self.domain {
+            search.push_str(&format!(" domain:{}", domain));
+        }
+        if self.include_spam {
+            search.push_str(" include:spam");
+        }
+        if let Some(sentiment) = &self.sentiment {
+            search.push_str(&format!(" sentiment:{}", sentiment));
+        }
+        search
+    }
+}
+
+fn supports_search(relay_url: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let document = nostr_probe::fetch_nip11(relay_url).map_err(|e| {
+        format!(
+            "Could not fetch the NIP-11 document of {} to check for search support: {} \
+             (use --no-check to search anyway)",
+            relay_url, e
+        )
+    })?;
+    let (nip11, _) = Nip11::parse(&document);
+    match nip11.supported_nips {
+        Some(nips) if nips.contains(&50) => Ok(()),
+        _ => Err(format!(
+            "{} does not support search: its NIP-11 document doesn't list NIP-50 \
+             (use --no-check to search anyway)",
+            relay_url
+        )
+        .into()),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let _finish = output::finish_on_drop();
+    let args = Args::parse();
+    let relay_url = args.relay_url.clone();
+
+    if !args.no_check {
+        let url = relay_url.clone();
+        tokio::task::spawn_blocking(move || supports_search(&url).map_err(|e| e.to_string()))
+            .await??;
+    }
+
+    let search = args.search();
+    eprintln!("Searching {} for {:?}", relay_url, search);
+    let filter = FilterArgs {
+        kind: args.kind.clone(),
+        author: args.author.clone(),
+        limit: Some(args.limit),
+        search: Some(search),
+        ..Default::default()
+    }
+    .to_json()?
+    .unwrap_or_default();
+
+    let (to_probe, from_main) = tokio::sync::mpsc::channel::<Command>(100);
+    let (to_main, mut from_probe) = tokio::sync::mpsc::channel::<RelayMessage>(100);
+    let inner_relay_url = relay_url.clone();
+    let join_handle = tokio::spawn(async move {
+        let mut probe = Probe::new(from_main, to_main);
+        if let Err(e) = probe.connect_and_listen(&inner_relay_url).await {
+            eprintln!("{}", e);
+        }
+    });
+
+    // Filter has no search field, so the REQ is sent as written
+    let our_sub_id = SubscriptionId("search".to_string());
+    let wire: Vec<Value> = vec!["REQ".into(), our_sub_id.0.clone().into(), filter];
+    to_probe
+        .send(Command::Raw(Value::Array(wire).to_string()))
+        .await?;
+
+    let mut results: usize = 0;
+    let mut failure: Option<String> = None;
+    loop {
+        let message = match from_probe.recv().await {
+            Some(m) => m,
+            None => {
+                failure = Some("the connection ended before the results did".to_owned());
+                break;
+            }
+        };
+        match message {
+            RelayMessage::Eose(sub) => {
+                if sub == our_sub_id {
+                    break;
+                }
+            }
+            RelayMessage::Event(sub, e) => {
+                if sub == our_sub_id {
+                    results += 1;
+                    output::event(Source::new(&relay_url, &sub.0), &e)?;
+                }
+            }
+            RelayMessage::Closed(sub, reason) => {
+                if sub == our_sub_id {
+                    failure = Some(format!("CLOSED: {}", reason));
+                    break;
+                }
+            }
+            RelayMessage::Notice(notice) => {
+                failure = Some(format!("NOTICE: {}", notice));
+                break;
+            }
+            _ => {}
+        }
+    }
+    let _ = to_probe.send(Command::Exit).await;
+    join_handle.await?;
+
+    match failure {
+        Some(why) => Err(format!("The search failed: {}", why).into()),
+        None => {
+            eprintln!("{} results", results);
+            Ok(())
+        }
+    }
+}
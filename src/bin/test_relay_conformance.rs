@@ -0,0 +1,41 @@
+use nostr_probe::conformance;
+use nostr_probe::report::{self, Format};
+use std::env;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = env::args();
+    let _ = args.next(); // program name
+    let relay_url = match args.next() {
+        Some(u) => u,
+        None => panic!("Usage: test_relay_conformance <RelayURL> [text|json|junit]"),
+    };
+    let format: Format = match args.next() {
+        Some(f) => f.parse()?,
+        None => Format::Text,
+    };
+
+    // Optional NIPs are only tested if the relay advertises them
+    let url = relay_url.clone();
+    let nip11 = tokio::task::spawn_blocking(move || {
+        nostr_probe::fetch_nip11(&url).map_err(|e| e.to_string())
+    })
+    .await?;
+    let supported_nips = match nip11 {
+        Ok(doc) => conformance::supported_nips(&doc),
+        Err(e) => {
+            eprintln!("Could not fetch NIP-11 document, testing all NIPs: {}", e);
+            None
+        }
+    };
+
+    let results = conformance::run(&relay_url, supported_nips).await;
+
+    report::print(format, "conformance", &relay_url, &results);
+
+    if report::any_failed(&results) {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
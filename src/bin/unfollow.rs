@@ -0,0 +1,61 @@
+use clap::Parser;
+use nostr_probe::contacts::{self, EditOptions};
+use nostr_probe::nip19;
+
+/// Unfollow people by removing them from your newest contact list (kind 3), keeping
+/// everything else in it
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// The people to unfollow, as npub, nprofile or hex public keys
+    #[arg(required = true)]
+    users: Vec<String>,
+
+    /// Relays to fetch your list from and publish it to (may be repeated). Defaults
+    /// to the write relays in your relay list.
+    #[arg(short, long)]
+    relay: Vec<String>,
+
+    /// Edit the newest list found even if some relays could not be checked for a
+    /// newer one
+    #[arg(long)]
+    allow_unchecked: bool,
+
+    /// Start a new list if none is found
+    #[arg(long)]
+    new_list: bool,
+
+    /// Publish even if the list shrinks drastically
+    #[arg(long)]
+    allow_shrink: bool,
+
+    /// Don't ask before signing
+    #[arg(short, long)]
+    yes: bool,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let mut pubkeys: Vec<String> = Vec::new();
+    for user in &args.users {
+        pubkeys.push(nip19::parse_pubkey(user)?.pubkey.as_hex_string());
+    }
+
+    let options = EditOptions {
+        relays: args.relay.clone(),
+        allow_unchecked: args.allow_unchecked,
+        new_list: args.new_list,
+        allow_shrink: args.allow_shrink,
+        yes: args.yes,
+    };
+    contacts::edit_contact_list(&options, |tags| {
+        let mut changed = false;
+        for pubkey in &pubkeys {
+            changed |= contacts::unfollow(tags, pubkey);
+        }
+        changed
+    })
+    .await
+}
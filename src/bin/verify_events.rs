@@ -0,0 +1,199 @@
+use clap::Parser;
+use nostr_probe::verify::{check_event_json, Failure};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+
+const BATCH_SIZE: usize = 1000;
+
+/// Verify a stream of JSONL events (such as the output of dump_relay)
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Files to read (reads stdin if none are given)
+    files: Vec<PathBuf>,
+
+    /// Number of worker threads (defaults to the number of CPUs)
+    #[arg(short, long)]
+    threads: Option<usize>,
+
+    /// How many seconds into the future an event may be dated
+    #[arg(long, default_value_t = 900)]
+    max_future: i64,
+
+    /// Only print lines that failed
+    #[arg(short, long)]
+    quiet: bool,
+}
+
+struct Batch {
+    seq: u64,
+    source: Arc<str>,
+    first_line: usize,
+    lines: Vec<Vec<u8>>,
+}
+
+struct Checked {
+    source: Arc<str>,
+    first_line: usize,
+    results: Vec<Option<Result<String, Failure>>>,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let threads = args
+        .threads
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(4, |n| n.get()))
+        .max(1);
+
+    let (to_workers, from_reader) = mpsc::sync_channel::<Batch>(threads * 2);
+    let from_reader = Arc::new(Mutex::new(from_reader));
+    let (to_printer, from_workers) = mpsc::channel::<(u64, Checked)>();
+
+    // Batches are printed in order, so one slow batch holds back those after it. The
+    // reader takes a slot for each batch and the printer frees it once printed, which
+    // bounds how many batches can pile up waiting.
+    let (take_slot, free_slot) = mpsc::sync_channel::<()>(threads * 4);
+
+    let mut workers = Vec::new();
+    for _ in 0..threads {
+        let from_reader = from_reader.clone();
+        let to_printer = to_printer.clone();
+        let max_future = args.max_future;
+        workers.push(std::thread::spawn(move || loop {
+            let batch = match from_reader.lock().unwrap().recv() {
+                Ok(b) => b,
+                Err(_) => break,
+            };
+            let results = batch
+                .lines
+                .iter()
+                .map(|line| match std::str::from_utf8(line) {
+                    Ok(line) if line.trim().is_empty() => None,
+                    Ok(line) => {
+                        Some(check_event_json(line, max_future).map(|e| e.id.as_hex_string()))
+                    }
+                    Err(e) => Some(Err(Failure::BadJson(format!("not UTF-8: {}", e)))),
+                })
+                .collect();
+            let checked = Checked {
+                source: batch.source,
+                first_line: batch.first_line,
+                results,
+            };
+            if to_printer.send((batch.seq, checked)).is_err() {
+                break;
+            }
+        }));
+    }
+    drop(to_printer);
+
+    let quiet = args.quiet;
+    let printer = std::thread::spawn(move || {
+        let stdout = std::io::stdout();
+        let mut out = stdout.lock();
+        let mut next_seq: u64 = 0;
+        let mut pending: BTreeMap<u64, Checked> = BTreeMap::new();
+        let mut total: usize = 0;
+        let mut failures: HashMap<&'static str, usize> = HashMap::new();
+        for (seq, checked) in from_workers {
+            pending.insert(seq, checked);
+            // Print batches in input order
+            while let Some(checked) = pending.remove(&next_seq) {
+                for (i, result) in checked.results.iter().enumerate() {
+                    let line = checked.first_line + i;
+                    match result {
+                        None => continue,
+                        Some(Ok(id)) => {
+                            if !quiet {
+                                let _ = writeln!(out, "{}:{}: OK {}", checked.source, line, id);
+                            }
+                        }
+                        Some(Err(failure)) => {
+                            *failures.entry(failure.class()).or_insert(0) += 1;
+                            let _ = writeln!(out, "{}:{}: {}", checked.source, line, failure);
+                        }
+                    }
+                    total += 1;
+                }
+                next_seq += 1;
+                let _ = free_slot.recv();
+            }
+        }
+        (total, failures)
+    });
+
+    let mut seq: u64 = 0;
+    let sources: Vec<Option<PathBuf>> = if args.files.is_empty() {
+        vec![None]
+    } else {
+        args.files.into_iter().map(Some).collect()
+    };
+    for source in sources {
+        let (name, reader): (Arc<str>, Box<dyn BufRead>) = match source {
+            None => ("-".into(), Box::new(BufReader::new(std::io::stdin()))),
+            Some(path) => (
+                path.display().to_string().into(),
+                Box::new(BufReader::new(std::fs::File::open(&path)?)),
+            ),
+        };
+        let mut batch = Batch {
+            seq,
+            source: name.clone(),
+            first_line: 1,
+            lines: Vec::with_capacity(BATCH_SIZE),
+        };
+        // Read bytes rather than strings so that a line of bad UTF-8 fails by itself
+        for (i, line) in reader.split(b'\n').enumerate() {
+            let mut line = line?;
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            batch.lines.push(line);
+            if batch.lines.len() == BATCH_SIZE {
+                let next = Batch {
+                    seq: seq + 1,
+                    source: name.clone(),
+                    first_line: i + 2,
+                    lines: Vec::with_capacity(BATCH_SIZE),
+                };
+                take_slot.send(())?;
+                to_workers.send(std::mem::replace(&mut batch, next))?;
+                seq += 1;
+            }
+        }
+        if !batch.lines.is_empty() {
+            take_slot.send(())?;
+            to_workers.send(batch)?;
+            seq += 1;
+        }
+    }
+    drop(to_workers);
+
+    for worker in workers {
+        let _ = worker.join();
+    }
+    let (total, failures) = printer.join().unwrap();
+
+    let failed: usize = failures.values().sum();
+    eprintln!(
+        "{} events checked, {} ok, {} failed",
+        total,
+        total - failed,
+        failed
+    );
+    let mut classes: Vec<(&&str, &usize)> = failures.iter().collect();
+    classes.sort();
+    for (class, count) in classes {
+        eprintln!("  {}: {}", class, count);
+    }
+
+    if failed > 0 {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
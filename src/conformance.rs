@@ -0,0 +1,759 @@
+//! A NIP conformance suite for relays.
+//!
+//! Every check publishes events from a fresh ephemeral keypair, tagged with a random
+//! nonce so they cannot be confused with anything else on the relay. Checks for
+//! optional NIPs are skipped when the relay's NIP-11 document doesn't list them.
+
+use crate::{Command, Probe, Unparsed};
+use nostr_types::{
+    Event, EventKind, Filter, Id, KeySigner, PreEvent, PrivateKey, PublicKey, RelayMessage, Signer,
+    SubscriptionId, Tag, Unixtime,
+};
+use std::time::Duration;
+use tokio::sync::mpsc::{Receiver, Sender};
+
+/// The machine-readable prefixes NIP-01 allows on OK and CLOSED messages
+pub const REASON_PREFIXES: &[&str] = &[
+    "duplicate",
+    "pow",
+    "blocked",
+    "rate-limited",
+    "invalid",
+    "restricted",
+    "mute",
+    "error",
+    "auth-required",
+];
+
+// How long to wait for any single response
+const RESPONSE_TIMEOUT_SECS: u64 = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Pass,
+    Fail(String),
+    Skip(String),
+}
+
+/// Why a request on a [`Session`] didn't get its answer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionError {
+    /// The relay gave a wrong answer or went silent
+    Failed(String),
+
+    /// The connection could not be made, or ended before the answer came
+    Closed(String),
+
+    /// The relay sent a NOTICE and never answered. A NOTICE may be about anything,
+    /// so the check can't tell whether the relay got it right.
+    Inconclusive(String),
+}
+
+impl SessionError {
+    /// The same error, with what was being done in front of the reason
+    pub fn context(self, what: &str) -> SessionError {
+        match self {
+            SessionError::Failed(why) => SessionError::Failed(format!("{}: {}", what, why)),
+            SessionError::Closed(why) => SessionError::Closed(format!("{}: {}", what, why)),
+            SessionError::Inconclusive(why) => {
+                SessionError::Inconclusive(format!("{}: {}", what, why))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for SessionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionError::Failed(why) | SessionError::Closed(why) => write!(f, "{}", why),
+            SessionError::Inconclusive(why) => write!(f, "inconclusive: {}", why),
+        }
+    }
+}
+
+impl From<SessionError> for Outcome {
+    fn from(e: SessionError) -> Outcome {
+        match e {
+            SessionError::Failed(_) | SessionError::Closed(_) => Outcome::Fail(e.to_string()),
+            SessionError::Inconclusive(_) => Outcome::Skip(e.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TestResult {
+    pub name: &'static str,
+    pub nip: u32,
+    pub outcome: Outcome,
+    pub duration: Duration,
+    /// Everything the relay said during the check
+    pub responses: Vec<String>,
+}
+
+/// Time a check and push its result, with everything the session heard meanwhile
+#[macro_export]
+macro_rules! record {
+    ($results:expr, $session:expr, $nip:expr, $name:expr, $body:expr) => {{
+        let start = std::time::Instant::now();
+        let outcome: $crate::conformance::Outcome = $body;
+        $results.push($crate::conformance::TestResult {
+            name: $name,
+            nip: $nip,
+            outcome,
+            duration: start.elapsed(),
+            responses: $session.take_responses(),
+        });
+    }};
+}
+
+/// One connection to the relay under test.
+///
+/// Only the relay's answer to our own subscription or event decides a request. A
+/// NOTICE is recorded but doesn't end it, and only makes it inconclusive if the
+/// answer never comes.
+pub struct Session {
+    to_probe: Sender<Command>,
+    from_probe: Receiver<RelayMessage>,
+    from_probe_unparsed: Receiver<Unparsed>,
+    join_handle: tokio::task::JoinHandle<()>,
+    responses: Vec<String>,
+    next_sub: usize,
+    alive: bool,
+    // The last NOTICE since the current request was sent
+    notice: Option<String>,
+}
+
+impl Session {
+    pub fn open(relay_url: &str) -> Session {
+        let (to_probe, from_main) = tokio::sync::mpsc::channel::<Command>(100);
+        let (to_main, from_probe) = tokio::sync::mpsc::channel::<RelayMessage>(100);
+        let (to_main_unparsed, from_probe_unparsed) = tokio::sync::mpsc::channel::<Unparsed>(1);
+        let url = relay_url.to_owned();
+        let join_handle = tokio::spawn(async move {
+            let mut probe = Probe::new(from_main, to_main);
+            // Anything the relay says that isn't valid NIP-01 should fail the check
+            probe.strict = true;
+            probe.to_main_unparsed = Some(to_main_unparsed);
+            if let Err(e) = probe.connect_and_listen(&url).await {
+                eprintln!("{}", e);
+            }
+        });
+        Session {
+            to_probe,
+            from_probe,
+            from_probe_unparsed,
+            join_handle,
+            responses: Vec::new(),
+            next_sub: 0,
+            alive: true,
+            notice: None,
+        }
+    }
+
+    pub async fn close(self) {
+        let _ = self.to_probe.send(Command::Exit).await;
+        let _ = self.join_handle.await;
+    }
+
+    /// Take the record of relay responses seen so far
+    pub fn take_responses(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.responses)
+    }
+
+    fn sub_id(&mut self, name: &str) -> SubscriptionId {
+        self.next_sub += 1;
+        SubscriptionId(format!("{}-{}", name, self.next_sub))
+    }
+
+    async fn send(&mut self, command: Command) -> Result<(), SessionError> {
+        self.notice = None;
+        if self.to_probe.send(command).await.is_err() {
+            self.alive = false;
+            return Err(SessionError::Closed("connection is closed".to_owned()));
+        }
+        Ok(())
+    }
+
+    async fn recv(&mut self) -> Result<RelayMessage, SessionError> {
+        let duration = Duration::from_secs(RESPONSE_TIMEOUT_SECS);
+        match tokio::time::timeout(duration, self.from_probe.recv()).await {
+            Ok(Some(message)) => {
+                self.responses.push(describe(&message));
+                if let RelayMessage::Notice(notice) = &message {
+                    self.notice = Some(notice.clone());
+                }
+                Ok(message)
+            }
+            Ok(None) => {
+                self.alive = false;
+                // In strict mode the probe hangs up on a frame it can't parse
+                match self.from_probe_unparsed.try_recv() {
+                    Ok(unparsed) => {
+                        self.responses.push(format!(
+                            "UNPARSEABLE({}, {})",
+                            unparsed.error, unparsed.frame
+                        ));
+                        Err(SessionError::Failed(format!(
+                            "relay sent a frame that is not a valid relay message ({})",
+                            unparsed.error
+                        )))
+                    }
+                    Err(_) => Err(SessionError::Closed("connection closed".to_owned())),
+                }
+            }
+            Err(_) => match self.notice.take() {
+                Some(notice) => Err(SessionError::Inconclusive(format!(
+                    "no answer within {} seconds, only NOTICE: {}",
+                    RESPONSE_TIMEOUT_SECS, notice
+                ))),
+                None => Err(SessionError::Failed(format!(
+                    "no response within {} seconds",
+                    RESPONSE_TIMEOUT_SECS
+                ))),
+            },
+        }
+    }
+
+    /// Publish an event, returning the OK flag and message
+    pub async fn post(&mut self, event: &Event) -> Result<(bool, String), SessionError> {
+        self.send(Command::PostEvent(event.clone())).await?;
+        loop {
+            match self.recv().await? {
+                RelayMessage::Ok(id, ok, reason) if id == event.id => return Ok((ok, reason)),
+                _ => {}
+            }
+        }
+    }
+
+    /// Run a REQ until EOSE, returning the events in the order received
+    pub async fn fetch(&mut self, filters: Vec<Filter>) -> Result<Vec<Event>, SessionError> {
+        let sub = self.sub_id("fetch");
+        self.send(Command::FetchEvents(sub.clone(), filters))
+            .await?;
+        let mut events: Vec<Event> = Vec::new();
+        loop {
+            match self.recv().await? {
+                RelayMessage::Event(s, e) if s == sub => events.push(*e),
+                RelayMessage::Eose(s) if s == sub => {
+                    self.send(Command::Close(sub)).await?;
+                    return Ok(events);
+                }
+                RelayMessage::Closed(s, reason) if s == sub => {
+                    return Err(SessionError::Failed(format!("CLOSED: {}", reason)))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Run a COUNT, returning the count
+    pub async fn count(&mut self, filters: Vec<Filter>) -> Result<u64, SessionError> {
+        let sub = self.sub_id("count");
+        self.send(Command::CountEvents(sub.clone(), filters))
+            .await?;
+        loop {
+            match self.recv().await? {
+                RelayMessage::Count(s, result) if s == sub => {
+                    let value = serde_json::to_value(&result)
+                        .map_err(|e| SessionError::Failed(e.to_string()))?;
+                    return value.get("count").and_then(|c| c.as_u64()).ok_or_else(|| {
+                        SessionError::Failed(format!("COUNT result has no count: {}", value))
+                    });
+                }
+                RelayMessage::Closed(s, reason) if s == sub => {
+                    return Err(SessionError::Failed(format!("CLOSED: {}", reason)))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Send a REQ with the given subscription id, returning Ok(None) on EOSE or
+    /// Ok(Some(reason)) if the relay CLOSED it
+    pub async fn req_closed(
+        &mut self,
+        sub: SubscriptionId,
+        filters: Vec<Filter>,
+    ) -> Result<Option<String>, SessionError> {
+        let closed = self.subscribe(sub.clone(), filters).await?;
+        if closed.is_none() {
+            self.send(Command::Close(sub)).await?;
+        }
+        Ok(closed)
+    }
+
+    /// Like `req_closed`, but leaves the subscription open after EOSE
+    pub async fn subscribe(
+        &mut self,
+        sub: SubscriptionId,
+        filters: Vec<Filter>,
+    ) -> Result<Option<String>, SessionError> {
+        self.send(Command::FetchEvents(sub.clone(), filters))
+            .await?;
+        loop {
+            match self.recv().await? {
+                RelayMessage::Eose(s) if s == sub => return Ok(None),
+                RelayMessage::Closed(s, reason) if s == sub => return Ok(Some(reason)),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// A one-line description of a relay message, for reports
+pub fn describe(message: &RelayMessage) -> String {
+    match message {
+        RelayMessage::Auth(challenge) => format!("AUTH({})", challenge),
+        RelayMessage::Event(sub, e) => {
+            format!("EVENT({}, {})", sub.as_str(), e.id.as_hex_string())
+        }
+        RelayMessage::Closed(sub, msg) => format!("CLOSED({}, {})", sub.as_str(), msg),
+        RelayMessage::Notice(s) => format!("NOTICE({})", s),
+        RelayMessage::Notify(s) => format!("NOTIFY({})", s),
+        RelayMessage::Eose(sub) => format!("EOSE({})", sub.as_str()),
+        RelayMessage::Count(sub, result) => format!(
+            "COUNT({}, {})",
+            sub.as_str(),
+            serde_json::to_string(result).unwrap_or_default()
+        ),
+        RelayMessage::Ok(id, ok, reason) => {
+            format!("OK({}, {}, {})", id.as_hex_string(), ok, reason)
+        }
+    }
+}
+
+/// Whether an OK or CLOSED message starts with a NIP-01 machine-readable prefix
+pub fn has_standard_prefix(message: &str) -> bool {
+    crate::reason_prefix(message)
+        .map(|p| REASON_PREFIXES.contains(&p))
+        .unwrap_or(false)
+}
+
+/// Signs events from a fresh ephemeral keypair, so that probing a relay never
+/// involves anyone's real key
+pub struct Author {
+    pub signer: KeySigner,
+    pub pubkey: PublicKey,
+}
+
+impl Default for Author {
+    fn default() -> Author {
+        Author::new()
+    }
+}
+
+impl Author {
+    pub fn new() -> Author {
+        let private_key = PrivateKey::generate();
+        let pubkey = private_key.public_key();
+        // The key is never saved, so its encryption needn't be strong
+        let signer = KeySigner::from_private_key(private_key, "pass", 8).unwrap();
+        Author { signer, pubkey }
+    }
+
+    pub fn sign(&self, kind: u32, created_at: i64, tags: Vec<Tag>, content: &str) -> Event {
+        let pre_event = PreEvent {
+            pubkey: self.pubkey,
+            created_at: Unixtime(created_at),
+            kind: EventKind::from(kind),
+            tags,
+            content: content.to_owned(),
+        };
+        self.signer.sign_event(pre_event).unwrap()
+    }
+
+    /// A note signed now
+    pub fn note(&self, content: &str) -> Event {
+        self.sign(1, Unixtime::now().0, vec![], content)
+    }
+
+    /// A filter for this author's events of one kind
+    pub fn filter(&self, kind: u32) -> Filter {
+        let mut filter = Filter::new();
+        filter.add_author(self.pubkey);
+        filter.add_event_kind(EventKind::from(kind));
+        filter
+    }
+}
+
+// Shared state for a run of the suite
+struct Context {
+    session: Session,
+    author: Author,
+    nonce: String,
+    now: i64,
+    supported_nips: Option<Vec<u32>>,
+}
+
+impl Context {
+    fn sign(&self, kind: u32, created_at: i64, mut tags: Vec<Tag>, content: &str) -> Event {
+        tags.push(Tag::new(&["t", &self.nonce]));
+        self.author.sign(kind, created_at, tags, content)
+    }
+
+    fn supports(&self, nip: u32) -> Option<String> {
+        match &self.supported_nips {
+            Some(nips) if !nips.contains(&nip) => {
+                Some(format!("relay does not advertise NIP-{:02}", nip))
+            }
+            _ => None,
+        }
+    }
+
+    // Publish an event that a check depends on
+    async fn publish(&mut self, event: &Event) -> Result<(), SessionError> {
+        match self.session.post(event).await? {
+            (true, _) => Ok(()),
+            (false, reason) => Err(SessionError::Failed(format!(
+                "relay rejected setup event: {}",
+                reason
+            ))),
+        }
+    }
+
+    fn our_filter(&self) -> Filter {
+        let mut filter = Filter::new();
+        filter.add_author(self.author.pubkey);
+        filter.add_tag_value('t', self.nonce.clone());
+        filter
+    }
+}
+
+// Check that `events` has exactly the `expected` ids, optionally in order
+fn expect_ids(events: &[Event], expected: &[Id], ordered: bool) -> Outcome {
+    let got: Vec<Id> = events.iter().map(|e| e.id).collect();
+    let matches = if ordered {
+        got == expected
+    } else {
+        got.len() == expected.len() && expected.iter().all(|id| got.contains(id))
+    };
+    if matches {
+        Outcome::Pass
+    } else {
+        Outcome::Fail(format!(
+            "expected [{}] got [{}]",
+            expected
+                .iter()
+                .map(|id| id.as_hex_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            got.iter()
+                .map(|id| id.as_hex_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    }
+}
+
+/// Run the whole suite against a relay. `supported_nips` comes from the relay's
+/// NIP-11 document, if it has one.
+pub async fn run(relay_url: &str, supported_nips: Option<Vec<u32>>) -> Vec<TestResult> {
+    let author = Author::new();
+    let pubkey = author.pubkey;
+    let nonce = hex::encode(rand::random::<[u8; 16]>());
+
+    let mut ctx = Context {
+        session: Session::open(relay_url),
+        author,
+        nonce,
+        now: Unixtime::now().0,
+        supported_nips,
+    };
+
+    let mut results: Vec<TestResult> = Vec::new();
+
+    // Fixture events for the NIP-01 filter checks
+    let referenced = hex::encode(rand::random::<[u8; 32]>());
+    let a = ctx.sign(1, ctx.now - 30, vec![Tag::new(&["e", &referenced])], "A");
+    let b = ctx.sign(1, ctx.now - 20, vec![], "B");
+    let c = ctx.sign(
+        7,
+        ctx.now - 10,
+        vec![Tag::new(&["p", &pubkey.as_hex_string()])],
+        "+",
+    );
+
+    macro_rules! check {
+        ($name:expr, $nip:expr, $body:expr) => {{
+            if !ctx.session.alive {
+                let old = std::mem::replace(&mut ctx.session, Session::open(relay_url));
+                old.close().await;
+            }
+            record!(results, ctx.session, $nip, $name, $body);
+        }};
+    }
+
+    check!("publish", 1, {
+        let mut outcome = Outcome::Pass;
+        for event in [&a, &b, &c] {
+            match ctx.session.post(event).await {
+                Ok((true, _)) => {}
+                Ok((false, reason)) => {
+                    outcome = Outcome::Fail(format!("rejected: {}", reason));
+                    break;
+                }
+                Err(e) => {
+                    outcome = e.into();
+                    break;
+                }
+            }
+        }
+        outcome
+    });
+    let published = matches!(results[0].outcome, Outcome::Pass);
+
+    macro_rules! fixture_check {
+        ($name:expr, $filter:expr, $expected:expr, $ordered:expr) => {
+            check!($name, 1, {
+                if !published {
+                    Outcome::Skip("fixture events were not published".to_owned())
+                } else {
+                    match ctx.session.fetch(vec![$filter]).await {
+                        Ok(events) => expect_ids(&events, $expected, $ordered),
+                        Err(e) => e.into(),
+                    }
+                }
+            })
+        };
+    }
+
+    fixture_check!(
+        "filter_ids",
+        {
+            let mut f = Filter::new();
+            f.add_id(a.id);
+            f
+        },
+        &[a.id],
+        false
+    );
+    fixture_check!(
+        "filter_authors",
+        {
+            let mut f = Filter::new();
+            f.add_author(pubkey);
+            f
+        },
+        &[a.id, b.id, c.id],
+        false
+    );
+    fixture_check!(
+        "filter_kinds",
+        {
+            let mut f = ctx.our_filter();
+            f.add_event_kind(EventKind::from(7));
+            f
+        },
+        &[c.id],
+        false
+    );
+    fixture_check!("filter_tag_t", ctx.our_filter(), &[a.id, b.id, c.id], false);
+    fixture_check!(
+        "filter_tag_e",
+        {
+            let mut f = ctx.our_filter();
+            f.add_tag_value('e', referenced.clone());
+            f
+        },
+        &[a.id],
+        false
+    );
+    fixture_check!(
+        "filter_since_until",
+        {
+            let mut f = ctx.our_filter();
+            f.since = Some(Unixtime(ctx.now - 25));
+            f.until = Some(Unixtime(ctx.now - 15));
+            f
+        },
+        &[b.id],
+        false
+    );
+    fixture_check!(
+        "filter_limit_newest_first",
+        {
+            let mut f = ctx.our_filter();
+            f.limit = Some(2);
+            f
+        },
+        &[c.id, b.id],
+        true
+    );
+
+    check!("replaceable", 1, {
+        let v1 = ctx.sign(0, ctx.now - 10, vec![], "{\"name\":\"v1\"}");
+        let v2 = ctx.sign(0, ctx.now - 5, vec![], "{\"name\":\"v2\"}");
+        let mut filter = Filter::new();
+        filter.add_author(pubkey);
+        filter.add_event_kind(EventKind::Metadata);
+        let result = async {
+            ctx.publish(&v1).await?;
+            ctx.publish(&v2).await?;
+            ctx.session.fetch(vec![filter]).await
+        }
+        .await;
+        match result {
+            Ok(events) => expect_ids(&events, &[v2.id], false),
+            Err(e) => e.into(),
+        }
+    });
+
+    check!("parameterized_replaceable", 1, {
+        let v1 = ctx.sign(30078, ctx.now - 10, vec![Tag::new(&["d", "one"])], "v1");
+        let v2 = ctx.sign(30078, ctx.now - 5, vec![Tag::new(&["d", "one"])], "v2");
+        let other = ctx.sign(30078, ctx.now - 10, vec![Tag::new(&["d", "two"])], "other");
+        let mut filter = ctx.our_filter();
+        filter.add_event_kind(EventKind::from(30078));
+        let result = async {
+            ctx.publish(&v1).await?;
+            ctx.publish(&other).await?;
+            ctx.publish(&v2).await?;
+            ctx.session.fetch(vec![filter]).await
+        }
+        .await;
+        match result {
+            Ok(events) => expect_ids(&events, &[v2.id, other.id], false),
+            Err(e) => e.into(),
+        }
+    });
+
+    check!("ephemeral_not_stored", 1, {
+        let event = ctx.sign(20001, ctx.now, vec![], "ephemeral");
+        let mut filter = Filter::new();
+        filter.add_id(event.id);
+        let result = async {
+            ctx.publish(&event).await?;
+            ctx.session.fetch(vec![filter]).await
+        }
+        .await;
+        match result {
+            Ok(events) => expect_ids(&events, &[], false),
+            Err(e) => e.into(),
+        }
+    });
+
+    check!("ok_duplicate_prefix", 1, {
+        if !published {
+            Outcome::Skip("fixture events were not published".to_owned())
+        } else {
+            match ctx.session.post(&a).await {
+                Ok((true, reason)) if crate::reason_prefix(&reason) == Some("duplicate") => {
+                    Outcome::Pass
+                }
+                Ok((ok, reason)) => Outcome::Fail(format!(
+                    "expected OK true with duplicate: prefix, got {} \"{}\"",
+                    ok, reason
+                )),
+                Err(e) => e.into(),
+            }
+        }
+    });
+
+    check!("ok_invalid_prefix", 1, {
+        let mut event = ctx.sign(1, ctx.now, vec![], "original");
+        event.content = "tampered".to_owned();
+        match ctx.session.post(&event).await {
+            Ok((true, _)) => Outcome::Fail("relay accepted an event with a bad id".to_owned()),
+            Ok((false, reason)) if has_standard_prefix(&reason) => Outcome::Pass,
+            Ok((false, reason)) => {
+                Outcome::Fail(format!("rejection has no standard prefix: \"{}\"", reason))
+            }
+            Err(e) => e.into(),
+        }
+    });
+
+    check!("closed_prefix", 1, {
+        // Subscription ids longer than 64 characters are invalid
+        let sub = SubscriptionId("x".repeat(80));
+        match ctx.session.req_closed(sub, vec![ctx.our_filter()]).await {
+            Ok(None) => Outcome::Skip("relay accepted an over-long subscription id".to_owned()),
+            Ok(Some(reason)) if has_standard_prefix(&reason) => Outcome::Pass,
+            Ok(Some(reason)) => {
+                Outcome::Fail(format!("CLOSED has no standard prefix: \"{}\"", reason))
+            }
+            Err(e) => e.into(),
+        }
+    });
+
+    check!("deletion", 9, {
+        if let Some(reason) = ctx.supports(9) {
+            Outcome::Skip(reason)
+        } else {
+            let target = ctx.sign(1, ctx.now - 5, vec![], "to be deleted");
+            let deletion = ctx.sign(
+                5,
+                ctx.now,
+                vec![Tag::new(&["e", &target.id.as_hex_string()])],
+                "",
+            );
+            let mut filter = Filter::new();
+            filter.add_id(target.id);
+            let result = async {
+                ctx.publish(&target).await?;
+                ctx.publish(&deletion).await?;
+                ctx.session.fetch(vec![filter]).await
+            }
+            .await;
+            match result {
+                Ok(events) => expect_ids(&events, &[], false),
+                Err(e) => e.into(),
+            }
+        }
+    });
+
+    check!("expiration", 40, {
+        if let Some(reason) = ctx.supports(40) {
+            Outcome::Skip(reason)
+        } else {
+            let expires = Unixtime::now().0 + 2;
+            let event = ctx.sign(
+                1,
+                ctx.now,
+                vec![Tag::new(&["expiration", &expires.to_string()])],
+                "expiring",
+            );
+            let mut filter = Filter::new();
+            filter.add_id(event.id);
+            let result = async {
+                ctx.publish(&event).await?;
+                tokio::time::sleep(Duration::from_secs(4)).await;
+                ctx.session.fetch(vec![filter]).await
+            }
+            .await;
+            match result {
+                Ok(events) => expect_ids(&events, &[], false),
+                Err(e) => e.into(),
+            }
+        }
+    });
+
+    check!("count", 45, {
+        if let Some(reason) = ctx.supports(45) {
+            Outcome::Skip(reason)
+        } else if !published {
+            Outcome::Skip("fixture events were not published".to_owned())
+        } else {
+            let mut filter = ctx.our_filter();
+            filter.add_event_kind(EventKind::TextNote);
+            filter.until = Some(Unixtime(ctx.now - 15));
+            match ctx.session.count(vec![filter]).await {
+                Ok(2) => Outcome::Pass,
+                Ok(n) => Outcome::Fail(format!("expected a count of 2, got {}", n)),
+                Err(e) => e.into(),
+            }
+        }
+    });
+
+    ctx.session.close().await;
+
+    results
+}
+
+/// The `supported_nips` from a NIP-11 document
+pub fn supported_nips(nip11: &serde_json::Value) -> Option<Vec<u32>> {
+    nip11.get("supported_nips")?.as_array().map(|a| {
+        a.iter()
+            .filter_map(|n| n.as_u64().map(|n| n as u32))
+            .collect()
+    })
+}
@@ -0,0 +1,701 @@
+//! The social graph in kind 3 contact lists: who follows whom.
+//!
+//! Contact lists are replaceable, so only the newest list of each author counts.
+//! Relays may still hold older ones, which is why followers found by `#p` are checked
+//! against their author's newest list.
+//!
+//! Publishing a list replaces the whole thing, so edits start from the newest list
+//! on the user's relays, keep every tag and the `content` they don't touch, and back
+//! up each version first.
+
+use crate::filter::tag_fields;
+use crate::outbox::{normalize_relay_url, parse_relay_list, BOOTSTRAP_RELAYS};
+use futures_util::future::join_all;
+use nostr_types::{Event, EventKind, Filter, PreEvent, PublicKey, Signer, Tag, Unixtime};
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+// Authors per filter when fetching many contact lists at once
+const CHUNK: usize = 200;
+
+/// One followed pubkey in a contact list
+#[derive(Debug, Clone, Serialize)]
+pub struct Contact {
+    pub pubkey: String,
+    pub relay: Option<String>,
+    pub petname: Option<String>,
+}
+
+/// The contacts in a kind 3 contact list, in order, skipping invalid pubkeys
+pub fn parse_contact_list(event: &Event) -> Vec<Contact> {
+    let mut contacts: Vec<Contact> = Vec::new();
+    for tag in tag_fields(event) {
+        if tag.first().map(|t| t.as_str()) != Some("p") {
+            continue;
+        }
+        let pubkey = match tag.get(1).map(|p| PublicKey::try_from_hex_string(p, true)) {
+            Some(Ok(pubkey)) => pubkey.as_hex_string(),
+            _ => continue,
+        };
+        if contacts.iter().any(|c| c.pubkey == pubkey) {
+            continue;
+        }
+        let non_empty = |i: usize| tag.get(i).filter(|s| !s.is_empty()).cloned();
+        contacts.push(Contact {
+            pubkey,
+            relay: non_empty(2),
+            petname: non_empty(3),
+        });
+    }
+    contacts
+}
+
+/// The relays to ask: those given, or else the hints and the bootstrap relays
+pub fn relays_to_ask(given: &[String], hints: &[String]) -> Vec<String> {
+    let candidates: Vec<String> = if given.is_empty() {
+        hints
+            .iter()
+            .cloned()
+            .chain(BOOTSTRAP_RELAYS.iter().map(|r| r.to_string()))
+            .collect()
+    } else {
+        given.to_vec()
+    };
+    let mut relays: Vec<String> = Vec::new();
+    for url in candidates {
+        if let Some(url) = normalize_relay_url(&url) {
+            if !relays.contains(&url) {
+                relays.push(url);
+            }
+        }
+    }
+    relays
+}
+
+// Whether `a` replaces `b`: it is newer, or as old with a lower id (NIP-01)
+fn replaces(a: &Event, b: &Event) -> bool {
+    (a.created_at, Reverse(a.id.0)) > (b.created_at, Reverse(b.id.0))
+}
+
+// Keep the newest contact list of each author, by hex pubkey
+fn newest_by_author(events: Vec<Event>) -> HashMap<String, Event> {
+    let mut newest: HashMap<String, Event> = HashMap::new();
+    for event in events {
+        if event.kind != EventKind::ContactList {
+            continue;
+        }
+        let author = event.pubkey.as_hex_string();
+        let replace = match newest.get(&author) {
+            Some(n) => replaces(&event, n),
+            None => true,
+        };
+        if replace {
+            newest.insert(author, event);
+        }
+    }
+    newest
+}
+
+/// The newest contact list of each of `authors` that any of `relays` has, by hex
+/// pubkey
+pub async fn contact_lists(authors: &[PublicKey], relays: &[String]) -> HashMap<String, Event> {
+    let filters: Vec<Filter> = authors
+        .chunks(CHUNK)
+        .map(|chunk| Filter {
+            kinds: vec![EventKind::ContactList],
+            authors: chunk.to_vec(),
+            ..Default::default()
+        })
+        .collect();
+    if filters.is_empty() {
+        return HashMap::new();
+    }
+    newest_by_author(crate::fetch_all_from(relays, filters).await.0)
+}
+
+/// The newest contact list of `pubkey`
+pub async fn contact_list(pubkey: PublicKey, relays: &[String]) -> Option<Event> {
+    contact_lists(&[pubkey], relays)
+        .await
+        .remove(&pubkey.as_hex_string())
+}
+
+/// Who follows `pubkey`, according to their newest contact lists. Returns each
+/// follower's hex pubkey with their contact list.
+///
+/// Contact lists are large and a popular account is in a great many of them, so each
+/// relay is only asked for the newest `limit` lists that mention `pubkey`. Beyond
+/// that the answer is partial, which is reported.
+pub async fn followers(pubkey: PublicKey, relays: &[String], limit: usize) -> Vec<(String, Event)> {
+    let target = pubkey.as_hex_string();
+    let mut filter = Filter {
+        kinds: vec![EventKind::ContactList],
+        limit: Some(limit),
+        ..Default::default()
+    };
+    filter.add_tag_value('p', target.clone());
+
+    // Asked one by one, as only a relay's own count shows whether it held back more
+    let fetches = relays.iter().map(|relay| {
+        let filter = filter.clone();
+        async move { (relay, crate::fetch_all(relay, vec![filter]).await) }
+    });
+    let mut events: Vec<Event> = Vec::new();
+    for (relay, result) in join_all(fetches).await {
+        match result {
+            Ok(found) => {
+                if found.len() >= limit {
+                    eprintln!(
+                        "{}: stopped at {} contact lists; there may be more followers",
+                        relay, limit
+                    );
+                }
+                events.extend(found);
+            }
+            Err(e) => eprintln!("{}: FAILED: {}", relay, e),
+        }
+    }
+    let mut candidates = newest_by_author(events);
+
+    // A relay may have returned an old list from someone who has since unfollowed
+    let authors: Vec<PublicKey> = candidates.values().map(|e| e.pubkey).collect();
+    for (author, event) in contact_lists(&authors, relays).await {
+        let newer = candidates
+            .get(&author)
+            .map(|c| replaces(&event, c))
+            .unwrap_or(false);
+        if newer {
+            candidates.insert(author, event);
+        }
+    }
+
+    let mut followers: Vec<(String, Event)> = candidates
+        .into_iter()
+        .filter(|(_, event)| parse_contact_list(event).iter().any(|c| c.pubkey == target))
+        .collect();
+    followers.sort_by(|a, b| a.0.cmp(&b.0));
+    followers
+}
+
+fn is_follow_of(tag: &[String], pubkey: &str) -> bool {
+    tag.first().map(|t| t.as_str()) == Some("p") && tag.get(1).map(|p| p.as_str()) == Some(pubkey)
+}
+
+/// Add a follow to a contact list's tags. An existing follow keeps its place and
+/// any fields it has, taking the new relay and petname only where given. Returns
+/// whether anything changed.
+pub fn follow(tags: &mut Vec<Vec<String>>, contact: &Contact) -> bool {
+    let index = match tags.iter().position(|t| is_follow_of(t, &contact.pubkey)) {
+        Some(index) => index,
+        None => {
+            let mut tag = vec!["p".to_owned(), contact.pubkey.clone()];
+            if contact.relay.is_some() || contact.petname.is_some() {
+                tag.push(contact.relay.clone().unwrap_or_default());
+            }
+            if let Some(petname) = &contact.petname {
+                tag.push(petname.clone());
+            }
+            tags.push(tag);
+            return true;
+        }
+    };
+
+    let tag = &mut tags[index];
+    let before = tag.clone();
+    let mut set = |i: usize, value: &String| {
+        while tag.len() <= i {
+            tag.push(String::new());
+        }
+        tag[i] = value.clone();
+    };
+    if let Some(relay) = &contact.relay {
+        set(2, relay);
+    }
+    if let Some(petname) = &contact.petname {
+        set(3, petname);
+    }
+    *tag != before
+}
+
+/// Remove every follow of `pubkey` (hex) from a contact list's tags, leaving all
+/// other tags as they are. Returns whether anything changed.
+pub fn unfollow(tags: &mut Vec<Vec<String>>, pubkey: &str) -> bool {
+    let before = tags.len();
+    tags.retain(|t| !is_follow_of(t, pubkey));
+    tags.len() != before
+}
+
+/// How many distinct pubkeys a contact list's tags follow
+pub fn follow_count(tags: &[Vec<String>]) -> usize {
+    let mut pubkeys: Vec<&String> = tags
+        .iter()
+        .filter(|t| t.first().map(|n| n.as_str()) == Some("p"))
+        .filter_map(|t| t.get(1))
+        .collect();
+    pubkeys.sort();
+    pubkeys.dedup();
+    pubkeys.len()
+}
+
+/// Whether going from `before` to `after` follows loses more than a few follows or
+/// more than a tenth of them, which is more likely a mistake than a decision
+pub fn shrinks_drastically(before: usize, after: usize) -> bool {
+    after < before && before - after > 5.max(before / 10)
+}
+
+/// Why going from the `before` tags to the `after` tags would be a mistake, if it
+/// shrinks drastically. A relay may only have a list some other client clobbered, or
+/// none at all, so the comparison is with our newest backup when that follows more.
+pub fn shrink_refusal(
+    before: &[Vec<String>],
+    after: &[Vec<String>],
+    backup: Option<&Event>,
+) -> Option<String> {
+    let count_before = follow_count(before);
+    let backed_up = backup.map(|e| follow_count(&tag_fields(e))).unwrap_or(0);
+    let reference = count_before.max(backed_up);
+    let count_after = follow_count(after);
+    if !shrinks_drastically(reference, count_after) {
+        return None;
+    }
+    Some(if backed_up > count_before {
+        format!(
+            "This would shrink your contact list from the {} follows in your newest backup \
+             to {}",
+            backed_up, count_after
+        )
+    } else {
+        format!(
+            "This would shrink your contact list from {} follows to {}",
+            count_before, count_after
+        )
+    })
+}
+
+/// The tags only in `before` and the tags only in `after`
+pub fn diff(before: &[Vec<String>], after: &[Vec<String>]) -> (Vec<Vec<String>>, Vec<Vec<String>>) {
+    let removed = before
+        .iter()
+        .filter(|t| !after.contains(t))
+        .cloned()
+        .collect();
+    let added = after
+        .iter()
+        .filter(|t| !before.contains(t))
+        .cloned()
+        .collect();
+    (removed, added)
+}
+
+// The list to publish: the edited tags exactly as they are, the old content, and a
+// time after the list it replaces, even if our clock is behind
+fn next_version(
+    pubkey: PublicKey,
+    tags: &[Vec<String>],
+    content: String,
+    previous: i64,
+) -> PreEvent {
+    PreEvent {
+        pubkey,
+        created_at: Unixtime(Unixtime::now().0.max(previous + 1)),
+        kind: EventKind::ContactList,
+        tags: tags
+            .iter()
+            .map(|t| Tag::new(&t.iter().map(|f| f.as_str()).collect::<Vec<&str>>()))
+            .collect(),
+        content,
+    }
+}
+
+/// Where the versions of a user's contact list are backed up
+pub fn backup_dir(pubkey: &PublicKey) -> Option<PathBuf> {
+    let mut dir = dirs::data_dir()?;
+    dir.push("nostr-probe");
+    dir.push("contact_lists");
+    dir.push(pubkey.as_hex_string());
+    Some(dir)
+}
+
+/// Save a version of a contact list, unless it is already saved
+pub fn backup(event: &Event) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let mut path = backup_dir(&event.pubkey)
+        .ok_or_else(|| std::io::Error::other("No data directory to keep backups in"))?;
+    std::fs::create_dir_all(&path)?;
+    path.push(format!(
+        "{}-{}.json",
+        event.created_at.0,
+        event.id.as_hex_string()
+    ));
+    if !path.exists() {
+        std::fs::write(&path, serde_json::to_vec_pretty(event)?)?;
+    }
+    Ok(path)
+}
+
+/// The newest backed up version of a user's contact list
+pub fn latest_backup(pubkey: &PublicKey) -> Option<Event> {
+    let entries = std::fs::read_dir(backup_dir(pubkey)?).ok()?;
+    entries
+        .filter_map(|entry| std::fs::read(entry.ok()?.path()).ok())
+        .filter_map(|bytes| serde_json::from_slice::<Event>(&bytes).ok())
+        .filter(|event| event.pubkey == *pubkey)
+        .max_by_key(|event| event.created_at.0)
+}
+
+/// How [`edit_contact_list`] finds, checks and publishes the list
+#[derive(Debug, Clone, Default)]
+pub struct EditOptions {
+    /// Relays to fetch the list from and publish it to. Defaults to the user's write
+    /// relays (and the bootstrap relays for fetching).
+    pub relays: Vec<String>,
+    /// Edit the newest list found even though some relays could not be checked for a
+    /// newer one
+    pub allow_unchecked: bool,
+    /// Start a new list if none is found
+    pub new_list: bool,
+    /// Publish even if the list shrinks drastically
+    pub allow_shrink: bool,
+    /// Don't ask before signing
+    pub yes: bool,
+}
+
+/// Fetch the user's newest contact list, let `change` edit its tags (returning
+/// whether it changed anything), show the difference and, once confirmed, sign and
+/// publish the result. The `content` is kept as it was. The old and new versions are
+/// both backed up.
+pub async fn edit_contact_list<F>(
+    options: &EditOptions,
+    change: F,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    F: FnOnce(&mut Vec<Vec<String>>) -> bool,
+{
+    let signer = crate::load_signer()?;
+    let me = signer.public_key();
+
+    // Fetch from everywhere the list might be, publish where the user writes. A relay
+    // that doesn't answer may have a newer list (or relay list) than those that do.
+    let mut fetch_from = relays_to_ask(&options.relays, &[]);
+    let mut publish_to = fetch_from.clone();
+    let mut unanswered: Vec<String> = Vec::new();
+    if options.relays.is_empty() {
+        let filter = Filter {
+            kinds: vec![EventKind::RelayList],
+            authors: vec![me],
+            ..Default::default()
+        };
+        let (relay_lists, failed) = crate::fetch_all_from(&fetch_from, vec![filter]).await;
+        unanswered.extend(failed);
+        let relay_list = relay_lists
+            .into_iter()
+            .filter(|e| e.kind == EventKind::RelayList && e.pubkey == me)
+            .reduce(|a, b| if replaces(&b, &a) { b } else { a });
+        if let Some(relay_list) = relay_list {
+            let write: Vec<String> = parse_relay_list(&relay_list)
+                .into_iter()
+                .filter(|e| e.write)
+                .map(|e| e.url)
+                .collect();
+            if !write.is_empty() {
+                for url in &write {
+                    if !fetch_from.contains(url) {
+                        fetch_from.push(url.clone());
+                    }
+                }
+                publish_to = write;
+            }
+        }
+    }
+
+    let filter = Filter {
+        kinds: vec![EventKind::ContactList],
+        authors: vec![me],
+        ..Default::default()
+    };
+    let (lists, failed) = crate::fetch_all_from(&fetch_from, vec![filter]).await;
+    for relay in failed {
+        if !unanswered.contains(&relay) {
+            unanswered.push(relay);
+        }
+    }
+    if !unanswered.is_empty() {
+        if !options.allow_unchecked {
+            return Err(format!(
+                "Could not check {} for your contact list. It may have a newer list than \
+                 the other relays; use --allow-unchecked to edit the newest list they have",
+                unanswered.join(", ")
+            )
+            .into());
+        }
+        eprintln!("Editing without checking {}", unanswered.join(", "));
+    }
+
+    let newest_backup = latest_backup(&me);
+    let current = newest_by_author(lists).remove(&me.as_hex_string());
+    let (mut tags, content, previous) = match &current {
+        Some(event) => {
+            let path = backup(event)?;
+            eprintln!("Backed up the current list to {}", path.display());
+            (tag_fields(event), event.content.clone(), event.created_at.0)
+        }
+        None if options.new_list => {
+            eprintln!("No contact list found, starting a new one");
+            (Vec::new(), String::new(), 0)
+        }
+        None => {
+            return Err(format!(
+                "No contact list found on {}. A new list would replace any list these \
+                 relays don't have; use --new-list to start one anyway",
+                fetch_from.join(", ")
+            )
+            .into())
+        }
+    };
+
+    let before = tags.clone();
+    if !change(&mut tags) {
+        eprintln!("Nothing to change");
+        return Ok(());
+    }
+
+    let (removed, added) = diff(&before, &tags);
+    for tag in &removed {
+        eprintln!("- {}", serde_json::to_string(tag)?);
+    }
+    for tag in &added {
+        eprintln!("+ {}", serde_json::to_string(tag)?);
+    }
+    let count_before = follow_count(&before);
+    let count_after = follow_count(&tags);
+    eprintln!("Follows: {} -> {}", count_before, count_after);
+
+    let backed_up = newest_backup
+        .as_ref()
+        .map(|e| follow_count(&tag_fields(e)))
+        .unwrap_or(0);
+    if backed_up > count_before {
+        eprintln!(
+            "Your newest backup follows {} people, more than the list on the relays",
+            backed_up
+        );
+    }
+    if let Some(refusal) = shrink_refusal(&before, &tags, newest_backup.as_ref()) {
+        if !options.allow_shrink {
+            return Err(format!("{}; use --allow-shrink if that is what you want", refusal).into());
+        }
+        eprintln!("{}", refusal);
+    }
+
+    if !options.yes {
+        eprint!("Sign and publish to {}? [y/N] ", publish_to.join(", "));
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if !answer.trim().eq_ignore_ascii_case("y") {
+            eprintln!("Not published");
+            return Ok(());
+        }
+    }
+
+    let event = signer.sign_event(next_version(me, &tags, content, previous))?;
+    backup(&event)?;
+
+    let event = &event;
+    let posts = publish_to
+        .iter()
+        .map(|relay| async move { (relay, crate::post_event(relay, event).await) });
+    let mut accepted: usize = 0;
+    for (relay, result) in join_all(posts).await {
+        match result {
+            Ok((true, _)) => {
+                accepted += 1;
+                eprintln!("{}: accepted", relay);
+            }
+            Ok((false, message)) => eprintln!("{}: rejected: {}", relay, message),
+            Err(e) => eprintln!("{}: FAILED: {}", relay, e),
+        }
+    }
+    println!("{}", serde_json::to_string(event)?);
+
+    if accepted == 0 {
+        return Err("No relay accepted the new contact list".into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_types::{KeySigner, PrivateKey};
+
+    fn tags(tags: &[&[&str]]) -> Vec<Vec<String>> {
+        tags.iter()
+            .map(|t| t.iter().map(|f| f.to_string()).collect())
+            .collect()
+    }
+
+    fn contact(pubkey: &str, relay: Option<&str>, petname: Option<&str>) -> Contact {
+        Contact {
+            pubkey: pubkey.to_owned(),
+            relay: relay.map(|r| r.to_owned()),
+            petname: petname.map(|p| p.to_owned()),
+        }
+    }
+
+    // Hex pubkeys are all the same to these helpers
+    const A: &str = "aa";
+    const B: &str = "bb";
+    const C: &str = "cc";
+
+    #[test]
+    fn follow_adds_at_the_end_and_keeps_other_tags() {
+        let mut list = tags(&[&["p", A], &["t", "nostr"], &["p", B, "wss://b", "bob", "x"]]);
+        assert!(follow(&mut list, &contact(C, None, None)));
+        assert!(follow(&mut list, &contact("dd", None, Some("dee"))));
+        assert_eq!(
+            list,
+            tags(&[
+                &["p", A],
+                &["t", "nostr"],
+                &["p", B, "wss://b", "bob", "x"],
+                &["p", C],
+                &["p", "dd", "", "dee"],
+            ])
+        );
+    }
+
+    #[test]
+    fn follow_updates_in_place() {
+        let mut list = tags(&[&["p", A], &["p", B, "wss://b", "bob", "x"]]);
+
+        // Following again with nothing new changes nothing
+        assert!(!follow(&mut list, &contact(B, None, None)));
+        assert!(!follow(
+            &mut list,
+            &contact(B, Some("wss://b"), Some("bob"))
+        ));
+
+        // New fields replace old ones and the rest of the tag stays
+        assert!(follow(&mut list, &contact(B, None, Some("robert"))));
+        assert!(follow(&mut list, &contact(A, Some("wss://a"), None)));
+        assert_eq!(
+            list,
+            tags(&[&["p", A, "wss://a"], &["p", B, "wss://b", "robert", "x"]])
+        );
+    }
+
+    #[test]
+    fn unfollow_removes_every_follow_and_nothing_else() {
+        let mut list = tags(&[
+            &["p", A],
+            &["e", A],
+            &["p", B],
+            &["p", A, "wss://a"],
+            &["t", A],
+        ]);
+        assert!(unfollow(&mut list, A));
+        assert_eq!(list, tags(&[&["e", A], &["p", B], &["t", A]]));
+        assert!(!unfollow(&mut list, A));
+        assert!(!unfollow(&mut list, C));
+    }
+
+    #[test]
+    fn follow_count_counts_distinct_pubkeys() {
+        let list = tags(&[
+            &["p", A],
+            &["p", A, "wss://a"],
+            &["p", B],
+            &["t", C],
+            &["p"],
+        ]);
+        assert_eq!(follow_count(&list), 2);
+        assert_eq!(follow_count(&[]), 0);
+    }
+
+    #[test]
+    fn diff_lists_removed_and_added_tags() {
+        let before = tags(&[&["p", A], &["p", B, "wss://b"], &["t", "nostr"]]);
+        let after = tags(&[&["p", A], &["t", "nostr"], &["p", B, "wss://b2"], &["p", C]]);
+        let (removed, added) = diff(&before, &after);
+        assert_eq!(removed, tags(&[&["p", B, "wss://b"]]));
+        assert_eq!(added, tags(&[&["p", B, "wss://b2"], &["p", C]]));
+        assert_eq!(diff(&before, &before), (vec![], vec![]));
+    }
+
+    #[test]
+    fn shrink_threshold() {
+        // Growing or staying the same is never drastic
+        assert!(!shrinks_drastically(0, 0));
+        assert!(!shrinks_drastically(10, 10));
+        assert!(!shrinks_drastically(10, 500));
+
+        // Small lists may lose up to five follows
+        assert!(!shrinks_drastically(10, 5));
+        assert!(shrinks_drastically(10, 4));
+        assert!(!shrinks_drastically(5, 0));
+        assert!(shrinks_drastically(6, 0));
+
+        // Large lists may lose up to a tenth
+        assert!(!shrinks_drastically(1000, 900));
+        assert!(shrinks_drastically(1000, 899));
+        assert!(shrinks_drastically(1000, 0));
+    }
+
+    #[test]
+    fn shrinking_is_measured_against_the_backup_too() {
+        let author = crate::conformance::Author::new();
+        let follows: Vec<String> = (0..50).map(|i| format!("{:064x}", i)).collect();
+        let backup_tags: Vec<Tag> = follows.iter().map(|p| Tag::new(&["p", p])).collect();
+        let backup = author.sign(3, 1, backup_tags, "");
+        let one = tags(&[&["p", &follows[0]]]);
+
+        // Every fetch came back empty, so a new list of one follow would replace 50
+        let refusal = shrink_refusal(&[], &one, Some(&backup)).unwrap();
+        assert!(
+            refusal.contains("50 follows in your newest backup"),
+            "{}",
+            refusal
+        );
+
+        // Without a backup there is nothing to lose
+        assert!(shrink_refusal(&[], &one, None).is_none());
+
+        // The relays' list counts when it is the larger
+        let current: Vec<Vec<String>> = follows
+            .iter()
+            .map(|p| vec!["p".to_owned(), p.clone()])
+            .collect();
+        let small = author.sign(3, 1, vec![], "");
+        assert!(shrink_refusal(&current, &one, Some(&small))
+            .unwrap()
+            .contains("from 50 follows"));
+        assert!(shrink_refusal(&current, &current[1..], Some(&backup)).is_none());
+    }
+
+    #[test]
+    fn next_version_keeps_tags_and_content() {
+        let private_key = PrivateKey::generate();
+        let pubkey = private_key.public_key();
+        let signer = KeySigner::from_private_key(private_key, "pass", 8).unwrap();
+
+        let list = tags(&[
+            &["p", &"ab".repeat(32), "wss://relay.example", "pet"],
+            &["t", "nostr"],
+            &["p", &"cd".repeat(32), "", "", "extra"],
+        ]);
+        let content = r#"{"wss://relay.example":{"read":true,"write":true}}"#;
+        let future = Unixtime::now().0 + 3600;
+        let pre_event = next_version(pubkey, &list, content.to_owned(), future);
+        let event = signer.sign_event(pre_event).unwrap();
+
+        assert_eq!(tag_fields(&event), list);
+        assert_eq!(event.content, content);
+        assert_eq!(event.kind, EventKind::ContactList);
+        assert_eq!(event.created_at.0, future + 1);
+        assert!(event.verify(None).is_ok());
+
+        let old = next_version(pubkey, &list, String::new(), 0);
+        assert!(old.created_at.0 >= Unixtime::now().0 - 5);
+    }
+}
@@ -0,0 +1,355 @@
+use clap::Parser;
+use nostr_types::{Event, Filter, Unixtime};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+
+/// Matches events against a `Filter` locally, with the semantics a relay uses:
+/// every field that is present must match, and within a field any value may match.
+///
+/// This works from the filter's JSON form so that it follows the wire format exactly.
+#[derive(Debug, Clone, Default)]
+pub struct FilterMatcher {
+    pub(crate) ids: Option<HashSet<String>>,
+    pub(crate) authors: Option<HashSet<String>>,
+    pub(crate) kinds: Option<HashSet<u32>>,
+    pub(crate) tags: HashMap<String, HashSet<String>>,
+    pub(crate) since: Option<i64>,
+    pub(crate) until: Option<i64>,
+    pub(crate) limit: Option<usize>,
+}
+
+impl FilterMatcher {
+    pub fn new(filter: &Filter) -> FilterMatcher {
+        let value = serde_json::to_value(filter).unwrap_or_default();
+        let mut matcher = FilterMatcher::default();
+        let object = match value.as_object() {
+            Some(o) => o,
+            None => return matcher,
+        };
+
+        let strings = |v: &serde_json::Value| -> HashSet<String> {
+            v.as_array()
+                .map(|a| {
+                    a.iter()
+                        .filter_map(|s| s.as_str().map(|s| s.to_owned()))
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        for (key, v) in object {
+            match key.as_str() {
+                "ids" => matcher.ids = Some(strings(v)),
+                "authors" => matcher.authors = Some(strings(v)),
+                "kinds" => {
+                    matcher.kinds = Some(
+                        v.as_array()
+                            .map(|a| {
+                                a.iter()
+                                    .filter_map(|k| k.as_u64().map(|k| k as u32))
+                                    .collect()
+                            })
+                            .unwrap_or_default(),
+                    )
+                }
+                "since" => matcher.since = v.as_i64(),
+                "until" => matcher.until = v.as_i64(),
+                "limit" => matcher.limit = v.as_u64().map(|l| l as usize),
+                k if k.starts_with('#') && k.len() > 1 => {
+                    matcher.tags.insert(k[1..].to_owned(), strings(v));
+                }
+                _ => {}
+            }
+        }
+
+        matcher
+    }
+
+    /// The filter's limit, which applies to a query and not to single events
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    pub fn matches(&self, event: &Event) -> bool {
+        if let Some(ids) = &self.ids {
+            // Relays accept id prefixes, so we do too
+            let id = event.id.as_hex_string();
+            if !ids.iter().any(|i| id.starts_with(i.as_str())) {
+                return false;
+            }
+        }
+        if let Some(authors) = &self.authors {
+            let author = event.pubkey.as_hex_string();
+            if !authors.iter().any(|a| author.starts_with(a.as_str())) {
+                return false;
+            }
+        }
+        if let Some(kinds) = &self.kinds {
+            if !kinds.contains(&Into::<u32>::into(event.kind)) {
+                return false;
+            }
+        }
+        if let Some(since) = self.since {
+            if event.created_at.0 < since {
+                return false;
+            }
+        }
+        if let Some(until) = self.until {
+            if event.created_at.0 > until {
+                return false;
+            }
+        }
+        if !self.tags.is_empty() {
+            let event_tags = tag_fields(event);
+            for (name, values) in &self.tags {
+                let found = event_tags.iter().any(|t| {
+                    t.first() == Some(name) && t.get(1).map(|v| values.contains(v)) == Some(true)
+                });
+                if !found {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+/// The fields of each of an event's tags, as plain strings
+pub fn tag_fields(event: &Event) -> Vec<Vec<String>> {
+    serde_json::to_value(&event.tags)
+        .ok()
+        .and_then(|v| serde_json::from_value(v).ok())
+        .unwrap_or_default()
+}
+
+/// Flags that build a filter, for tools that take filters on the command line.
+/// Each flag narrows the filter; repeating a flag allows more values for that field.
+/// With [`parse_with_or`], `--or` starts another filter.
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct FilterArgs {
+    /// Event id, as hex, note or nevent (may be repeated)
+    #[arg(long)]
+    pub id: Vec<String>,
+
+    /// Author, as hex, npub or nprofile (may be repeated)
+    #[arg(long)]
+    pub author: Vec<String>,
+
+    /// Event kind (may be repeated)
+    #[arg(long)]
+    pub kind: Vec<u32>,
+
+    /// Tag value as NAME=VALUE, e.g. `t=nostr` (may be repeated). Values of `e` and
+    /// `p` tags may also be given as NIP-19.
+    #[arg(long)]
+    pub tag: Vec<String>,
+
+    /// Only events at or after this time: unix seconds, a UTC date such as
+    /// `2024-01-31` or `2024-01-31 12:00`, or a relative time such as `2h ago`
+    #[arg(long)]
+    pub since: Option<String>,
+
+    /// Only events at or before this time, in the same forms as --since
+    #[arg(long)]
+    pub until: Option<String>,
+
+    /// At most this many events
+    #[arg(long)]
+    pub limit: Option<usize>,
+
+    /// NIP-50 full text search
+    #[arg(long)]
+    pub search: Option<String>,
+}
+
+impl FilterArgs {
+    pub fn is_empty(&self) -> bool {
+        self.id.is_empty()
+            && self.author.is_empty()
+            && self.kind.is_empty()
+            && self.tag.is_empty()
+            && self.since.is_none()
+            && self.until.is_none()
+            && self.limit.is_none()
+            && self.search.is_none()
+    }
+
+    /// The filter the flags describe, in its wire form (which, unlike `Filter`, can
+    /// carry a NIP-50 `search`). None if no flags were given.
+    pub fn to_json(&self) -> Result<Option<serde_json::Value>, Box<dyn std::error::Error>> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+
+        let mut filter = Filter::new();
+        for id in &self.id {
+            filter.add_id(crate::nip19::parse_id(id)?.id);
+        }
+        for author in &self.author {
+            filter.add_author(crate::nip19::parse_pubkey(author)?.pubkey);
+        }
+        for kind in &self.kind {
+            filter.add_event_kind((*kind).into());
+        }
+        for tag in &self.tag {
+            let (name, value) = tag.split_once('=').ok_or_else(|| {
+                std::io::Error::other(format!("Expected --tag NAME=VALUE, got {}", tag))
+            })?;
+            let mut chars = name.chars();
+            let name = match (chars.next(), chars.next()) {
+                (Some(c), None) if c.is_ascii_alphabetic() => c,
+                _ => {
+                    return Err(Box::new(std::io::Error::other(format!(
+                        "Tag names in filters are single letters, got {}",
+                        name
+                    ))))
+                }
+            };
+            let value = match name {
+                'e' => crate::nip19::parse_id(value)?.id.as_hex_string(),
+                'p' => crate::nip19::parse_pubkey(value)?.pubkey.as_hex_string(),
+                _ => value.to_owned(),
+            };
+            filter.add_tag_value(name, value);
+        }
+        if let Some(since) = &self.since {
+            filter.since = Some(Unixtime(crate::parse_unixtime(since)?));
+        }
+        if let Some(until) = &self.until {
+            filter.until = Some(Unixtime(crate::parse_unixtime(until)?));
+        }
+        filter.limit = self.limit;
+
+        let mut value = serde_json::to_value(&filter)?;
+        if let (Some(search), Some(object)) = (&self.search, value.as_object_mut()) {
+            object.insert("search".to_owned(), search.clone().into());
+        }
+        Ok(Some(value))
+    }
+}
+
+/// The separator between filters built from flags
+pub const OR: &str = "--or";
+
+// The flags of a filter after an `--or`
+#[derive(Parser, Debug)]
+#[command(no_binary_name = true)]
+struct OrFilter {
+    #[command(flatten)]
+    filter: FilterArgs,
+}
+
+/// Parse the command line of a tool whose flags build filters, where each `--or`
+/// starts another filter. Returns the tool's arguments, which hold the first
+/// filter, and the flags of each filter after it. Exits with a usage message on
+/// bad arguments, like `Parser::parse`.
+pub fn parse_with_or<P: Parser>() -> (P, Vec<FilterArgs>) {
+    let mut groups = split_at_or(std::env::args_os());
+    let first = P::parse_from(groups.remove(0));
+    let more = groups
+        .into_iter()
+        .map(|group| match OrFilter::try_parse_from(group) {
+            Ok(or) => or.filter,
+            Err(e) => e.exit(),
+        })
+        .collect();
+    (first, more)
+}
+
+// Split arguments at each `--or`, dropping the separators
+fn split_at_or<I: IntoIterator<Item = OsString>>(args: I) -> Vec<Vec<OsString>> {
+    let mut groups: Vec<Vec<OsString>> = vec![Vec::new()];
+    for arg in args {
+        if arg == OR {
+            groups.push(Vec::new());
+        } else {
+            groups.last_mut().unwrap().push(arg);
+        }
+    }
+    groups
+}
+
+/// The wire form of each filter built from flags. The first may be empty (when all
+/// filters were given as JSON), but every filter after an `--or` needs a flag.
+pub fn filters_to_json(
+    first: &FilterArgs,
+    more: &[FilterArgs],
+) -> Result<Vec<serde_json::Value>, Box<dyn std::error::Error>> {
+    let mut filters: Vec<serde_json::Value> = Vec::new();
+    filters.extend(first.to_json()?);
+    for filter in more {
+        match filter.to_json()? {
+            Some(f) => filters.push(f),
+            None => {
+                return Err(Box::new(std::io::Error::other(format!(
+                    "{} must be followed by filter flags",
+                    OR
+                ))))
+            }
+        }
+    }
+    Ok(filters)
+}
+
+/// Parse a filter given as JSON, keeping fields `Filter` doesn't know (like `search`)
+pub fn parse_filter_json(s: &str) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let value: serde_json::Value = serde_json::from_str(s)?;
+    if !value.is_object() {
+        return Err(Box::new(std::io::Error::other(format!(
+            "A filter must be a JSON object, got {}",
+            s
+        ))));
+    }
+    // Check the fields Filter does know
+    let _: Filter = serde_json::from_value(value.clone())?;
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Vec<OsString> {
+        s.split_whitespace().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn or_splits_the_flags() {
+        let groups = split_at_or(args("fetch wss://r --kind 1 --or --kind 7 --limit 5 --or"));
+        assert_eq!(
+            groups,
+            vec![
+                args("fetch wss://r --kind 1"),
+                args("--kind 7 --limit 5"),
+                args("")
+            ]
+        );
+        assert_eq!(
+            split_at_or(args("fetch wss://r")),
+            vec![args("fetch wss://r")]
+        );
+    }
+
+    #[test]
+    fn each_or_makes_a_filter() {
+        let parse = |s: &str| OrFilter::try_parse_from(args(s)).unwrap().filter;
+        let first = FilterArgs {
+            kind: vec![1],
+            ..Default::default()
+        };
+        let more = [parse("--kind 7 --limit 5"), parse("--kind 0 --kind 3")];
+        let filters = filters_to_json(&first, &more).unwrap();
+        let fields: Vec<String> = filters
+            .iter()
+            .map(|f| format!("{} {}", f["kinds"], f["limit"]))
+            .collect();
+        assert_eq!(fields, ["[1] null", "[7] 5", "[0,3] null"]);
+
+        // All JSON filters and no flags is fine, an empty --or is not
+        assert!(filters_to_json(&FilterArgs::default(), &[])
+            .unwrap()
+            .is_empty());
+        assert!(filters_to_json(&first, &[parse("")]).is_err());
+        assert!(OrFilter::try_parse_from(args("{\"kinds\":[1]}")).is_err());
+    }
+}
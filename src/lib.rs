@@ -8,11 +8,31 @@ use nostr_types::{
     ClientMessage, EncryptedPrivateKey, Event, EventKind, Filter, Id, KeySigner, PreEvent,
     RelayMessage, Signer, SubscriptionId, Tag, Unixtime, Why,
 };
+use std::collections::HashSet;
 use tokio::sync::mpsc::{Receiver, Sender};
 use tungstenite::Message;
 use zeroize::Zeroize;
 
-type Ws =
+pub mod conformance;
+pub mod contacts;
+pub mod filter;
+pub mod localtime;
+pub mod mock_relay;
+pub mod negentropy;
+pub mod nip10;
+pub mod nip11;
+pub mod nip19;
+pub mod outbox;
+pub mod output;
+pub mod recording;
+pub mod render;
+pub mod report;
+pub mod store;
+pub mod verify;
+
+use negentropy::NegMessage;
+
+pub type Ws =
     tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
 
 pub struct Prefixes {
@@ -22,22 +42,76 @@ pub struct Prefixes {
 
 lazy_static! {
     pub static ref PREFIXES: Prefixes = Prefixes {
-        from_relay: "Relay".color(Color::Blue).to_string(),
-        sending: "Sending".color(Color::MediumPurple).to_string(),
+        from_relay: paint("Relay", Color::Blue, true),
+        sending: paint("Sending", Color::MediumPurple, true),
     };
 }
 
+/// Color text for a terminal, or leave it plain when `enabled` is false (such as
+/// when output is going to a file)
+pub fn paint(s: &str, color: Color, enabled: bool) -> String {
+    if enabled {
+        s.color(color).to_string()
+    } else {
+        s.to_owned()
+    }
+}
+
 pub enum Command {
     PostEvent(Event),
     Auth(Event),
     FetchEvents(SubscriptionId, Vec<Filter>),
     CountEvents(SubscriptionId, Vec<Filter>),
+    Close(SubscriptionId),
+    NegOpen(SubscriptionId, Filter, String),
+    NegMsg(SubscriptionId, String),
+    NegClose(SubscriptionId),
+    /// Send a text frame exactly as given
+    Raw(String),
     Exit,
 }
 
+/// A text frame from the relay that could not be parsed as a `RelayMessage`
+#[derive(Debug, Clone)]
+pub struct Unparsed {
+    pub frame: String,
+    pub error: String,
+}
+
 pub struct Probe {
     pub from_main: tokio::sync::mpsc::Receiver<Command>,
     pub to_main: tokio::sync::mpsc::Sender<RelayMessage>,
+
+    /// Where NIP-77 NEG-MSG and NEG-ERR messages go. These are dropped if not set.
+    pub to_main_neg: Option<tokio::sync::mpsc::Sender<NegMessage>>,
+
+    /// If set, every text frame from the relay is passed here unparsed instead of
+    /// being handled as a `RelayMessage`
+    pub to_main_raw: Option<tokio::sync::mpsc::Sender<String>>,
+
+    /// Where frames that are not valid relay messages go. These are dropped
+    /// (after being displayed and counted) if not set. In strict mode the frame
+    /// is passed here before the session ends.
+    pub to_main_unparsed: Option<tokio::sync::mpsc::Sender<Unparsed>>,
+
+    /// End the session on the first frame that is not a valid relay message
+    pub strict: bool,
+
+    /// Don't print every frame sent and received, for tools that time the relay
+    pub quiet: bool,
+
+    /// How long the connection may go without a frame either way before the probe
+    /// gives up. Defaults to the connect timeout.
+    pub idle_timeout: Option<std::time::Duration>,
+
+    /// How many frames could not be parsed
+    pub unparsed_count: usize,
+
+    /// Record every frame sent and received to this session file. Defaults to
+    /// the file named by the NOSTR_PROBE_RECORD environment variable.
+    pub record_path: Option<std::path::PathBuf>,
+
+    recorder: Option<recording::Recorder>,
 }
 
 impl Probe {
@@ -45,7 +119,19 @@ impl Probe {
         from_main: tokio::sync::mpsc::Receiver<Command>,
         to_main: tokio::sync::mpsc::Sender<RelayMessage>,
     ) -> Probe {
-        Probe { from_main, to_main }
+        Probe {
+            from_main,
+            to_main,
+            to_main_neg: None,
+            to_main_raw: None,
+            to_main_unparsed: None,
+            strict: false,
+            quiet: false,
+            idle_timeout: None,
+            unparsed_count: 0,
+            record_path: std::env::var_os(recording::RECORD_ENV_VAR).map(|p| p.into()),
+            recorder: None,
+        }
     }
 
     pub async fn connect_and_listen(
@@ -60,29 +146,16 @@ impl Probe {
         relay_url: &str,
         timeout_secs: u64,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let (host, uri) = url_to_host_and_uri(relay_url);
-
-        let key: [u8; 16] = rand::random();
-        let request = http::request::Request::builder()
-            .method("GET")
-            .header("Host", host)
-            .header("Connection", "Upgrade")
-            .header("Upgrade", "websocket")
-            .header("Sec-WebSocket-Version", "13")
-            .header(
-                "Sec-WebSocket-Key",
-                base64::engine::general_purpose::STANDARD.encode(key),
-            )
-            .uri(uri)
-            .body(())?;
-
-        let (mut websocket, _response) = tokio::time::timeout(
-            std::time::Duration::new(timeout_secs, 0),
-            tokio_tungstenite::connect_async(request),
-        )
-        .await??;
+        let mut websocket = connect_websocket(relay_url, timeout_secs).await?;
+
+        if let Some(path) = &self.record_path {
+            self.recorder = Some(recording::Recorder::open(path, relay_url)?);
+        }
 
-        let mut timeout_timer = tokio::time::interval(std::time::Duration::new(timeout_secs, 0));
+        let idle_timeout = self
+            .idle_timeout
+            .unwrap_or(std::time::Duration::new(timeout_secs, 0));
+        let mut timeout_timer = tokio::time::interval(idle_timeout);
         timeout_timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
         timeout_timer.tick().await; // use up the first immediate tick.
 
@@ -92,6 +165,7 @@ impl Probe {
                     return Ok(()); // timed out
                 },
                 local_message = self.from_main.recv() => {
+                    timeout_timer.reset();
                     match local_message {
                         Some(Command::PostEvent(event)) => {
                             let client_message = ClientMessage::Event(Box::new(event));
@@ -117,6 +191,32 @@ impl Probe {
                             let msg = Message::Text(wire);
                             self.send(&mut websocket, msg).await?;
                         },
+                        Some(Command::Close(subid)) => {
+                            let client_message = ClientMessage::Close(subid);
+                            let wire = serde_json::to_string(&client_message)?;
+                            let msg = Message::Text(wire);
+                            self.send(&mut websocket, msg).await?;
+                        },
+                        Some(Command::NegOpen(subid, filter, message)) => {
+                            let wire =
+                                serde_json::json!(["NEG-OPEN", subid.as_str(), filter, message]);
+                            let msg = Message::Text(wire.to_string());
+                            self.send(&mut websocket, msg).await?;
+                        },
+                        Some(Command::NegMsg(subid, message)) => {
+                            let wire = serde_json::json!(["NEG-MSG", subid.as_str(), message]);
+                            let msg = Message::Text(wire.to_string());
+                            self.send(&mut websocket, msg).await?;
+                        },
+                        Some(Command::NegClose(subid)) => {
+                            let wire = serde_json::json!(["NEG-CLOSE", subid.as_str()]);
+                            let msg = Message::Text(wire.to_string());
+                            self.send(&mut websocket, msg).await?;
+                        },
+                        Some(Command::Raw(wire)) => {
+                            let msg = Message::Text(wire);
+                            self.send(&mut websocket, msg).await?;
+                        },
                         Some(Command::Exit) => {
                             break;
                         },
@@ -134,15 +234,58 @@ impl Probe {
                         }
                     }?;
 
+                    // The timeout is an idle timeout, so long fetches are not cut off
+                    timeout_timer.reset();
+
+                    if let (Some(recorder), Message::Text(s)) = (&mut self.recorder, &message) {
+                        recorder.record(recording::Direction::Received, s)?;
+                    }
+
+                    // In raw mode text frames go to main untouched
+                    if let Some(to_main_raw) = &self.to_main_raw {
+                        if let Message::Text(s) = &message {
+                            to_main_raw.send(s.clone()).await?;
+                            continue;
+                        }
+                    }
+
                     // Display it
-                    Self::display(message.clone())?;
+                    if !self.quiet {
+                        Self::display(message.clone())?;
+                    }
 
                     // Take action
                     match message {
                         Message::Text(s) => {
+                            // NIP-77 messages are not RelayMessages
+                            if let Some(neg_message) = NegMessage::from_wire(&s) {
+                                if let Some(to_main_neg) = &self.to_main_neg {
+                                    to_main_neg.send(neg_message).await?;
+                                }
+                                continue;
+                            }
+
                             // Send back to main
-                            let relay_message: RelayMessage = serde_json::from_str(&s)?;
-                            self.to_main.send(relay_message).await?;
+                            match serde_json::from_str::<RelayMessage>(&s) {
+                                Ok(relay_message) => self.to_main.send(relay_message).await?,
+                                Err(e) => {
+                                    self.unparsed_count += 1;
+                                    // Main hears about the frame even when it ends the session
+                                    if let Some(to_main_unparsed) = &self.to_main_unparsed {
+                                        let unparsed = Unparsed {
+                                            frame: s.clone(),
+                                            error: e.to_string(),
+                                        };
+                                        to_main_unparsed.send(unparsed).await?;
+                                    }
+                                    if self.strict {
+                                        return Err(Box::new(std::io::Error::other(format!(
+                                            "Unparseable frame from relay ({}): {}",
+                                            e, s
+                                        ))));
+                                    }
+                                }
+                            }
                         },
                         Message::Binary(_) => { },
                         Message::Ping(_) => { },
@@ -154,6 +297,17 @@ impl Probe {
             }
         }
 
+        if self.unparsed_count > 0 {
+            eprintln!(
+                "{}",
+                format!(
+                    "{} frames from the relay could not be parsed",
+                    self.unparsed_count
+                )
+                .color(Color::Orange1)
+            );
+        }
+
         // Send close message before disconnecting
         let msg = Message::Close(None);
         self.send(&mut websocket, msg).await?;
@@ -164,7 +318,40 @@ impl Probe {
     fn display(message: Message) -> Result<(), Box<dyn std::error::Error>> {
         match message {
             Message::Text(s) => {
-                let relay_message: RelayMessage = serde_json::from_str(&s)?;
+                if let Some(neg_message) = NegMessage::from_wire(&s) {
+                    match neg_message {
+                        NegMessage::Msg(sub, msg) => {
+                            eprintln!(
+                                "{}: NEG-MSG({}, {})",
+                                PREFIXES.from_relay,
+                                sub.as_str(),
+                                msg
+                            );
+                        }
+                        NegMessage::Err(sub, reason) => {
+                            eprintln!(
+                                "{}: NEG-ERR({}, {})",
+                                PREFIXES.from_relay,
+                                sub.as_str(),
+                                reason
+                            );
+                        }
+                    }
+                    return Ok(());
+                }
+                let relay_message: RelayMessage = match serde_json::from_str(&s) {
+                    Ok(m) => m,
+                    Err(e) => {
+                        eprintln!(
+                            "{}: {}({}, {})",
+                            PREFIXES.from_relay,
+                            "UNPARSEABLE".color(Color::Orange1),
+                            e,
+                            s
+                        );
+                        return Ok(());
+                    }
+                };
                 match relay_message {
                     RelayMessage::Auth(challenge) => {
                         eprintln!("{}: AUTH({})", PREFIXES.from_relay, challenge);
@@ -234,18 +421,53 @@ impl Probe {
         websocket: &mut Ws,
         message: Message,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        match message {
-            Message::Text(ref s) => eprintln!("{}: Text({})", PREFIXES.sending, s),
-            Message::Binary(_) => eprintln!("{}: Binary(_)", PREFIXES.sending),
-            Message::Ping(_) => eprintln!("{}: Ping(_)", PREFIXES.sending),
-            Message::Pong(_) => eprintln!("{}: Pong(_)", PREFIXES.sending),
-            Message::Close(_) => eprintln!("{}: Close(_)", PREFIXES.sending),
-            Message::Frame(_) => eprintln!("{}: Frame(_)", PREFIXES.sending),
+        if !self.quiet {
+            match message {
+                Message::Text(ref s) => eprintln!("{}: Text({})", PREFIXES.sending, s),
+                Message::Binary(_) => eprintln!("{}: Binary(_)", PREFIXES.sending),
+                Message::Ping(_) => eprintln!("{}: Ping(_)", PREFIXES.sending),
+                Message::Pong(_) => eprintln!("{}: Pong(_)", PREFIXES.sending),
+                Message::Close(_) => eprintln!("{}: Close(_)", PREFIXES.sending),
+                Message::Frame(_) => eprintln!("{}: Frame(_)", PREFIXES.sending),
+            }
+        }
+        if let (Some(recorder), Message::Text(s)) = (&mut self.recorder, &message) {
+            recorder.record(recording::Direction::Sent, s)?;
         }
         Ok(websocket.send(message).await?)
     }
 }
 
+/// Open a websocket to a relay, without any nostr handling
+pub async fn connect_websocket(
+    relay_url: &str,
+    timeout_secs: u64,
+) -> Result<Ws, Box<dyn std::error::Error>> {
+    let (host, uri) = url_to_host_and_uri(relay_url);
+
+    let key: [u8; 16] = rand::random();
+    let request = http::request::Request::builder()
+        .method("GET")
+        .header("Host", host)
+        .header("Connection", "Upgrade")
+        .header("Upgrade", "websocket")
+        .header("Sec-WebSocket-Version", "13")
+        .header(
+            "Sec-WebSocket-Key",
+            base64::engine::general_purpose::STANDARD.encode(key),
+        )
+        .uri(uri)
+        .body(())?;
+
+    let (websocket, _response) = tokio::time::timeout(
+        std::time::Duration::new(timeout_secs, 0),
+        tokio_tungstenite::connect_async(request),
+    )
+    .await??;
+
+    Ok(websocket)
+}
+
 pub fn url_to_host_and_uri(url: &str) -> (String, Uri) {
     let uri: http::Uri = url.parse::<http::Uri>().expect("Could not parse url");
     let authority = uri.authority().expect("Has no hostname").as_str();
@@ -259,6 +481,35 @@ pub fn url_to_host_and_uri(url: &str) -> (String, Uri) {
     (host.to_owned(), uri)
 }
 
+/// Fetch a relay's NIP-11 information document
+pub fn fetch_nip11(relay_url: &str) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let (host, uri) = url_to_host_and_uri(relay_url);
+
+    let scheme = match uri.scheme() {
+        Some(refscheme) => match refscheme.as_str() {
+            "wss" => "https",
+            "ws" => "http",
+            u => panic!("Unknown scheme {}", u),
+        },
+        None => panic!("Relay URL has no scheme."),
+    };
+
+    let client = reqwest::blocking::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .connect_timeout(Some(std::time::Duration::from_secs(60)))
+        .timeout(Some(std::time::Duration::from_secs(60)))
+        .connection_verbose(true)
+        .build()?;
+    let response = client
+        .get(format!("{}://{}", scheme, host))
+        .header("Host", host)
+        .header("Accept", "application/nostr+json")
+        .send()?;
+    let json = response.text()?;
+    let value: serde_json::Value = serde_json::from_str(&json)?;
+    Ok(value)
+}
+
 pub fn load_signer() -> Result<KeySigner, Box<dyn std::error::Error>> {
     let mut config_dir = match dirs::config_dir() {
         Some(cd) => cd,
@@ -287,15 +538,18 @@ pub fn load_signer() -> Result<KeySigner, Box<dyn std::error::Error>> {
     Ok(signer)
 }
 
+/// REQ `filter`, AUTHing with `signer` if the relay asks, and print the events.
+/// Returns how many events were printed.
 pub async fn req(
     relay_url: &str,
-    signer: KeySigner,
+    signer: &KeySigner,
     filter: Filter,
     to_probe: Sender<Command>,
     mut from_probe: Receiver<RelayMessage>,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<usize, Box<dyn std::error::Error>> {
     let pubkey = signer.public_key();
     let mut authenticated: Option<Id> = None;
+    let mut printed: usize = 0;
 
     let our_sub_id = SubscriptionId("subscription-id".to_string());
     to_probe
@@ -332,7 +586,8 @@ pub async fn req(
             }
             RelayMessage::Event(sub, e) => {
                 if sub == our_sub_id {
-                    println!("{}", serde_json::to_string(&e)?);
+                    output::event(output::Source::new(relay_url, &sub.0), &e)?;
+                    printed += 1;
                 }
             }
             RelayMessage::Closed(sub, _) => {
@@ -382,5 +637,558 @@ pub async fn req(
         }
     }
 
-    Ok(())
+    Ok(printed)
+}
+
+/// Walks backwards through time fetching every event matching a filter.
+///
+/// Relays cap how many events they return for a REQ. After each EOSE we reissue the
+/// filter with `until` set to the oldest `created_at` seen on that page, and stop once
+/// a page adds nothing new. `until` is inclusive, so events sharing the oldest
+/// timestamp come back again on the next page and are deduplicated by id. If an
+/// entire page shares one timestamp we step one second past it so we cannot get stuck.
+///
+/// If the relay CLOSEs the subscription, sends a NOTICE or goes away part way, the
+/// events received so far are returned and the next call fails, so a cut off fetch
+/// is never taken for a complete one.
+pub struct Pager {
+    sub_prefix: String,
+    filter: Filter,
+    seen: HashSet<Id>,
+    page: usize,
+    done: bool,
+    failure: Option<String>,
+}
+
+impl Pager {
+    pub fn new(sub_prefix: &str, filter: Filter) -> Pager {
+        Pager {
+            sub_prefix: sub_prefix.to_owned(),
+            filter,
+            seen: HashSet::new(),
+            page: 0,
+            done: false,
+            failure: None,
+        }
+    }
+
+    /// The filter that the next page will be requested with. Its `until` marks how far
+    /// back we have gotten.
+    pub fn filter(&self) -> &Filter {
+        &self.filter
+    }
+
+    /// The number of distinct events seen so far
+    pub fn seen(&self) -> usize {
+        self.seen.len()
+    }
+
+    /// The subscription id the next page will be requested on
+    pub fn subscription_id(&self) -> SubscriptionId {
+        SubscriptionId(format!("{}-{}", self.sub_prefix, self.page))
+    }
+
+    // Whether a subscription is one of the pages requested so far
+    fn is_ours(&self, sub: &SubscriptionId) -> bool {
+        sub.0
+            .strip_prefix(&self.sub_prefix)
+            .and_then(|rest| rest.strip_prefix('-'))
+            .and_then(|page| page.parse::<usize>().ok())
+            .map(|page| page <= self.page)
+            .unwrap_or(false)
+    }
+
+    /// Fetch the next page, returning only events not seen on earlier pages.
+    /// Returns None once there is nothing more to fetch, or an error if the previous
+    /// page was cut off. The probe is left running.
+    pub async fn next_page(
+        &mut self,
+        to_probe: &Sender<Command>,
+        from_probe: &mut Receiver<RelayMessage>,
+    ) -> Result<Option<Vec<Event>>, Box<dyn std::error::Error>> {
+        if let Some(failure) = self.failure.take() {
+            self.done = true;
+            return Err(Box::new(std::io::Error::other(failure)));
+        }
+        if self.done {
+            return Ok(None);
+        }
+
+        let our_sub_id = self.subscription_id();
+        to_probe
+            .send(Command::FetchEvents(
+                our_sub_id.clone(),
+                vec![self.filter.clone()],
+            ))
+            .await?;
+
+        let mut page_count: usize = 0;
+        let mut new_events: Vec<Event> = Vec::new();
+        let mut oldest: Option<Unixtime> = None;
+
+        loop {
+            let relay_message = match from_probe.recv().await {
+                Some(m) => m,
+                None => {
+                    self.failure = Some("the connection ended before the fetch did".to_owned());
+                    return Ok(Some(new_events));
+                }
+            };
+            match relay_message {
+                RelayMessage::Eose(sub) => {
+                    if sub == our_sub_id {
+                        to_probe.send(Command::Close(our_sub_id)).await?;
+                        break;
+                    }
+                }
+                RelayMessage::Event(sub, e) => {
+                    // Late events from earlier pages are still welcome
+                    if self.is_ours(&sub) {
+                        if sub == our_sub_id {
+                            page_count += 1;
+                            if oldest.map(|o| e.created_at < o).unwrap_or(true) {
+                                oldest = Some(e.created_at);
+                            }
+                        }
+                        if self.seen.insert(e.id) {
+                            new_events.push(*e);
+                        }
+                    }
+                }
+                RelayMessage::Closed(sub, reason) => {
+                    if sub == our_sub_id {
+                        self.failure = Some(format!("CLOSED: {}", reason));
+                        return Ok(Some(new_events));
+                    }
+                }
+                RelayMessage::Notice(notice) => {
+                    self.failure = Some(format!("NOTICE: {}", notice));
+                    return Ok(Some(new_events));
+                }
+                _ => {}
+            }
+        }
+
+        let oldest = match oldest {
+            Some(o) => o,
+            None => {
+                // empty page, we have reached the beginning
+                self.done = true;
+                return Ok(Some(new_events));
+            }
+        };
+
+        if new_events.is_empty() && self.filter.until == Some(oldest) && oldest.0 > 0 {
+            // Either we are done, or more events share this timestamp than the relay
+            // will return in one page. Step past it to find out.
+            eprintln!(
+                "Page {} added nothing new at created_at={}; stepping past it",
+                self.page, oldest.0
+            );
+            self.filter.until = Some(Unixtime(oldest.0 - 1));
+        } else if new_events.is_empty() {
+            self.done = true;
+        } else {
+            self.filter.until = Some(oldest);
+        }
+
+        if let (Some(since), Some(until)) = (self.filter.since, self.filter.until) {
+            if until < since {
+                self.done = true;
+            }
+        }
+
+        if !self.done {
+            eprintln!(
+                "Page {} returned {} events ({} new), continuing until={}",
+                self.page,
+                page_count,
+                new_events.len(),
+                oldest.0
+            );
+        }
+        self.page += 1;
+
+        Ok(Some(new_events))
+    }
+}
+
+/// Fetch every event matching `filter` using a [`Pager`], passing each new event
+/// (and the subscription it was requested on) to `on_event`. Returns the number of
+/// distinct events seen, or an error if the relay cut the fetch short. The probe is
+/// told to exit when we are done.
+pub async fn req_paged<F>(
+    sub_prefix: &str,
+    filter: Filter,
+    to_probe: &Sender<Command>,
+    from_probe: &mut Receiver<RelayMessage>,
+    mut on_event: F,
+) -> Result<usize, Box<dyn std::error::Error>>
+where
+    F: FnMut(&str, Event) -> Result<(), Box<dyn std::error::Error>>,
+{
+    let mut pager = Pager::new(sub_prefix, filter);
+    let mut result: Result<(), Box<dyn std::error::Error>> = Ok(());
+    while result.is_ok() {
+        let sub = pager.subscription_id();
+        result = match pager.next_page(to_probe, from_probe).await {
+            Ok(Some(events)) => events.into_iter().try_for_each(|e| on_event(&sub.0, e)),
+            Ok(None) => break,
+            Err(e) => Err(e),
+        };
+    }
+
+    // The probe may already have exited if the relay went away
+    let _ = to_probe.send(Command::Exit).await;
+
+    result?;
+    Ok(pager.seen())
+}
+
+/// Connect to a relay, fetch every event matching any of `filters` (paging through
+/// those without a limit) and disconnect. Events that fail verification are dropped.
+/// If the relay CLOSEs a subscription, sends a NOTICE or goes away before the fetch
+/// is done, this fails rather than return what it got so far.
+pub async fn fetch_all(
+    relay_url: &str,
+    filters: Vec<Filter>,
+) -> Result<Vec<Event>, Box<dyn std::error::Error>> {
+    let (to_probe, from_main) = tokio::sync::mpsc::channel::<Command>(100);
+    let (to_main, mut from_probe) = tokio::sync::mpsc::channel::<RelayMessage>(100);
+    let url = relay_url.to_owned();
+    let join_handle = tokio::spawn(async move {
+        let mut probe = Probe::new(from_main, to_main);
+        if let Err(e) = probe.connect_and_listen(&url).await {
+            eprintln!("{}: {}", url, e);
+        }
+    });
+
+    let result = fetch_filters(&to_probe, &mut from_probe, filters).await;
+
+    // The probe may already have exited if the relay went away
+    let _ = to_probe.send(Command::Exit).await;
+    join_handle.await?;
+
+    result
+}
+
+/// Run [`fetch_all`] on every relay at once, merging what they return. Relays that
+/// fail are reported and skipped, and returned alongside the events.
+pub async fn fetch_all_from(relays: &[String], filters: Vec<Filter>) -> (Vec<Event>, Vec<String>) {
+    let fetches = relays.iter().map(|relay| {
+        let filters = filters.clone();
+        async move { (relay, fetch_all(relay, filters).await) }
+    });
+    let mut events: Vec<Event> = Vec::new();
+    let mut failed: Vec<String> = Vec::new();
+    for (relay, result) in futures_util::future::join_all(fetches).await {
+        match result {
+            Ok(e) => events.extend(e),
+            Err(e) => {
+                eprintln!("{}: FAILED: {}", relay, e);
+                failed.push(relay.clone());
+            }
+        }
+    }
+    (events, failed)
+}
+
+// The body of fetch_all, on an open connection
+async fn fetch_filters(
+    to_probe: &Sender<Command>,
+    from_probe: &mut Receiver<RelayMessage>,
+    filters: Vec<Filter>,
+) -> Result<Vec<Event>, Box<dyn std::error::Error>> {
+    let mut seen: HashSet<Id> = HashSet::new();
+    let mut events: Vec<Event> = Vec::new();
+    let mut keep = |event: Event| {
+        if event.verify(None).is_ok() && seen.insert(event.id) {
+            events.push(event);
+        }
+    };
+
+    for (n, filter) in filters.into_iter().enumerate() {
+        if filter.limit.is_none() {
+            let mut pager = Pager::new(&format!("fetch_all-{}", n), filter);
+            while let Some(page) = pager.next_page(to_probe, from_probe).await? {
+                page.into_iter().for_each(&mut keep);
+            }
+            continue;
+        }
+
+        let sub = SubscriptionId(format!("fetch_all-{}", n));
+        to_probe
+            .send(Command::FetchEvents(sub.clone(), vec![filter]))
+            .await?;
+        loop {
+            match from_probe.recv().await {
+                Some(RelayMessage::Event(s, e)) if s == sub => keep(*e),
+                Some(RelayMessage::Eose(s)) if s == sub => {
+                    to_probe.send(Command::Close(sub)).await?;
+                    break;
+                }
+                Some(RelayMessage::Closed(s, reason)) if s == sub => {
+                    return Err(Box::new(std::io::Error::other(format!(
+                        "CLOSED: {}",
+                        reason
+                    ))));
+                }
+                Some(RelayMessage::Notice(notice)) => {
+                    return Err(Box::new(std::io::Error::other(format!(
+                        "NOTICE: {}",
+                        notice
+                    ))));
+                }
+                Some(_) => {}
+                None => {
+                    return Err(Box::new(std::io::Error::other(
+                        "the connection ended before the fetch did",
+                    )));
+                }
+            }
+        }
+    }
+
+    Ok(events)
+}
+
+/// Connect to a relay, publish `event` and disconnect, returning the relay's OK flag
+/// and message
+pub async fn post_event(
+    relay_url: &str,
+    event: &Event,
+) -> Result<(bool, String), Box<dyn std::error::Error>> {
+    let (to_probe, from_main) = tokio::sync::mpsc::channel::<Command>(100);
+    let (to_main, mut from_probe) = tokio::sync::mpsc::channel::<RelayMessage>(100);
+    let url = relay_url.to_owned();
+    let join_handle = tokio::spawn(async move {
+        let mut probe = Probe::new(from_main, to_main);
+        if let Err(e) = probe.connect_and_listen(&url).await {
+            eprintln!("{}: {}", url, e);
+        }
+    });
+
+    to_probe.send(Command::PostEvent(event.clone())).await?;
+    let result = loop {
+        match from_probe.recv().await {
+            Some(RelayMessage::Ok(id, ok, message)) if id == event.id => break Ok((ok, message)),
+            Some(RelayMessage::Notice(notice)) => {
+                break Err(std::io::Error::other(format!("NOTICE: {}", notice)))
+            }
+            Some(_) => {}
+            None => {
+                break Err(std::io::Error::other(format!(
+                    "connection to {} ended before it answered",
+                    relay_url
+                )))
+            }
+        }
+    };
+    let _ = to_probe.send(Command::Exit).await;
+    join_handle.await?;
+
+    Ok(result?)
+}
+
+/// The machine-readable prefix of an OK or CLOSED message (e.g. `duplicate`,
+/// `rate-limited`), if it has one
+pub fn reason_prefix(message: &str) -> Option<&str> {
+    let (prefix, _) = message.split_once(':')?;
+    if !prefix.is_empty() && prefix.bytes().all(|b| b.is_ascii_lowercase() || b == b'-') {
+        Some(prefix)
+    } else {
+        None
+    }
+}
+
+/// A unix time as `YYYY-MM-DD HH:MM:SS` UTC
+pub fn format_unixtime(secs: i64) -> String {
+    let days = secs.div_euclid(86400);
+    let rem = secs.rem_euclid(86400);
+
+    // Days since the epoch to a civil date (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// Parse a time given on the command line: unix seconds, `now`, a relative time such
+/// as `2h ago` or `3 days ago`, or a UTC date as `2024-01-31`, `2024-01-31 12:00` or
+/// `2024-01-31T12:00:00Z`
+pub fn parse_unixtime(s: &str) -> Result<i64, Box<dyn std::error::Error>> {
+    let s = s.trim();
+    let bad = || -> Box<dyn std::error::Error> {
+        Box::new(std::io::Error::other(format!(
+            "Could not parse time {:?}, expected unix seconds, a date or e.g. \"2h ago\"",
+            s
+        )))
+    };
+
+    if s == "now" {
+        return Ok(Unixtime::now().0);
+    }
+    if let Ok(secs) = s.parse::<i64>() {
+        return Ok(secs);
+    }
+
+    if let Some(ago) = s.strip_suffix("ago") {
+        let ago = ago.trim();
+        let split = ago.find(|c: char| !c.is_ascii_digit()).unwrap_or(ago.len());
+        let (amount, unit) = ago.split_at(split);
+        let amount: i64 = amount.parse().map_err(|_| bad())?;
+        let unit_secs: i64 = match unit.trim() {
+            "s" | "sec" | "secs" | "second" | "seconds" => 1,
+            "m" | "min" | "mins" | "minute" | "minutes" => 60,
+            "h" | "hr" | "hrs" | "hour" | "hours" => 3600,
+            "d" | "day" | "days" => 86400,
+            "w" | "week" | "weeks" => 7 * 86400,
+            "y" | "year" | "years" => 365 * 86400,
+            _ => return Err(bad()),
+        };
+        return amount
+            .checked_mul(unit_secs)
+            .and_then(|secs| Unixtime::now().0.checked_sub(secs))
+            .ok_or_else(bad);
+    }
+
+    let (date, time) = match s.split_once(['T', ' ']) {
+        Some((date, time)) => (date, time.trim().trim_end_matches('Z')),
+        None => (s, ""),
+    };
+    let date: Vec<i64> = date
+        .split('-')
+        .map(|p| p.parse::<i64>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| bad())?;
+    let time: Vec<i64> = if time.is_empty() {
+        vec![]
+    } else {
+        time.split(':')
+            .map(|p| p.parse::<i64>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| bad())?
+    };
+    let (year, month, day) = match date[..] {
+        [y, m, d] if (0..=9999).contains(&y) && (1..=12).contains(&m) => (y, m, d),
+        _ => return Err(bad()),
+    };
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    if !(1..=days_in_month).contains(&day) {
+        return Err(bad());
+    }
+    let (hour, minute, second) = match time[..] {
+        [] => (0, 0, 0),
+        [h, m] => (h, m, 0),
+        [h, m, s] => (h, m, s),
+        _ => return Err(bad()),
+    };
+    if !(0..=23).contains(&hour) || !(0..=59).contains(&minute) || !(0..=60).contains(&second) {
+        return Err(bad());
+    }
+
+    let days = localtime::days_from_civil(year, month, day);
+    Ok(days * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pager_only_claims_its_own_pages() {
+        let mut pager = Pager::new("fetch_all-1", Filter::new());
+        pager.page = 2;
+        let sub = |s: &str| SubscriptionId(s.to_owned());
+        assert!(pager.is_ours(&sub("fetch_all-1-0")));
+        assert!(pager.is_ours(&sub("fetch_all-1-2")));
+        assert!(!pager.is_ours(&sub("fetch_all-1-3")));
+        assert!(!pager.is_ours(&sub("fetch_all-10-0")));
+        assert!(!pager.is_ours(&sub("fetch_all-1")));
+        assert!(!pager.is_ours(&sub("fetch_all-1-x")));
+    }
+
+    #[test]
+    fn parse_unixtime_dates() {
+        let parse = |s: &str| parse_unixtime(s).unwrap();
+        assert_eq!(parse("1700000000"), 1_700_000_000);
+        assert_eq!(parse("1970-01-01"), 0);
+        assert_eq!(parse("2024-01-31"), 1_706_659_200);
+        assert_eq!(parse("2024-01-31 12:00"), 1_706_702_400);
+        assert_eq!(parse("2024-01-31T12:00:30Z"), 1_706_702_430);
+        assert_eq!(parse("1969-12-31 23:59:59"), -1);
+
+        // Leap days exist only in leap years
+        assert_eq!(parse("2024-02-29"), 1_709_164_800);
+        assert_eq!(parse("2000-02-29"), 951_782_400);
+        for bad in [
+            "2023-02-29",
+            "1900-02-29",
+            "2024-02-30",
+            "2024-02-31",
+            "2024-04-31",
+        ] {
+            assert!(parse_unixtime(bad).is_err(), "{} was accepted", bad);
+        }
+        for bad in [
+            "2024-13-01",
+            "2024-00-10",
+            "2024-01-00",
+            "2024-01-32",
+            "2024-01",
+        ] {
+            assert!(parse_unixtime(bad).is_err(), "{} was accepted", bad);
+        }
+        for bad in [
+            "2024-01-31 24:00",
+            "2024-01-31 12:60",
+            "2024-01-31 -3:00",
+            "2024-01-31 12:-5",
+            "2024-01-31 12:00:-1",
+            "2024-01-31 12",
+            "tomorrow",
+        ] {
+            assert!(parse_unixtime(bad).is_err(), "{} was accepted", bad);
+        }
+    }
+
+    #[test]
+    fn parse_unixtime_relative() {
+        let now = Unixtime::now().0;
+        let close = |s: &str, expected: i64| {
+            let parsed = parse_unixtime(s).unwrap();
+            assert!((parsed - expected).abs() <= 5, "{} gave {}", s, parsed);
+        };
+        close("now", now);
+        close("90s ago", now - 90);
+        close("2h ago", now - 2 * 3600);
+        close("3 days ago", now - 3 * 86400);
+        close("1 week ago", now - 7 * 86400);
+
+        // Too large to compute must be an error, not an overflow
+        assert!(parse_unixtime("9223372036854775807 years ago").is_err());
+        assert!(parse_unixtime("99999999999999999999 s ago").is_err());
+        assert!(parse_unixtime("5 fortnights ago").is_err());
+    }
 }
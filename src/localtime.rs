@@ -0,0 +1,417 @@
+//! The local time zone, read from the system's TZif files (RFC 8536) rather than
+//! taken from a dependency.
+//!
+//! The zone is the file named by `TZ` (a path, or a name under
+//! `/usr/share/zoneinfo`), else `/etc/localtime`. Times past the file's last
+//! transition follow the POSIX TZ rule in its footer. If none of this can be
+//! read, there is no local time and callers stay with UTC.
+
+use lazy_static::lazy_static;
+use std::path::PathBuf;
+
+const ZONEINFO: &str = "/usr/share/zoneinfo";
+
+lazy_static! {
+    static ref LOCAL: Option<Zone> = Zone::load();
+}
+
+/// The offset from UTC in seconds and the zone abbreviation (like `CET`) in force
+/// locally at `secs`, or None if the local zone is unknown
+pub fn offset_at(secs: i64) -> Option<(i64, String)> {
+    LOCAL.as_ref().map(|zone| zone.offset_at(secs))
+}
+
+// An offset from UTC and its abbreviation
+type LocalType = (i64, String);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Zone {
+    // Transition times and the index of the type that starts at each
+    transitions: Vec<(i64, usize)>,
+    types: Vec<LocalType>,
+    footer: Option<Rule>,
+}
+
+impl Zone {
+    fn load() -> Option<Zone> {
+        let path = match std::env::var("TZ") {
+            Ok(tz) if !tz.is_empty() => {
+                let name = tz.trim_start_matches(':');
+                if name.starts_with('/') {
+                    PathBuf::from(name)
+                } else {
+                    PathBuf::from(ZONEINFO).join(name)
+                }
+            }
+            _ => PathBuf::from("/etc/localtime"),
+        };
+        Zone::parse(&std::fs::read(path).ok()?)
+    }
+
+    fn parse(data: &[u8]) -> Option<Zone> {
+        let mut at = 0;
+        let mut time_size = 4;
+        loop {
+            if data.get(at..at + 4)? != b"TZif" {
+                return None;
+            }
+            let version = *data.get(at + 4)?;
+            let count = |i: usize| -> Option<usize> {
+                let bytes = data.get(at + 20 + 4 * i..at + 24 + 4 * i)?;
+                Some(u32::from_be_bytes(bytes.try_into().ok()?) as usize)
+            };
+            let (isut, isstd, leaps) = (count(0)?, count(1)?, count(2)?);
+            let (times, types, chars) = (count(3)?, count(4)?, count(5)?);
+            at += 44;
+
+            // Version 2 and later repeat the data with 64-bit times; skip to that
+            if time_size == 4 && version >= b'2' {
+                at += times * 5 + types * 6 + chars + leaps * 8 + isstd + isut;
+                time_size = 8;
+                continue;
+            }
+
+            let mut transitions: Vec<(i64, usize)> = Vec::with_capacity(times);
+            for i in 0..times {
+                let bytes = data.get(at + i * time_size..at + (i + 1) * time_size)?;
+                let time = if time_size == 8 {
+                    i64::from_be_bytes(bytes.try_into().ok()?)
+                } else {
+                    i32::from_be_bytes(bytes.try_into().ok()?) as i64
+                };
+                let index = *data.get(at + times * time_size + i)? as usize;
+                transitions.push((time, index));
+            }
+            at += times * (time_size + 1);
+
+            let abbreviations = data.get(at + types * 6..at + types * 6 + chars)?;
+            let mut local_types: Vec<LocalType> = Vec::with_capacity(types);
+            for i in 0..types {
+                let record = data.get(at + i * 6..at + i * 6 + 6)?;
+                let offset = i32::from_be_bytes(record[0..4].try_into().ok()?) as i64;
+                let start = (record[5] as usize).min(abbreviations.len());
+                let name = abbreviations[start..].split(|c| *c == 0).next()?;
+                local_types.push((offset, String::from_utf8_lossy(name).into_owned()));
+            }
+            if local_types.is_empty() || transitions.iter().any(|(_, i)| *i >= types) {
+                return None;
+            }
+            at += types * 6 + chars + leaps * (time_size + 4) + isstd + isut;
+
+            // The footer is the rule as a POSIX TZ string, between newlines
+            let footer = match data.get(at..) {
+                Some([b'\n', rest @ ..]) if time_size == 8 => rest
+                    .split(|c| *c == b'\n')
+                    .next()
+                    .and_then(|s| std::str::from_utf8(s).ok())
+                    .and_then(Rule::parse),
+                _ => None,
+            };
+
+            return Some(Zone {
+                transitions,
+                types: local_types,
+                footer,
+            });
+        }
+    }
+
+    fn offset_at(&self, secs: i64) -> LocalType {
+        let after = self.transitions.partition_point(|(t, _)| *t <= secs);
+        if after == self.transitions.len() {
+            if let Some(rule) = &self.footer {
+                return rule.offset_at(secs);
+            }
+        }
+        match after {
+            0 => self.types[0].clone(),
+            n => self.types[self.transitions[n - 1].1].clone(),
+        }
+    }
+}
+
+// A POSIX TZ rule like `CET-1CEST,M3.5.0,M10.5.0/3`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    standard: LocalType,
+    daylight: Option<(LocalType, Change, Change)>,
+}
+
+// When daylight saving starts or ends: the `week`th (5 for the last) `weekday`
+// (0 for Sunday) of `month`, at `time` seconds past local midnight
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Change {
+    month: i64,
+    week: i64,
+    weekday: i64,
+    time: i64,
+}
+
+impl Rule {
+    fn parse(s: &str) -> Option<Rule> {
+        let mut rest = s;
+        let standard_name = name(&mut rest)?;
+        // POSIX offsets are west of UTC, so the sign is the other way around
+        let standard = (-seconds(&mut rest)?, standard_name);
+        if rest.is_empty() {
+            return Some(Rule {
+                standard,
+                daylight: None,
+            });
+        }
+        let daylight_name = name(&mut rest)?;
+        let daylight_offset = if rest.starts_with(',') {
+            standard.0 + 3600
+        } else {
+            -seconds(&mut rest)?
+        };
+        let start = change(&mut rest)?;
+        let end = change(&mut rest)?;
+        if !rest.is_empty() {
+            return None;
+        }
+        Some(Rule {
+            standard,
+            daylight: Some(((daylight_offset, daylight_name), start, end)),
+        })
+    }
+
+    fn offset_at(&self, secs: i64) -> LocalType {
+        let (daylight, start, end) = match &self.daylight {
+            Some(d) => d,
+            None => return self.standard.clone(),
+        };
+        let year = year_of(secs + self.standard.0);
+        // The start is given in standard time and the end in daylight time
+        let starts = start.local_time(year) - self.standard.0;
+        let ends = end.local_time(year) - daylight.0;
+        let in_daylight = if starts < ends {
+            starts <= secs && secs < ends
+        } else {
+            // Southern hemisphere: daylight time spans the new year
+            !(ends <= secs && secs < starts)
+        };
+        if in_daylight {
+            daylight.clone()
+        } else {
+            self.standard.clone()
+        }
+    }
+}
+
+impl Change {
+    // Seconds since the epoch of the change in `year`, as local time
+    fn local_time(&self, year: i64) -> i64 {
+        let first = days_from_civil(year, self.month, 1);
+        let next_month = if self.month == 12 {
+            days_from_civil(year + 1, 1, 1)
+        } else {
+            days_from_civil(year, self.month + 1, 1)
+        };
+        let first_weekday = (first + 4).rem_euclid(7);
+        let mut day = first + (self.weekday - first_weekday).rem_euclid(7) + (self.week - 1) * 7;
+        while day >= next_month {
+            day -= 7;
+        }
+        day * 86400 + self.time
+    }
+}
+
+// A zone name: letters, or anything between < and >
+fn name(rest: &mut &str) -> Option<String> {
+    let s = *rest;
+    let (name, after) = match s.strip_prefix('<') {
+        Some(quoted) => {
+            let end = quoted.find('>')?;
+            (&quoted[..end], &quoted[end + 1..])
+        }
+        None => {
+            let end = s
+                .find(|c: char| !c.is_ascii_alphabetic())
+                .unwrap_or(s.len());
+            s.split_at(end)
+        }
+    };
+    if name.len() < 3 {
+        return None;
+    }
+    *rest = after;
+    Some(name.to_owned())
+}
+
+// A signed [+|-]hh[:mm[:ss]]
+fn seconds(rest: &mut &str) -> Option<i64> {
+    let s = *rest;
+    let sign = match s.chars().next()? {
+        '-' => -1,
+        _ => 1,
+    };
+    let unsigned = s.trim_start_matches(['+', '-']);
+    let end = unsigned
+        .find(|c: char| !c.is_ascii_digit() && c != ':')
+        .unwrap_or(unsigned.len());
+    let mut total = 0;
+    let mut scale = 3600;
+    for part in unsigned[..end].split(':') {
+        if scale == 0 {
+            return None;
+        }
+        total += part.parse::<i64>().ok()? * scale;
+        scale /= 60;
+    }
+    *rest = &unsigned[end..];
+    Some(sign * total)
+}
+
+// A `,Mm.w.d[/time]` change. The Julian day forms are not supported.
+fn change(rest: &mut &str) -> Option<Change> {
+    let s = *rest;
+    let after = s.strip_prefix(",M")?;
+    let end = after
+        .find(|c: char| c != '.' && !c.is_ascii_digit())
+        .unwrap_or(after.len());
+    let fields: Vec<i64> = after[..end]
+        .split('.')
+        .map(|f| f.parse().ok())
+        .collect::<Option<_>>()?;
+    let (month, week, weekday) = match fields[..] {
+        [m, w, d] if (1..=12).contains(&m) && (1..=5).contains(&w) && (0..=6).contains(&d) => {
+            (m, w, d)
+        }
+        _ => return None,
+    };
+    let mut after = &after[end..];
+    let time = match after.strip_prefix('/') {
+        Some(time) => {
+            after = time;
+            seconds(&mut after)?
+        }
+        None => 2 * 3600,
+    };
+    *rest = after;
+    Some(Change {
+        month,
+        week,
+        weekday,
+        time,
+    })
+}
+
+/// A civil date to days since the epoch (Howard Hinnant's algorithm)
+pub(crate) fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn year_of(secs: i64) -> i64 {
+    let mut year = 1970 + secs.div_euclid(86400 * 365);
+    while days_from_civil(year, 1, 1) * 86400 > secs {
+        year -= 1;
+    }
+    while days_from_civil(year + 1, 1, 1) * 86400 <= secs {
+        year += 1;
+    }
+    year
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A version 2 TZif file with the given transitions, types and footer
+    fn tzif(transitions: &[(i64, u8)], types: &[(i32, &str)], footer: &str) -> Vec<u8> {
+        let mut chars: Vec<u8> = Vec::new();
+        let mut records: Vec<u8> = Vec::new();
+        for (offset, name) in types {
+            records.extend(offset.to_be_bytes());
+            records.push(0);
+            records.push(chars.len() as u8);
+            chars.extend(name.as_bytes());
+            chars.push(0);
+        }
+        let header = |data: &mut Vec<u8>| {
+            data.extend(b"TZif2");
+            data.extend([0; 15]);
+            for count in [0, 0, 0, transitions.len(), types.len(), chars.len()] {
+                data.extend((count as u32).to_be_bytes());
+            }
+        };
+        let mut data: Vec<u8> = Vec::new();
+        header(&mut data);
+        for (time, _) in transitions {
+            data.extend((*time as i32).to_be_bytes());
+        }
+        data.extend(transitions.iter().map(|(_, i)| *i));
+        data.extend(&records);
+        data.extend(&chars);
+        header(&mut data);
+        for (time, _) in transitions {
+            data.extend(time.to_be_bytes());
+        }
+        data.extend(transitions.iter().map(|(_, i)| *i));
+        data.extend(&records);
+        data.extend(&chars);
+        data.extend(format!("\n{}\n", footer).as_bytes());
+        data
+    }
+
+    fn utc(date: &str) -> i64 {
+        crate::parse_unixtime(date).unwrap()
+    }
+
+    #[test]
+    fn transitions_and_footer() {
+        // Berlin, with only the 2020 changes listed and the rule for the rest
+        let data = tzif(
+            &[(utc("2020-03-29 01:00"), 1), (utc("2020-10-25 01:00"), 0)],
+            &[(3600, "CET"), (7200, "CEST")],
+            "CET-1CEST,M3.5.0,M10.5.0/3",
+        );
+        let zone = Zone::parse(&data).unwrap();
+        let cet = (3600, "CET".to_owned());
+        let cest = (7200, "CEST".to_owned());
+
+        // Before the first transition, and from the transitions themselves
+        assert_eq!(zone.offset_at(utc("2019-07-01")), cet);
+        assert_eq!(zone.offset_at(utc("2020-03-29 00:59:59")), cet);
+        assert_eq!(zone.offset_at(utc("2020-03-29 01:00")), cest);
+
+        // From the footer, on either side of each change
+        assert_eq!(zone.offset_at(utc("2024-03-31 00:59:59")), cet);
+        assert_eq!(zone.offset_at(utc("2024-03-31 01:00")), cest);
+        assert_eq!(zone.offset_at(utc("2024-10-27 00:59:59")), cest);
+        assert_eq!(zone.offset_at(utc("2024-10-27 01:00")), cet);
+    }
+
+    #[test]
+    fn southern_rules_and_fixed_zones() {
+        let sydney = Rule::parse("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        assert_eq!(sydney.offset_at(utc("2024-01-15")).1, "AEDT");
+        assert_eq!(sydney.offset_at(utc("2024-07-15")).1, "AEST");
+        // Daylight time starts at 02:00 AEST on 6 October, which is 16:00 UTC the day before
+        assert_eq!(sydney.offset_at(utc("2024-10-05 15:59:59")).1, "AEST");
+        assert_eq!(sydney.offset_at(utc("2024-10-05 16:00")).1, "AEDT");
+
+        let new_york = Rule::parse("EST5EDT,M3.2.0,M11.1.0").unwrap();
+        assert_eq!(
+            new_york.offset_at(utc("2024-07-04")),
+            (-4 * 3600, "EDT".to_owned())
+        );
+        assert_eq!(
+            new_york.offset_at(utc("2024-12-25")),
+            (-5 * 3600, "EST".to_owned())
+        );
+
+        let kolkata = Rule::parse("IST-5:30").unwrap();
+        assert_eq!(kolkata.offset_at(0), (5 * 3600 + 1800, "IST".to_owned()));
+        let quoted = Rule::parse("<+0330>-3:30").unwrap();
+        assert_eq!(quoted.standard, (3 * 3600 + 1800, "+0330".to_owned()));
+
+        assert_eq!(Rule::parse("EST5EDT,J60,J300"), None);
+        assert_eq!(Zone::parse(b"not a zone file"), None);
+    }
+}
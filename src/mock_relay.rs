@@ -0,0 +1,142 @@
+//! A relay that runs in-process, for testing tools without touching real relays.
+//!
+//! It keeps every event it is sent (without checking signatures), answers a REQ
+//! with the matching events newest first, up to the filter's limit, and can be
+//! made slow to answer or not answer EVENTs at all.
+
+use crate::filter::FilterMatcher;
+use futures_util::{SinkExt, StreamExt};
+use nostr_types::{Event, Filter};
+use serde_json::{json, Value};
+use std::cmp::Reverse;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tungstenite::Message;
+
+/// How a [`MockRelay`] answers EVENTs
+#[derive(Debug, Clone, Default)]
+pub struct Behaviour {
+    /// How long to wait before each OK
+    pub ok_delay: Duration,
+
+    /// Never send an OK
+    pub silent: bool,
+}
+
+pub struct MockRelay {
+    /// The ws:// url to connect to
+    pub url: String,
+    events: Arc<Mutex<Vec<Event>>>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl MockRelay {
+    /// Start listening on a free local port, holding `events` to begin with
+    pub async fn start(events: Vec<Event>, behaviour: Behaviour) -> std::io::Result<MockRelay> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("ws://{}", listener.local_addr()?);
+        let events = Arc::new(Mutex::new(events));
+        let shared = events.clone();
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let events = shared.clone();
+                let behaviour = behaviour.clone();
+                tokio::spawn(async move {
+                    let _ = serve(stream, events, behaviour).await;
+                });
+            }
+        });
+        Ok(MockRelay { url, events, task })
+    }
+
+    /// Every event the relay holds
+    pub fn events(&self) -> Vec<Event> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockRelay {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve(
+    stream: tokio::net::TcpStream,
+    events: Arc<Mutex<Vec<Event>>>,
+    behaviour: Behaviour,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut websocket = tokio_tungstenite::accept_async(stream).await?;
+
+    while let Some(message) = websocket.next().await {
+        let text = match message? {
+            Message::Text(s) => s,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        let array: Vec<Value> = match serde_json::from_str(&text) {
+            Ok(a) => a,
+            Err(e) => {
+                let notice = json!(["NOTICE", format!("invalid: {}", e)]);
+                websocket.send(Message::Text(notice.to_string())).await?;
+                continue;
+            }
+        };
+
+        let replies: Vec<Value> = match array.first().and_then(|v| v.as_str()) {
+            Some("EVENT") => {
+                let event: Event = serde_json::from_value(array[1].clone())?;
+                let mut events = events.lock().unwrap();
+                let reply = if events.iter().any(|e| e.id == event.id) {
+                    json!(["OK", event.id.as_hex_string(), true, "duplicate: have it"])
+                } else {
+                    let id = event.id.as_hex_string();
+                    events.push(event);
+                    json!(["OK", id, true, ""])
+                };
+                if behaviour.silent {
+                    vec![]
+                } else {
+                    vec![reply]
+                }
+            }
+            Some("REQ") => {
+                let sub = array[1].clone();
+                let mut replies = Vec::new();
+                for filter in &array[2..] {
+                    let filter: Filter = serde_json::from_value(filter.clone())?;
+                    for event in query(&events.lock().unwrap(), &filter) {
+                        replies.push(json!(["EVENT", sub, event]));
+                    }
+                }
+                replies.push(json!(["EOSE", sub]));
+                replies
+            }
+            _ => vec![],
+        };
+
+        for reply in replies {
+            if reply[0] == "OK" && !behaviour.ok_delay.is_zero() {
+                tokio::time::sleep(behaviour.ok_delay).await;
+            }
+            websocket.send(Message::Text(reply.to_string())).await?;
+        }
+    }
+
+    Ok(())
+}
+
+// The events matching a filter, newest first and up to its limit
+fn query(events: &[Event], filter: &Filter) -> Vec<Event> {
+    let matcher = FilterMatcher::new(filter);
+    let mut found: Vec<Event> = events
+        .iter()
+        .filter(|e| matcher.matches(e))
+        .cloned()
+        .collect();
+    found.sort_by_key(|e| (Reverse(e.created_at.0), e.id.0));
+    if let Some(limit) = matcher.limit() {
+        found.truncate(limit);
+    }
+    found
+}
@@ -0,0 +1,606 @@
+//! NIP-77 negentropy set reconciliation, from the initiator (client) side.
+//!
+//! See <https://github.com/hoytech/negentropy> for the protocol description.
+
+use crate::Command;
+use nostr_types::{Filter, Id, RelayMessage, SubscriptionId};
+use secp256k1::hashes::Hash;
+use std::collections::HashSet;
+use tokio::sync::mpsc::{Receiver, Sender};
+
+const PROTOCOL_VERSION: u8 = 0x61;
+const ID_SIZE: usize = 32;
+const FINGERPRINT_SIZE: usize = 16;
+const BUCKETS: usize = 16;
+const INFINITY: u64 = u64::MAX;
+
+const MODE_SKIP: u64 = 0;
+const MODE_FINGERPRINT: u64 = 1;
+const MODE_IDLIST: u64 = 2;
+
+/// A NIP-77 message from the relay
+#[derive(Debug, Clone)]
+pub enum NegMessage {
+    Msg(SubscriptionId, String),
+    Err(SubscriptionId, String),
+}
+
+impl NegMessage {
+    /// Parse a text frame as a NEG-MSG or NEG-ERR, if it is one
+    pub fn from_wire(s: &str) -> Option<NegMessage> {
+        // Cheap check first, as almost all frames are not negentropy frames
+        if !s
+            .trim_start()
+            .trim_start_matches('[')
+            .trim_start()
+            .starts_with("\"NEG-")
+        {
+            return None;
+        }
+        let value: serde_json::Value = serde_json::from_str(s).ok()?;
+        let array = value.as_array()?;
+        let sub = SubscriptionId(array.get(1)?.as_str()?.to_owned());
+        let payload = array.get(2)?.as_str()?.to_owned();
+        match array.first()?.as_str()? {
+            "NEG-MSG" => Some(NegMessage::Msg(sub, payload)),
+            "NEG-ERR" => Some(NegMessage::Err(sub, payload)),
+            _ => None,
+        }
+    }
+}
+
+fn error(msg: &str) -> Box<dyn std::error::Error> {
+    Box::new(std::io::Error::other(format!("negentropy: {}", msg)))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Bound {
+    timestamp: u64,
+    id_prefix: Vec<u8>,
+}
+
+impl Bound {
+    fn new(timestamp: u64) -> Bound {
+        Bound {
+            timestamp,
+            id_prefix: Vec::new(),
+        }
+    }
+}
+
+/// Negentropy state for the set of items we hold
+pub struct Negentropy {
+    // Sorted by (timestamp, id)
+    items: Vec<(u64, [u8; ID_SIZE])>,
+    last_timestamp_in: u64,
+    last_timestamp_out: u64,
+}
+
+impl Negentropy {
+    /// Create from (created_at, id) pairs. They need not be sorted.
+    pub fn new(items: impl IntoIterator<Item = (u64, Id)>) -> Negentropy {
+        let mut items: Vec<(u64, [u8; ID_SIZE])> =
+            items.into_iter().map(|(t, id)| (t, id.0)).collect();
+        items.sort();
+        items.dedup();
+        Negentropy {
+            items,
+            last_timestamp_in: 0,
+            last_timestamp_out: 0,
+        }
+    }
+
+    /// The first message, to send with NEG-OPEN
+    pub fn initiate(&mut self) -> Vec<u8> {
+        self.last_timestamp_out = 0;
+        let mut output = vec![PROTOCOL_VERSION];
+        self.split_range(0, self.items.len(), &Bound::new(INFINITY), &mut output);
+        output
+    }
+
+    /// Process a message from the relay, returning the next message to send (or None
+    /// if reconciliation is complete) and adding to the ids only we have and the ids
+    /// only they have.
+    pub fn reconcile(
+        &mut self,
+        query: &[u8],
+        have_ids: &mut Vec<Id>,
+        need_ids: &mut Vec<Id>,
+    ) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+        self.last_timestamp_in = 0;
+        self.last_timestamp_out = 0;
+
+        let mut query = query;
+        let mut output = vec![PROTOCOL_VERSION];
+
+        let version = take(&mut query, 1)?[0];
+        if !(0x60..=0x6F).contains(&version) {
+            return Err(error("invalid protocol version byte"));
+        }
+        if version != PROTOCOL_VERSION {
+            return Err(error(&format!(
+                "unsupported protocol version {}",
+                version - 0x60
+            )));
+        }
+
+        let mut prev_bound = Bound::new(0);
+        let mut prev_index: usize = 0;
+        let mut skip = false;
+
+        while !query.is_empty() {
+            let mut o: Vec<u8> = Vec::new();
+
+            let curr_bound = self.decode_bound(&mut query)?;
+            let mode = decode_varint(&mut query)?;
+
+            let lower = prev_index;
+            let upper = self.find_lower_bound(prev_index, &curr_bound);
+
+            match mode {
+                MODE_SKIP => skip = true,
+                MODE_FINGERPRINT => {
+                    let theirs = take(&mut query, FINGERPRINT_SIZE)?;
+                    let ours = self.fingerprint(lower, upper);
+                    if theirs != ours {
+                        if skip {
+                            skip = false;
+                            self.encode_bound(&prev_bound, &mut o);
+                            encode_varint(MODE_SKIP, &mut o);
+                        }
+                        self.split_range(lower, upper, &curr_bound, &mut o);
+                    } else {
+                        skip = true;
+                    }
+                }
+                MODE_IDLIST => {
+                    let num_ids = decode_varint(&mut query)? as usize;
+                    let mut theirs: HashSet<[u8; ID_SIZE]> = HashSet::with_capacity(num_ids);
+                    for _ in 0..num_ids {
+                        let mut id = [0u8; ID_SIZE];
+                        id.copy_from_slice(take(&mut query, ID_SIZE)?);
+                        theirs.insert(id);
+                    }
+                    for (_, id) in &self.items[lower..upper] {
+                        if !theirs.remove(id) {
+                            have_ids.push(Id(*id));
+                        }
+                    }
+                    need_ids.extend(theirs.into_iter().map(Id));
+                    skip = true;
+                }
+                _ => return Err(error("unexpected mode")),
+            }
+
+            output.extend(o);
+            prev_index = upper;
+            prev_bound = curr_bound;
+        }
+
+        if output.len() == 1 {
+            Ok(None)
+        } else {
+            Ok(Some(output))
+        }
+    }
+
+    fn split_range(&mut self, lower: usize, upper: usize, upper_bound: &Bound, o: &mut Vec<u8>) {
+        let num_elems = upper - lower;
+        if num_elems < BUCKETS * 2 {
+            self.encode_bound(upper_bound, o);
+            encode_varint(MODE_IDLIST, o);
+            encode_varint(num_elems as u64, o);
+            for (_, id) in &self.items[lower..upper] {
+                o.extend_from_slice(id);
+            }
+        } else {
+            let items_per_bucket = num_elems / BUCKETS;
+            let buckets_with_extra = num_elems % BUCKETS;
+            let mut curr = lower;
+            for i in 0..BUCKETS {
+                let bucket_size = items_per_bucket + usize::from(i < buckets_with_extra);
+                let fingerprint = self.fingerprint(curr, curr + bucket_size);
+                curr += bucket_size;
+                let next_bound = if curr == upper {
+                    upper_bound.clone()
+                } else {
+                    minimal_bound(&self.items[curr - 1], &self.items[curr])
+                };
+                self.encode_bound(&next_bound, o);
+                encode_varint(MODE_FINGERPRINT, o);
+                o.extend_from_slice(&fingerprint);
+            }
+        }
+    }
+
+    // Index of the first item at or after `bound`, searching from `begin`
+    fn find_lower_bound(&self, begin: usize, bound: &Bound) -> usize {
+        let begin = begin.min(self.items.len());
+        begin
+            + self.items[begin..]
+                .partition_point(|(t, id)| (*t, &id[..]) < (bound.timestamp, &bound.id_prefix[..]))
+    }
+
+    fn fingerprint(&self, lower: usize, upper: usize) -> [u8; FINGERPRINT_SIZE] {
+        // Sum of ids as little-endian 256-bit numbers, mod 2^256
+        let mut sum = [0u8; ID_SIZE];
+        for (_, id) in &self.items[lower..upper] {
+            let mut carry: u16 = 0;
+            for (s, b) in sum.iter_mut().zip(id.iter()) {
+                let total = *s as u16 + *b as u16 + carry;
+                *s = total as u8;
+                carry = total >> 8;
+            }
+        }
+        let mut input = sum.to_vec();
+        encode_varint((upper - lower) as u64, &mut input);
+        let hash = secp256k1::hashes::sha256::Hash::hash(&input);
+        let mut fingerprint = [0u8; FINGERPRINT_SIZE];
+        fingerprint.copy_from_slice(&hash.to_byte_array()[..FINGERPRINT_SIZE]);
+        fingerprint
+    }
+
+    fn encode_bound(&mut self, bound: &Bound, o: &mut Vec<u8>) {
+        if bound.timestamp == INFINITY {
+            self.last_timestamp_out = INFINITY;
+            encode_varint(0, o);
+        } else {
+            let delta = bound.timestamp - self.last_timestamp_out;
+            self.last_timestamp_out = bound.timestamp;
+            encode_varint(delta + 1, o);
+        }
+        encode_varint(bound.id_prefix.len() as u64, o);
+        o.extend_from_slice(&bound.id_prefix);
+    }
+
+    fn decode_bound(&mut self, query: &mut &[u8]) -> Result<Bound, Box<dyn std::error::Error>> {
+        let encoded = decode_varint(query)?;
+        let timestamp = if encoded == 0 || self.last_timestamp_in == INFINITY {
+            INFINITY
+        } else {
+            self.last_timestamp_in
+                .checked_add(encoded - 1)
+                .ok_or_else(|| error("timestamp overflow"))?
+        };
+        self.last_timestamp_in = timestamp;
+        let len = decode_varint(query)? as usize;
+        if len > ID_SIZE {
+            return Err(error("bound key too long"));
+        }
+        let id_prefix = take(query, len)?.to_vec();
+        Ok(Bound {
+            timestamp,
+            id_prefix,
+        })
+    }
+}
+
+fn minimal_bound(prev: &(u64, [u8; ID_SIZE]), curr: &(u64, [u8; ID_SIZE])) -> Bound {
+    if curr.0 != prev.0 {
+        Bound::new(curr.0)
+    } else {
+        let shared = prev
+            .1
+            .iter()
+            .zip(curr.1.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        Bound {
+            timestamp: curr.0,
+            id_prefix: curr.1[..(shared + 1).min(ID_SIZE)].to_vec(),
+        }
+    }
+}
+
+fn take<'a>(query: &mut &'a [u8], n: usize) -> Result<&'a [u8], Box<dyn std::error::Error>> {
+    if query.len() < n {
+        return Err(error("message truncated"));
+    }
+    let (head, tail) = query.split_at(n);
+    *query = tail;
+    Ok(head)
+}
+
+fn encode_varint(mut n: u64, o: &mut Vec<u8>) {
+    if n == 0 {
+        o.push(0);
+        return;
+    }
+    let mut bytes: Vec<u8> = Vec::new();
+    while n != 0 {
+        bytes.push((n & 0x7F) as u8);
+        n >>= 7;
+    }
+    bytes.reverse();
+    let last = bytes.len() - 1;
+    for b in &mut bytes[..last] {
+        *b |= 0x80;
+    }
+    o.extend(bytes);
+}
+
+fn decode_varint(query: &mut &[u8]) -> Result<u64, Box<dyn std::error::Error>> {
+    let mut n: u64 = 0;
+    loop {
+        let byte = take(query, 1)?[0];
+        n = n.checked_mul(128).ok_or_else(|| error("varint overflow"))? | (byte & 0x7F) as u64;
+        if byte & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+}
+
+/// The outcome of reconciling against a relay
+#[derive(Debug, Default)]
+pub struct Reconciliation {
+    /// Ids we have that the relay lacks
+    pub have: Vec<Id>,
+
+    /// Ids the relay has that we lack
+    pub need: Vec<Id>,
+}
+
+/// Reconcile `items` against the relay's events matching `filter`.
+///
+/// Returns Ok(Err(reason)) if the relay does not support NIP-77 (or refuses to do it for
+/// this filter), so the caller can fall back to a plain diff. The relay is sent a
+/// NEG-CLOSE however it ends, so it doesn't keep the session open.
+pub async fn reconcile(
+    sub_id: SubscriptionId,
+    filter: Filter,
+    items: impl IntoIterator<Item = (u64, Id)>,
+    to_probe: &Sender<Command>,
+    from_probe: &mut Receiver<RelayMessage>,
+    from_probe_neg: &mut Receiver<NegMessage>,
+    timeout_secs: u64,
+) -> Result<Result<Reconciliation, String>, Box<dyn std::error::Error>> {
+    let outcome = exchange(
+        &sub_id,
+        filter,
+        items,
+        to_probe,
+        from_probe,
+        from_probe_neg,
+        timeout_secs,
+    )
+    .await;
+
+    // The probe may already be gone, in which case so is the session
+    let _ = to_probe.send(Command::NegClose(sub_id)).await;
+
+    outcome
+}
+
+// Exchange NEG-MSGs with the relay until the sets are reconciled or it gives up
+async fn exchange(
+    sub_id: &SubscriptionId,
+    filter: Filter,
+    items: impl IntoIterator<Item = (u64, Id)>,
+    to_probe: &Sender<Command>,
+    from_probe: &mut Receiver<RelayMessage>,
+    from_probe_neg: &mut Receiver<NegMessage>,
+    timeout_secs: u64,
+) -> Result<Result<Reconciliation, String>, Box<dyn std::error::Error>> {
+    let mut negentropy = Negentropy::new(items);
+    let mut result = Reconciliation::default();
+
+    let initial = hex::encode(negentropy.initiate());
+    to_probe
+        .send(Command::NegOpen(sub_id.clone(), filter, initial))
+        .await?;
+
+    loop {
+        let timeout = tokio::time::sleep(std::time::Duration::new(timeout_secs, 0));
+        let message = tokio::select! {
+            _ = timeout => {
+                return Ok(Err("no response to NEG-OPEN".to_owned()));
+            },
+            relay_message = from_probe.recv() => {
+                match relay_message {
+                    Some(RelayMessage::Notice(notice)) => {
+                        return Ok(Err(format!("NOTICE: {}", notice)));
+                    },
+                    Some(_) => continue,
+                    None => return Ok(Err("disconnected".to_owned())),
+                }
+            },
+            neg_message = from_probe_neg.recv() => neg_message,
+        };
+
+        match message {
+            Some(NegMessage::Msg(sub, msg)) if sub == *sub_id => {
+                let query = hex::decode(&msg)?;
+                match negentropy.reconcile(&query, &mut result.have, &mut result.need)? {
+                    Some(next) => {
+                        to_probe
+                            .send(Command::NegMsg(sub_id.clone(), hex::encode(next)))
+                            .await?;
+                    }
+                    None => return Ok(Ok(result)),
+                }
+            }
+            Some(NegMessage::Err(sub, reason)) if sub == *sub_id => {
+                return Ok(Err(format!("NEG-ERR: {}", reason)));
+            }
+            Some(_) => {}
+            None => return Ok(Err("disconnected".to_owned())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varint(n: u64) -> Vec<u8> {
+        let mut o = Vec::new();
+        encode_varint(n, &mut o);
+        o
+    }
+
+    fn id(first: u8) -> [u8; ID_SIZE] {
+        let mut id = [0u8; ID_SIZE];
+        id[0] = first;
+        id
+    }
+
+    #[test]
+    fn varint_vectors() {
+        let vectors: &[(u64, &[u8])] = &[
+            (0, &[0x00]),
+            (1, &[0x01]),
+            (127, &[0x7F]),
+            (128, &[0x81, 0x00]),
+            (300, &[0x82, 0x2C]),
+            (16383, &[0xFF, 0x7F]),
+            (16384, &[0x81, 0x80, 0x00]),
+        ];
+        for (n, bytes) in vectors {
+            assert_eq!(varint(*n), *bytes, "encoding {}", n);
+            let mut query: &[u8] = bytes;
+            assert_eq!(decode_varint(&mut query).unwrap(), *n, "decoding {}", n);
+            assert!(query.is_empty());
+        }
+
+        let mut query: &[u8] = &varint(u64::MAX);
+        assert_eq!(decode_varint(&mut query).unwrap(), u64::MAX);
+
+        let mut truncated: &[u8] = &[0x81];
+        assert!(decode_varint(&mut truncated).is_err());
+        let mut too_long: &[u8] = &[0xFF; 11];
+        assert!(decode_varint(&mut too_long).is_err());
+    }
+
+    #[test]
+    fn bounds_are_delta_encoded() {
+        let mut negentropy = Negentropy::new([]);
+        let mut o = Vec::new();
+        negentropy.encode_bound(&Bound::new(100), &mut o);
+        negentropy.encode_bound(
+            &Bound {
+                timestamp: 105,
+                id_prefix: vec![0xAB],
+            },
+            &mut o,
+        );
+        negentropy.encode_bound(&Bound::new(INFINITY), &mut o);
+        assert_eq!(o, [101, 0, 6, 1, 0xAB, 0, 0]);
+
+        let mut query: &[u8] = &o;
+        assert_eq!(
+            negentropy.decode_bound(&mut query).unwrap(),
+            Bound::new(100)
+        );
+        assert_eq!(
+            negentropy.decode_bound(&mut query).unwrap(),
+            Bound {
+                timestamp: 105,
+                id_prefix: vec![0xAB]
+            }
+        );
+        assert_eq!(
+            negentropy.decode_bound(&mut query).unwrap(),
+            Bound::new(INFINITY)
+        );
+        assert!(query.is_empty());
+
+        let mut too_long: &[u8] = &[1, 33];
+        assert!(negentropy.decode_bound(&mut too_long).is_err());
+    }
+
+    #[test]
+    fn minimal_bounds() {
+        assert_eq!(minimal_bound(&(5, id(1)), &(7, id(2))), Bound::new(7));
+        let mut a = id(0x12);
+        a[1] = 0x34;
+        let mut b = id(0x12);
+        b[1] = 0x56;
+        assert_eq!(
+            minimal_bound(&(5, a), &(5, b)),
+            Bound {
+                timestamp: 5,
+                id_prefix: vec![0x12, 0x56]
+            }
+        );
+    }
+
+    #[test]
+    fn fingerprint_vectors() {
+        let empty = Negentropy::new([]);
+        assert_eq!(
+            hex::encode(empty.fingerprint(0, 0)),
+            "7f9c9e31ac8256ca2f258583df262dbc"
+        );
+
+        let mut counting = [0u8; ID_SIZE];
+        for (i, b) in counting.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let one = Negentropy::new([(1, Id(counting))]);
+        assert_eq!(
+            hex::encode(one.fingerprint(0, 1)),
+            "8b44d96f214304bc15fe5ccb132bd5d5"
+        );
+
+        // 0xFF.. + 0x01 carries all the way out, leaving a sum of zero
+        let carry = Negentropy::new([(1, Id([0xFF; ID_SIZE])), (2, Id(id(1)))]);
+        assert_eq!(
+            hex::encode(carry.fingerprint(0, 2)),
+            "58cc2f44d3a27866874701fbad573da9"
+        );
+    }
+
+    #[test]
+    fn small_sets_are_sent_as_id_lists() {
+        let mut negentropy = Negentropy::new([(20, Id(id(2))), (10, Id(id(1)))]);
+        let mut expected = vec![PROTOCOL_VERSION, 0, 0, MODE_IDLIST as u8, 2];
+        expected.extend_from_slice(&id(1));
+        expected.extend_from_slice(&id(2));
+        assert_eq!(negentropy.initiate(), expected);
+    }
+
+    #[test]
+    fn matching_fingerprint_completes() {
+        let items: Vec<(u64, Id)> = (0..40).map(|i| (i, Id(id(i as u8)))).collect();
+        let mut negentropy = Negentropy::new(items);
+        let mut query = vec![PROTOCOL_VERSION, 0, 0, MODE_FINGERPRINT as u8];
+        query.extend_from_slice(&negentropy.fingerprint(0, 40));
+
+        let (mut have, mut need) = (Vec::new(), Vec::new());
+        let next = negentropy.reconcile(&query, &mut have, &mut need).unwrap();
+        assert!(next.is_none());
+        assert!(have.is_empty() && need.is_empty());
+    }
+
+    #[test]
+    fn id_lists_give_the_differences() {
+        let mut negentropy = Negentropy::new([(1, Id(id(1))), (2, Id(id(2)))]);
+        let mut query = vec![PROTOCOL_VERSION, 0, 0, MODE_IDLIST as u8, 2];
+        query.extend_from_slice(&id(2));
+        query.extend_from_slice(&id(3));
+
+        let (mut have, mut need) = (Vec::new(), Vec::new());
+        let next = negentropy.reconcile(&query, &mut have, &mut need).unwrap();
+        assert!(next.is_none());
+        assert_eq!(have, vec![Id(id(1))]);
+        assert_eq!(need, vec![Id(id(3))]);
+    }
+
+    #[test]
+    fn bad_messages_are_errors() {
+        let mut negentropy = Negentropy::new([]);
+        let (mut have, mut need) = (Vec::new(), Vec::new());
+        for query in [
+            &[][..],
+            &[0x62][..],
+            &[0x10][..],
+            &[PROTOCOL_VERSION, 0, 0, 7][..],
+        ] {
+            assert!(negentropy.reconcile(query, &mut have, &mut need).is_err());
+        }
+        let truncated = [PROTOCOL_VERSION, 0, 0, MODE_IDLIST as u8, 1, 0xAA];
+        assert!(negentropy
+            .reconcile(&truncated, &mut have, &mut need)
+            .is_err());
+    }
+}
@@ -0,0 +1,218 @@
+//! NIP-10 reply threading: which event a note replies to and which thread it is in,
+//! from either marked `e` tags or the older positional convention.
+
+use crate::filter::tag_fields;
+use nostr_types::Event;
+
+/// What a note's `e` tags say about where it sits in a thread. Ids are hex.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Thread {
+    pub root: Option<String>,
+    pub reply: Option<String>,
+    pub mentions: Vec<String>,
+    /// Whether the tags use markers (rather than position)
+    pub marked: bool,
+    /// Anything about the tags that doesn't follow NIP-10
+    pub problems: Vec<String>,
+}
+
+impl Thread {
+    /// The event this one directly replies to
+    pub fn parent(&self) -> Option<&String> {
+        self.reply.as_ref().or(self.root.as_ref())
+    }
+}
+
+/// Read the thread position of an event from its `e` tags
+pub fn parse(event: &Event) -> Thread {
+    let e_tags: Vec<Vec<String>> = tag_fields(event)
+        .into_iter()
+        .filter(|t| t.first().map(|n| n == "e").unwrap_or(false) && t.len() >= 2)
+        .collect();
+
+    let mut thread = Thread::default();
+    let marker = |t: &Vec<String>| t.get(3).filter(|m| !m.is_empty()).cloned();
+    let any_marked = e_tags.iter().any(|t| marker(t).is_some());
+
+    if any_marked {
+        thread.marked = true;
+        for tag in &e_tags {
+            let id = tag[1].clone();
+            match marker(tag).as_deref() {
+                Some("root") => {
+                    if thread.root.is_some() {
+                        thread.problems.push("more than one root marker".to_owned());
+                    } else {
+                        thread.root = Some(id);
+                    }
+                }
+                Some("reply") => {
+                    if thread.reply.is_some() {
+                        thread
+                            .problems
+                            .push("more than one reply marker".to_owned());
+                    } else {
+                        thread.reply = Some(id);
+                    }
+                }
+                Some("mention") => thread.mentions.push(id),
+                Some(other) => {
+                    thread.problems.push(format!("unknown marker {:?}", other));
+                    thread.mentions.push(id);
+                }
+                None => {
+                    thread
+                        .problems
+                        .push("mixes marked and positional e tags".to_owned());
+                    thread.mentions.push(id);
+                }
+            }
+        }
+        if thread.reply.is_some() && thread.root.is_none() {
+            thread
+                .problems
+                .push("reply marker without a root marker".to_owned());
+        }
+    } else {
+        // Positional: first is the root, last is the reply, the rest are mentions
+        let ids: Vec<String> = e_tags.into_iter().map(|t| t[1].clone()).collect();
+        if let Some(first) = ids.first() {
+            thread.root = Some(first.clone());
+        }
+        if ids.len() > 1 {
+            thread.reply = ids.last().cloned();
+            thread.mentions = ids[1..ids.len() - 1].to_vec();
+        }
+    }
+
+    if thread.root.is_some() && thread.root == thread.reply {
+        thread.reply = None;
+    }
+    thread
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conformance::Author;
+    use nostr_types::Tag;
+
+    // A note with the given tags, from a throwaway key
+    fn note(tags: &[&[&str]]) -> Event {
+        let tags = tags.iter().map(|t| Tag::new(t)).collect();
+        Author::new().sign(1, 1_700_000_000, tags, "reply")
+    }
+
+    fn id(c: char) -> String {
+        c.to_string().repeat(64)
+    }
+
+    #[test]
+    fn marked_tags() {
+        let (a, b, c) = (id('a'), id('b'), id('c'));
+        let thread = parse(&note(&[
+            &["e", &c, "", "mention"],
+            &["e", &b, "wss://relay.example", "reply"],
+            &["e", &a, "", "root"],
+            &["p", &a],
+        ]));
+        assert!(thread.marked);
+        assert_eq!(thread.root, Some(a));
+        assert_eq!(thread.reply, Some(b.clone()));
+        assert_eq!(thread.mentions, vec![c]);
+        assert_eq!(thread.parent(), Some(&b));
+        assert!(thread.problems.is_empty());
+    }
+
+    #[test]
+    fn marked_root_only_is_a_direct_reply_to_the_root() {
+        let a = id('a');
+        let thread = parse(&note(&[&["e", &a, "", "root"]]));
+        assert_eq!(thread.root, Some(a.clone()));
+        assert_eq!(thread.reply, None);
+        assert_eq!(thread.parent(), Some(&a));
+        assert!(thread.problems.is_empty());
+    }
+
+    #[test]
+    fn marked_reply_without_root_is_a_problem() {
+        let b = id('b');
+        let thread = parse(&note(&[&["e", &b, "", "reply"]]));
+        assert_eq!(thread.reply, Some(b));
+        assert_eq!(thread.problems, vec!["reply marker without a root marker"]);
+    }
+
+    #[test]
+    fn positional_tags() {
+        let (a, b, c, d) = (id('a'), id('b'), id('c'), id('d'));
+
+        let thread = parse(&note(&[]));
+        assert_eq!(thread, Thread::default());
+
+        let thread = parse(&note(&[&["e", &a]]));
+        assert!(!thread.marked);
+        assert_eq!(thread.root, Some(a.clone()));
+        assert_eq!(thread.reply, None);
+
+        let thread = parse(&note(&[&["e", &a], &["e", &b]]));
+        assert_eq!(thread.root, Some(a.clone()));
+        assert_eq!(thread.reply, Some(b.clone()));
+        assert!(thread.mentions.is_empty());
+
+        let thread = parse(&note(&[&["e", &a], &["e", &c], &["e", &d], &["e", &b]]));
+        assert_eq!(thread.root, Some(a));
+        assert_eq!(thread.reply, Some(b));
+        assert_eq!(thread.mentions, vec![c, d]);
+        assert!(thread.problems.is_empty());
+    }
+
+    #[test]
+    fn a_reply_that_is_also_the_root_is_only_the_root() {
+        let a = id('a');
+        let thread = parse(&note(&[&["e", &a], &["e", &a]]));
+        assert_eq!(thread.root, Some(a.clone()));
+        assert_eq!(thread.reply, None);
+
+        let thread = parse(&note(&[&["e", &a, "", "root"], &["e", &a, "", "reply"]]));
+        assert_eq!(thread.root, Some(a));
+        assert_eq!(thread.reply, None);
+    }
+
+    #[test]
+    fn mixed_tags_are_read_as_marked() {
+        let (a, b, c) = (id('a'), id('b'), id('c'));
+        let thread = parse(&note(&[
+            &["e", &a, "", "root"],
+            &["e", &c],
+            &["e", &b, "", "reply"],
+        ]));
+        assert!(thread.marked);
+        assert_eq!(thread.root, Some(a));
+        assert_eq!(thread.reply, Some(b));
+        assert_eq!(thread.mentions, vec![c]);
+        assert_eq!(thread.problems, vec!["mixes marked and positional e tags"]);
+    }
+
+    #[test]
+    fn duplicate_and_unknown_markers() {
+        let (a, b, c, d) = (id('a'), id('b'), id('c'), id('d'));
+        let thread = parse(&note(&[
+            &["e", &a, "", "root"],
+            &["e", &b, "", "root"],
+            &["e", &c, "", "reply"],
+            &["e", &d, "", "reply"],
+            &["e", &d, "", "quote"],
+        ]));
+        assert_eq!(thread.root, Some(a));
+        assert_eq!(thread.reply, Some(c));
+        assert_eq!(thread.mentions, vec![d]);
+        assert_eq!(
+            thread.problems,
+            vec![
+                "more than one root marker",
+                "more than one reply marker",
+                "unknown marker \"quote\"",
+            ]
+        );
+    }
+}
@@ -0,0 +1,512 @@
+//! NIP-11 relay information documents: parsing with schema checks, and checks of the
+//! advertised limitations against the live relay.
+
+use crate::conformance::{Author, Outcome, Session, SessionError, TestResult};
+use crate::record;
+use nostr_types::{Event, EventKind, Filter, PublicKey, SubscriptionId};
+use serde_json::Value;
+
+/// NIPs that have been assigned (hex-numbered NIPs like 7D cannot be advertised). New
+/// NIPs are merged often, so a NIP missing from here is only worth a note, not a problem.
+pub const KNOWN_NIPS: &[u32] = &[
+    1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26,
+    27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40, 42, 44, 45, 46, 47, 48, 49, 50, 51, 52,
+    53, 54, 55, 56, 57, 58, 59, 60, 61, 62, 64, 65, 66, 68, 69, 70, 71, 72, 73, 75, 77, 78, 84, 86,
+    87, 88, 89, 90, 92, 94, 96, 98, 99,
+];
+
+/// NIPs that have been folded into others and should no longer be advertised
+pub const DEPRECATED_NIPS: &[u32] = &[12, 16, 20, 33];
+
+// Limits above these are not checked live, so that checking stays cheap for the relay
+const MAX_CHECKED_MESSAGE_LENGTH: u64 = 16 * 1024 * 1024;
+const MAX_CHECKED_SUBSCRIPTIONS: u64 = 200;
+const MAX_CHECKED_FILTERS: u64 = 200;
+const MAX_CHECKED_LIMIT: u64 = 10_000;
+
+#[derive(Debug, Clone, Default)]
+pub struct Limitation {
+    pub max_message_length: Option<u64>,
+    pub max_subscriptions: Option<u64>,
+    pub max_filters: Option<u64>,
+    pub max_limit: Option<u64>,
+    pub max_subid_length: Option<u64>,
+    pub max_event_tags: Option<u64>,
+    pub max_content_length: Option<u64>,
+    pub min_pow_difficulty: Option<u64>,
+    pub auth_required: Option<bool>,
+    pub payment_required: Option<bool>,
+    pub restricted_writes: Option<bool>,
+    pub created_at_lower_limit: Option<i64>,
+    pub created_at_upper_limit: Option<i64>,
+}
+
+/// A relay information document
+#[derive(Debug, Clone, Default)]
+pub struct Nip11 {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub icon: Option<String>,
+    pub banner: Option<String>,
+    pub pubkey: Option<PublicKey>,
+    pub contact: Option<String>,
+    pub supported_nips: Option<Vec<u32>>,
+    pub software: Option<String>,
+    pub version: Option<String>,
+    pub limitation: Option<Limitation>,
+    pub relay_countries: Option<Vec<String>>,
+    pub language_tags: Option<Vec<String>>,
+    pub tags: Option<Vec<String>>,
+    pub posting_policy: Option<String>,
+    pub payments_url: Option<String>,
+}
+
+// Reads fields out of a JSON object, noting any that have the wrong type
+struct Fields<'a> {
+    object: &'a serde_json::Map<String, Value>,
+    prefix: &'a str,
+    problems: &'a mut Vec<String>,
+}
+
+impl Fields<'_> {
+    fn wrong_type(&mut self, key: &str, expected: &str, value: &Value) {
+        self.problems.push(format!(
+            "{}{} should be {}, found {}",
+            self.prefix, key, expected, value
+        ));
+    }
+
+    fn string(&mut self, key: &str) -> Option<String> {
+        match self.object.get(key)? {
+            Value::String(s) => Some(s.clone()),
+            v => {
+                self.wrong_type(key, "a string", v);
+                None
+            }
+        }
+    }
+
+    fn unsigned(&mut self, key: &str) -> Option<u64> {
+        let v = self.object.get(key)?;
+        match v.as_u64() {
+            Some(n) => Some(n),
+            None => {
+                self.wrong_type(key, "a non-negative integer", v);
+                None
+            }
+        }
+    }
+
+    fn integer(&mut self, key: &str) -> Option<i64> {
+        let v = self.object.get(key)?;
+        match v.as_i64() {
+            Some(n) => Some(n),
+            None => {
+                self.wrong_type(key, "an integer", v);
+                None
+            }
+        }
+    }
+
+    fn boolean(&mut self, key: &str) -> Option<bool> {
+        match self.object.get(key)? {
+            Value::Bool(b) => Some(*b),
+            v => {
+                self.wrong_type(key, "a boolean", v);
+                None
+            }
+        }
+    }
+
+    fn strings(&mut self, key: &str) -> Option<Vec<String>> {
+        let v = self.object.get(key)?;
+        let strings: Option<Vec<String>> = v
+            .as_array()
+            .and_then(|a| a.iter().map(|s| s.as_str().map(|s| s.to_owned())).collect());
+        if strings.is_none() {
+            self.wrong_type(key, "an array of strings", v);
+        }
+        strings
+    }
+}
+
+impl Nip11 {
+    /// Advertised NIPs missing from [`KNOWN_NIPS`], which may just be newer than it
+    pub fn unknown_nips(&self) -> Vec<u32> {
+        self.supported_nips
+            .iter()
+            .flatten()
+            .copied()
+            .filter(|n| !KNOWN_NIPS.contains(n))
+            .collect()
+    }
+
+    /// Parse a NIP-11 document, returning what could be understood along with a
+    /// description of every schema problem found
+    pub fn parse(value: &Value) -> (Nip11, Vec<String>) {
+        let mut problems: Vec<String> = Vec::new();
+        let mut nip11 = Nip11::default();
+
+        let object = match value.as_object() {
+            Some(o) => o,
+            None => {
+                problems.push(format!("document is not a JSON object: {}", value));
+                return (nip11, problems);
+            }
+        };
+
+        let mut fields = Fields {
+            object,
+            prefix: "",
+            problems: &mut problems,
+        };
+        nip11.name = fields.string("name");
+        nip11.description = fields.string("description");
+        nip11.icon = fields.string("icon");
+        nip11.banner = fields.string("banner");
+        nip11.contact = fields.string("contact");
+        nip11.software = fields.string("software");
+        nip11.version = fields.string("version");
+        nip11.relay_countries = fields.strings("relay_countries");
+        nip11.language_tags = fields.strings("language_tags");
+        nip11.tags = fields.strings("tags");
+        nip11.posting_policy = fields.string("posting_policy");
+        nip11.payments_url = fields.string("payments_url");
+
+        match fields.string("pubkey") {
+            Some(hex) => match PublicKey::try_from_hex_string(&hex, true) {
+                Ok(pk) => nip11.pubkey = Some(pk),
+                Err(_) => problems.push(format!("pubkey is not a valid hex public key: {}", hex)),
+            },
+            None => {
+                if !object.contains_key("pubkey") {
+                    problems.push("pubkey is missing".to_owned());
+                }
+            }
+        }
+
+        if let Some(v) = object.get("supported_nips") {
+            match v.as_array() {
+                Some(entries) => {
+                    let mut nips: Vec<u32> = Vec::new();
+                    for entry in entries {
+                        match entry.as_u64() {
+                            Some(n) => {
+                                let n = n as u32;
+                                if DEPRECATED_NIPS.contains(&n) {
+                                    problems.push(format!(
+                                        "supported_nips lists deprecated NIP-{:02}",
+                                        n
+                                    ));
+                                }
+                                nips.push(n);
+                            }
+                            None => problems.push(format!(
+                                "supported_nips entries should be integers, found {}",
+                                entry
+                            )),
+                        }
+                    }
+                    nip11.supported_nips = Some(nips);
+                }
+                None => problems.push(format!(
+                    "supported_nips should be an array of integers, found {}",
+                    v
+                )),
+            }
+        }
+
+        if let Some(v) = object.get("limitation") {
+            match v.as_object() {
+                Some(object) => {
+                    let mut fields = Fields {
+                        object,
+                        prefix: "limitation.",
+                        problems: &mut problems,
+                    };
+                    nip11.limitation = Some(Limitation {
+                        max_message_length: fields.unsigned("max_message_length"),
+                        max_subscriptions: fields.unsigned("max_subscriptions"),
+                        max_filters: fields.unsigned("max_filters"),
+                        max_limit: fields.unsigned("max_limit"),
+                        max_subid_length: fields.unsigned("max_subid_length"),
+                        max_event_tags: fields.unsigned("max_event_tags"),
+                        max_content_length: fields.unsigned("max_content_length"),
+                        min_pow_difficulty: fields.unsigned("min_pow_difficulty"),
+                        auth_required: fields.boolean("auth_required"),
+                        payment_required: fields.boolean("payment_required"),
+                        restricted_writes: fields.boolean("restricted_writes"),
+                        created_at_lower_limit: fields.integer("created_at_lower_limit"),
+                        created_at_upper_limit: fields.integer("created_at_upper_limit"),
+                    });
+                }
+                None => problems.push(format!("limitation should be an object, found {}", v)),
+            }
+        }
+
+        if let Some(v) = object.get("retention") {
+            if !v.is_array() {
+                problems.push(format!("retention should be an array, found {}", v));
+            }
+        }
+        if let Some(v) = object.get("fees") {
+            if !v.is_object() {
+                problems.push(format!("fees should be an object, found {}", v));
+            }
+        }
+
+        (nip11, problems)
+    }
+}
+
+// Leading zero bits of an event id, as counted by NIP-13
+fn pow_difficulty(event: &Event) -> u64 {
+    let mut bits: u64 = 0;
+    for byte in event.id.0.iter() {
+        if *byte == 0 {
+            bits += 8;
+        } else {
+            bits += byte.leading_zeros() as u64;
+            break;
+        }
+    }
+    bits
+}
+
+// Only an OK false counts as refusing the event. Silence, a lone NOTICE or a
+// connection that never worked doesn't show the relay enforces anything.
+fn refused(result: Result<(bool, String), SessionError>) -> Outcome {
+    match result {
+        Ok((true, _)) => Outcome::Fail("relay accepted the event".to_owned()),
+        Ok((false, _)) => Outcome::Pass,
+        Err(e) => e.into(),
+    }
+}
+
+/// Check the relay's advertised limitations against how it actually behaves.
+/// Each check uses its own connection, since exceeding a limit may cost us ours.
+pub async fn check_limitations(relay_url: &str, limitation: &Limitation) -> Vec<TestResult> {
+    let mut results: Vec<TestResult> = Vec::new();
+    let author = Author::new();
+
+    let mut session = Session::open(relay_url);
+    record!(results, session, 11, "max_message_length", {
+        match limitation.max_message_length {
+            None => Outcome::Skip("not advertised".to_owned()),
+            Some(n) if n > MAX_CHECKED_MESSAGE_LENGTH => {
+                Outcome::Skip(format!("{} is too large to check", n))
+            }
+            Some(n) => {
+                // Make sure the connection works, so that it closing on the oversized
+                // event means the relay hung up on it
+                match session.fetch(vec![author.filter(1)]).await {
+                    Err(e) => e.context("before sending the oversized event").into(),
+                    Ok(_) => {
+                        let event = author.note(&"x".repeat(n as usize + 1));
+                        match session.post(&event).await {
+                            // Websocket servers enforce a frame size by closing the connection
+                            Err(SessionError::Closed(_)) => Outcome::Pass,
+                            result => refused(result),
+                        }
+                    }
+                }
+            }
+        }
+    });
+    session.close().await;
+
+    let mut session = Session::open(relay_url);
+    record!(results, session, 11, "max_subscriptions", {
+        match limitation.max_subscriptions {
+            None => Outcome::Skip("not advertised".to_owned()),
+            Some(n) if n > MAX_CHECKED_SUBSCRIPTIONS => {
+                Outcome::Skip(format!("{} is too many to check", n))
+            }
+            Some(n) => {
+                let mut outcome = Outcome::Fail(format!("relay allowed {} subscriptions", n + 1));
+                for i in 0..=n {
+                    let sub = SubscriptionId(format!("limit-sub-{}", i));
+                    match session.subscribe(sub, vec![author.filter(1)]).await {
+                        Ok(None) => {}
+                        Ok(Some(_)) if i == n => {
+                            outcome = Outcome::Pass;
+                        }
+                        Ok(Some(reason)) => {
+                            outcome = Outcome::Fail(format!(
+                                "subscription {} was CLOSED: {}",
+                                i + 1,
+                                reason
+                            ));
+                            break;
+                        }
+                        Err(e) => {
+                            outcome = e.context(&format!("subscription {}", i + 1)).into();
+                            break;
+                        }
+                    }
+                }
+                outcome
+            }
+        }
+    });
+    session.close().await;
+
+    let mut session = Session::open(relay_url);
+    record!(results, session, 11, "max_filters", {
+        match limitation.max_filters {
+            None => Outcome::Skip("not advertised".to_owned()),
+            Some(n) if n > MAX_CHECKED_FILTERS => {
+                Outcome::Skip(format!("{} is too many to check", n))
+            }
+            Some(n) => {
+                let filters: Vec<Filter> = (0..n as u32).map(|k| author.filter(k)).collect();
+                let sub = SubscriptionId("limit-filters-ok".to_owned());
+                match session.req_closed(sub, filters).await {
+                    Ok(None) => {
+                        let filters: Vec<Filter> =
+                            (0..=n as u32).map(|k| author.filter(k)).collect();
+                        let sub = SubscriptionId("limit-filters-over".to_owned());
+                        match session.req_closed(sub, filters).await {
+                            Ok(None) => Outcome::Fail(format!("relay accepted {} filters", n + 1)),
+                            Ok(Some(_)) => Outcome::Pass,
+                            Err(e) => e.context(&format!("{} filters", n + 1)).into(),
+                        }
+                    }
+                    Ok(Some(reason)) => {
+                        Outcome::Fail(format!("relay refused {} filters: {}", n, reason))
+                    }
+                    Err(e) => e.context(&format!("relay refused {} filters", n)).into(),
+                }
+            }
+        }
+    });
+    session.close().await;
+
+    let mut session = Session::open(relay_url);
+    record!(results, session, 11, "max_limit", {
+        match limitation.max_limit {
+            None => Outcome::Skip("not advertised".to_owned()),
+            Some(n) if n > MAX_CHECKED_LIMIT => {
+                Outcome::Skip(format!("{} is too large to check", n))
+            }
+            Some(n) => {
+                let mut filter = Filter::new();
+                filter.add_event_kind(EventKind::TextNote);
+                filter.limit = Some(n as usize * 2);
+                match session.fetch(vec![filter]).await {
+                    Ok(events) if events.len() as u64 > n => Outcome::Fail(format!(
+                        "relay returned {} events, more than its max_limit of {}",
+                        events.len(),
+                        n
+                    )),
+                    Ok(_) => Outcome::Pass,
+                    Err(e) => e.into(),
+                }
+            }
+        }
+    });
+    session.close().await;
+
+    let mut session = Session::open(relay_url);
+    record!(results, session, 11, "auth_required", {
+        let sub = SubscriptionId("limit-auth".to_owned());
+        let closed = session.req_closed(sub, vec![author.filter(1)]).await;
+        let auth_closed = matches!(&closed, Ok(Some(reason))
+            if crate::reason_prefix(reason) == Some("auth-required"));
+        match (limitation.auth_required, closed) {
+            (None, _) => Outcome::Skip("not advertised".to_owned()),
+            (_, Err(e)) => e.into(),
+            (Some(true), _) if auth_closed => Outcome::Pass,
+            (Some(true), _) => {
+                Outcome::Fail("relay answered a REQ without auth-required".to_owned())
+            }
+            (Some(false), _) if auth_closed => {
+                Outcome::Fail("relay CLOSED a REQ with auth-required".to_owned())
+            }
+            (Some(false), _) => Outcome::Pass,
+        }
+    });
+    session.close().await;
+
+    let mut session = Session::open(relay_url);
+    record!(results, session, 11, "payment_required", {
+        let restricted =
+            limitation.auth_required == Some(true) || limitation.restricted_writes == Some(true);
+        match limitation.payment_required {
+            None => Outcome::Skip("not advertised".to_owned()),
+            Some(true) => {
+                let event = author.note("An unpaid note, which this relay should refuse.");
+                refused(session.post(&event).await)
+            }
+            Some(false) if restricted => {
+                Outcome::Skip("relay restricts writes for other reasons".to_owned())
+            }
+            Some(false) => {
+                let event =
+                    author.note("A note from an ephemeral keypair. It can be ignored or deleted.");
+                match session.post(&event).await {
+                    Ok((true, _)) => Outcome::Pass,
+                    Ok((false, reason)) => {
+                        Outcome::Fail(format!("relay rejected a free note: {}", reason))
+                    }
+                    Err(e) => e.into(),
+                }
+            }
+        }
+    });
+    session.close().await;
+
+    let mut session = Session::open(relay_url);
+    record!(results, session, 11, "min_pow_difficulty", {
+        match limitation.min_pow_difficulty {
+            None | Some(0) => Outcome::Skip("no difficulty advertised".to_owned()),
+            Some(difficulty) => {
+                // Make sure we don't meet the difficulty by luck
+                let mut n: u64 = 0;
+                let event = loop {
+                    let event = author.note(&format!("A note without proof of work ({}).", n));
+                    if pow_difficulty(&event) < difficulty {
+                        break event;
+                    }
+                    n += 1;
+                };
+                match session.post(&event).await {
+                    Ok((true, _)) => Outcome::Fail(format!(
+                        "relay accepted an event below difficulty {}",
+                        difficulty
+                    )),
+                    Ok((false, reason)) if crate::reason_prefix(&reason) != Some("pow") => {
+                        Outcome::Fail(format!("rejected without a pow: prefix: {}", reason))
+                    }
+                    result => refused(result),
+                }
+            }
+        }
+    });
+    session.close().await;
+
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_an_explicit_rejection_is_a_refusal() {
+        assert_eq!(
+            refused(Ok((false, "invalid: too big".into()))),
+            Outcome::Pass
+        );
+        assert!(matches!(
+            refused(Ok((true, String::new()))),
+            Outcome::Fail(_)
+        ));
+        let closed = SessionError::Closed("connection closed".into());
+        assert!(matches!(refused(Err(closed)), Outcome::Fail(_)));
+        let silent = SessionError::Failed("no response within 10 seconds".into());
+        assert!(matches!(refused(Err(silent)), Outcome::Fail(_)));
+        let notice = SessionError::Inconclusive("only NOTICE: slow down".into());
+        assert!(matches!(refused(Err(notice)), Outcome::Skip(_)));
+    }
+}
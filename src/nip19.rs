@@ -0,0 +1,286 @@
+//! Parsing of the ways people write keys, event ids and addresses: hex, NIP-19
+//! bech32 (npub, nprofile, note, nevent, naddr) and NIP-21 `nostr:` URIs.
+//!
+//! Relay hints found in nprofile, nevent and naddr are returned so callers can query
+//! those relays too.
+
+use crate::outbox::normalize_relay_url;
+use nostr_types::{EventKind, Id, NostrBech32, PublicKey, UncheckedUrl};
+
+pub struct PubkeyArg {
+    pub pubkey: PublicKey,
+    pub relays: Vec<String>,
+}
+
+pub struct IdArg {
+    pub id: Id,
+    pub relays: Vec<String>,
+    pub author: Option<PublicKey>,
+    pub kind: Option<EventKind>,
+}
+
+pub struct AddressArg {
+    pub kind: EventKind,
+    pub author: PublicKey,
+    pub d: String,
+    pub relays: Vec<String>,
+}
+
+/// Remove a NIP-21 `nostr:` prefix, if present
+pub fn strip_uri(s: &str) -> &str {
+    let s = s.trim();
+    match s.get(..6) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("nostr:") => &s[6..],
+        _ => s,
+    }
+}
+
+fn hints(relays: &[UncheckedUrl]) -> Vec<String> {
+    relays
+        .iter()
+        .filter_map(|r| normalize_relay_url(r.as_str()))
+        .collect()
+}
+
+fn unparseable(s: &str, expected: &str) -> Box<dyn std::error::Error> {
+    Box::new(std::io::Error::other(format!(
+        "Could not parse {}, expected {}",
+        s, expected
+    )))
+}
+
+/// Parse a public key given as hex, npub or nprofile
+pub fn parse_pubkey(s: &str) -> Result<PubkeyArg, Box<dyn std::error::Error>> {
+    const EXPECTED: &str = "a hex public key, npub or nprofile";
+    let s = strip_uri(s);
+    match NostrBech32::try_from_string(s) {
+        Some(NostrBech32::Pubkey(pubkey)) => Ok(PubkeyArg {
+            pubkey,
+            relays: vec![],
+        }),
+        Some(NostrBech32::Profile(profile)) => Ok(PubkeyArg {
+            pubkey: profile.pubkey,
+            relays: hints(&profile.relays),
+        }),
+        Some(_) => Err(unparseable(s, EXPECTED)),
+        None => match PublicKey::try_from_hex_string(s, true) {
+            Ok(pubkey) => Ok(PubkeyArg {
+                pubkey,
+                relays: vec![],
+            }),
+            Err(_) => Err(unparseable(s, EXPECTED)),
+        },
+    }
+}
+
+/// Parse an event id given as hex, note or nevent
+pub fn parse_id(s: &str) -> Result<IdArg, Box<dyn std::error::Error>> {
+    const EXPECTED: &str = "a hex event id, note or nevent";
+    let s = strip_uri(s);
+    match NostrBech32::try_from_string(s) {
+        Some(NostrBech32::Id(id)) => Ok(IdArg {
+            id,
+            relays: vec![],
+            author: None,
+            kind: None,
+        }),
+        Some(NostrBech32::NEvent(nevent)) => Ok(IdArg {
+            id: nevent.id,
+            relays: hints(&nevent.relays),
+            author: nevent.author,
+            kind: nevent.kind,
+        }),
+        Some(_) => Err(unparseable(s, EXPECTED)),
+        None => match Id::try_from_hex_string(s) {
+            Ok(id) => Ok(IdArg {
+                id,
+                relays: vec![],
+                author: None,
+                kind: None,
+            }),
+            Err(_) => Err(unparseable(s, EXPECTED)),
+        },
+    }
+}
+
+/// Parse an addressable event's coordinates given as naddr or kind:pubkey:d (the
+/// pubkey may be hex or npub)
+pub fn parse_address(s: &str) -> Result<AddressArg, Box<dyn std::error::Error>> {
+    const EXPECTED: &str = "an naddr or kind:pubkey:d";
+    let s = strip_uri(s);
+    match NostrBech32::try_from_string(s) {
+        Some(NostrBech32::NAddr(naddr)) => Ok(AddressArg {
+            kind: naddr.kind,
+            author: naddr.author,
+            d: naddr.d,
+            relays: hints(&naddr.relays),
+        }),
+        Some(_) => Err(unparseable(s, EXPECTED)),
+        None => {
+            let mut parts = s.splitn(3, ':');
+            let (kind, author, d) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(k), Some(a), Some(d)) => (k, a, d),
+                _ => return Err(unparseable(s, EXPECTED)),
+            };
+            let kind: u32 = kind.parse().map_err(|_| unparseable(s, EXPECTED))?;
+            let author = parse_pubkey(author)?.pubkey;
+            Ok(AddressArg {
+                kind: kind.into(),
+                author,
+                d: d.to_owned(),
+                relays: vec![],
+            })
+        }
+    }
+}
+
+/// The relay the user gave followed by any hints, without duplicates
+pub fn with_hints(relay_url: &str, hints: &[String]) -> Vec<String> {
+    let mut relays: Vec<String> = vec![relay_url.to_owned()];
+    let given = normalize_relay_url(relay_url);
+    for hint in hints {
+        if Some(hint) != given.as_ref() && !relays.contains(hint) {
+            relays.push(hint.clone());
+        }
+    }
+    relays
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conformance::Author;
+    use nostr_types::{NAddr, NEvent, Profile};
+
+    fn urls(relays: &[&str]) -> Vec<UncheckedUrl> {
+        relays.iter().map(|r| UncheckedUrl::from_str(r)).collect()
+    }
+
+    #[test]
+    fn pubkeys() {
+        let pubkey = Author::new().pubkey;
+        let npub = NostrBech32::Pubkey(pubkey).to_string();
+        let nprofile = NostrBech32::Profile(Profile {
+            pubkey,
+            relays: urls(&["wss://Relay.Example.com/", "https://not.a.relay"]),
+        })
+        .to_string();
+
+        for s in [
+            pubkey.as_hex_string(),
+            npub.clone(),
+            format!("nostr:{}", npub),
+            format!("NOSTR:{}", npub),
+            format!("  {}\n", npub),
+        ] {
+            let arg = parse_pubkey(&s).unwrap();
+            assert_eq!(arg.pubkey, pubkey, "{}", s);
+            assert!(arg.relays.is_empty(), "{}", s);
+        }
+
+        for s in [nprofile.clone(), format!("nostr:{}", nprofile)] {
+            let arg = parse_pubkey(&s).unwrap();
+            assert_eq!(arg.pubkey, pubkey);
+            assert_eq!(arg.relays, vec!["wss://relay.example.com"]);
+        }
+    }
+
+    #[test]
+    fn ids() {
+        let author = Author::new();
+        let id = author.note("hello").id;
+        let note = NostrBech32::Id(id).to_string();
+        let nevent = NostrBech32::NEvent(NEvent {
+            id,
+            relays: urls(&["wss://relay.example.com"]),
+            kind: Some(EventKind::TextNote),
+            author: Some(author.pubkey),
+        })
+        .to_string();
+
+        for s in [id.as_hex_string(), note.clone(), format!("NOSTR:{}", note)] {
+            let arg = parse_id(&s).unwrap();
+            assert_eq!(arg.id, id, "{}", s);
+            assert!(arg.relays.is_empty() && arg.author.is_none() && arg.kind.is_none());
+        }
+
+        let arg = parse_id(&format!("nostr:{}", nevent)).unwrap();
+        assert_eq!(arg.id, id);
+        assert_eq!(arg.relays, vec!["wss://relay.example.com"]);
+        assert_eq!(arg.author, Some(author.pubkey));
+        assert_eq!(arg.kind, Some(EventKind::TextNote));
+    }
+
+    #[test]
+    fn addresses() {
+        let pubkey = Author::new().pubkey;
+        let article: EventKind = 30023u32.into();
+        let naddr = NostrBech32::NAddr(NAddr {
+            d: "my-article".to_owned(),
+            relays: urls(&["wss://relay.example.com/"]),
+            kind: article,
+            author: pubkey,
+        })
+        .to_string();
+
+        let arg = parse_address(&format!("nostr:{}", naddr)).unwrap();
+        assert_eq!(arg.kind, article);
+        assert_eq!(arg.author, pubkey);
+        assert_eq!(arg.d, "my-article");
+        assert_eq!(arg.relays, vec!["wss://relay.example.com"]);
+
+        let npub = NostrBech32::Pubkey(pubkey).to_string();
+        for author in [pubkey.as_hex_string(), npub] {
+            let arg = parse_address(&format!("30023:{}:a:b:c", author)).unwrap();
+            assert_eq!(arg.kind, article);
+            assert_eq!(arg.author, pubkey);
+            assert_eq!(arg.d, "a:b:c");
+            assert!(arg.relays.is_empty());
+        }
+
+        let arg = parse_address(&format!("30023:{}:", pubkey.as_hex_string())).unwrap();
+        assert_eq!(arg.d, "");
+    }
+
+    #[test]
+    fn wrong_types_are_rejected() {
+        let author = Author::new();
+        let npub = NostrBech32::Pubkey(author.pubkey).to_string();
+        let note = NostrBech32::Id(author.note("hello").id).to_string();
+
+        assert!(parse_id(&npub).is_err());
+        assert!(parse_id(&format!("nostr:{}", npub)).is_err());
+        assert!(parse_pubkey(&note).is_err());
+        assert!(parse_address(&npub).is_err());
+        assert!(parse_address(&note).is_err());
+
+        assert!(parse_pubkey("").is_err());
+        assert!(parse_id("abcd").is_err());
+        assert!(parse_pubkey("npub1notreallyakey").is_err());
+        assert!(parse_address("30023:nobody:d").is_err());
+        assert!(parse_address(&format!("article:{}:d", author.pubkey.as_hex_string())).is_err());
+        assert!(parse_address(&author.pubkey.as_hex_string()).is_err());
+    }
+
+    #[test]
+    fn hints_follow_the_given_relay_once() {
+        let hints = vec![
+            "wss://relay.example.com".to_owned(),
+            "wss://other.example.com".to_owned(),
+            "wss://other.example.com".to_owned(),
+            "wss://third.example.com".to_owned(),
+        ];
+        assert_eq!(
+            with_hints("wss://Relay.Example.com/", &hints),
+            vec![
+                "wss://Relay.Example.com/",
+                "wss://other.example.com",
+                "wss://third.example.com"
+            ]
+        );
+        assert_eq!(
+            with_hints("wss://relay.example.com", &[]),
+            vec!["wss://relay.example.com"]
+        );
+    }
+}
@@ -0,0 +1,193 @@
+//! The outbox model: finding the relays a user publishes to from their NIP-65
+//! relay list (kind 10002), and fetching from those relays.
+
+use crate::filter::tag_fields;
+use futures_util::future::join_all;
+use nostr_types::{Event, EventKind, Filter, PublicKey};
+use std::cmp::Reverse;
+
+/// Relays that are widely used to publish and look up relay lists
+pub const BOOTSTRAP_RELAYS: &[&str] = &[
+    "wss://purplepag.es",
+    "wss://relay.damus.io",
+    "wss://nos.lol",
+    "wss://relay.nostr.band",
+];
+
+/// One entry of a NIP-65 relay list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayListEntry {
+    pub url: String,
+    pub read: bool,
+    pub write: bool,
+}
+
+/// Clean up a relay URL so that the same relay is always written the same way.
+/// Returns None if it isn't a websocket URL we can connect to.
+pub fn normalize_relay_url(url: &str) -> Option<String> {
+    let url = url.trim().trim_end_matches('/');
+    let uri: http::Uri = url.parse().ok()?;
+    match uri.scheme_str() {
+        Some("ws") | Some("wss") => {}
+        _ => return None,
+    }
+    let authority = uri.authority()?.as_str();
+    if authority.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "{}://{}{}",
+        uri.scheme_str()?,
+        authority.to_lowercase(),
+        uri.path().trim_end_matches('/')
+    ))
+}
+
+/// The relays in a kind 10002 relay list
+pub fn parse_relay_list(event: &Event) -> Vec<RelayListEntry> {
+    let mut entries: Vec<RelayListEntry> = Vec::new();
+    for tag in tag_fields(event) {
+        if tag.first().map(|t| t.as_str()) != Some("r") {
+            continue;
+        }
+        let url = match tag.get(1).and_then(|u| normalize_relay_url(u)) {
+            Some(u) => u,
+            None => continue,
+        };
+        let (read, write) = match tag.get(2).map(|m| m.as_str()) {
+            Some("read") => (true, false),
+            Some("write") => (false, true),
+            _ => (true, true),
+        };
+        // A relay listed twice (say once as read and once as write) is both
+        match entries.iter_mut().find(|e| e.url == url) {
+            Some(entry) => {
+                entry.read |= read;
+                entry.write |= write;
+            }
+            None => entries.push(RelayListEntry { url, read, write }),
+        }
+    }
+    entries
+}
+
+/// Ask all of `relays` at once for the user's relay list, returning the newest
+/// one any of them has
+pub async fn find_relay_list(
+    pubkey: PublicKey,
+    relays: &[String],
+) -> Result<Option<Event>, Box<dyn std::error::Error>> {
+    let mut filter = Filter::new();
+    filter.add_author(pubkey);
+    filter.add_event_kind(EventKind::RelayList);
+    filter.limit = Some(1);
+
+    Ok(newest(relays, filter).await?.map(|(_, event)| event))
+}
+
+/// Ask all of `relays` at once for events matching `filter`, returning the newest
+/// any of them has (the lowest id on a tie, as NIP-01 has it for replaceable
+/// events) and the relay it came from. Relays that fail are reported and skipped,
+/// and it is an error if they all fail.
+pub async fn newest(
+    relays: &[String],
+    filter: Filter,
+) -> Result<Option<(String, Event)>, Box<dyn std::error::Error>> {
+    let fetches = relays.iter().map(|relay| {
+        let filter = filter.clone();
+        async move { (relay, crate::fetch_all(relay, vec![filter]).await) }
+    });
+    let mut newest: Option<(String, Event)> = None;
+    let mut answered: usize = 0;
+    for (relay, result) in join_all(fetches).await {
+        let events = match result {
+            Ok(events) => events,
+            Err(e) => {
+                eprintln!("{}: FAILED: {}", relay, e);
+                continue;
+            }
+        };
+        answered += 1;
+        for event in events {
+            let newer = match &newest {
+                Some((_, n)) => {
+                    (event.created_at, Reverse(event.id.0)) > (n.created_at, Reverse(n.id.0))
+                }
+                None => true,
+            };
+            if newer {
+                newest = Some((relay.clone(), event));
+            }
+        }
+    }
+    if answered == 0 && !relays.is_empty() {
+        return Err(Box::new(std::io::Error::other(
+            "none of the relays answered",
+        )));
+    }
+    Ok(newest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conformance::Author;
+    use nostr_types::Tag;
+
+    #[test]
+    fn relay_urls_are_normalized() {
+        let normalized = |url: &str| normalize_relay_url(url);
+        assert_eq!(
+            normalized("wss://relay.example"),
+            Some("wss://relay.example".into())
+        );
+        assert_eq!(
+            normalized(" wss://Relay.Example/ "),
+            Some("wss://relay.example".into())
+        );
+        assert_eq!(
+            normalized("ws://localhost:7777"),
+            Some("ws://localhost:7777".into())
+        );
+        assert_eq!(
+            normalized("wss://relay.example/nostr/"),
+            Some("wss://relay.example/nostr".into())
+        );
+        assert_eq!(normalized("https://relay.example"), None);
+        assert_eq!(normalized("relay.example"), None);
+        assert_eq!(normalized("wss://"), None);
+        assert_eq!(normalized(""), None);
+    }
+
+    #[test]
+    fn relay_lists() {
+        let tags: [&[&str]; 9] = [
+            &["r", "wss://both.example"],
+            &["r", "wss://read.example/", "read"],
+            &["r", "wss://write.example", "write"],
+            &["r", "wss://Split.example", "read"],
+            &["r", "wss://split.example/", "write"],
+            &["r", "wss://both.example", "read"],
+            &["r", "https://not-a-relay.example"],
+            &["r"],
+            &["p", "wss://not-an-r-tag.example"],
+        ];
+        let tags = tags.iter().map(|t| Tag::new(t)).collect();
+        let event = Author::new().sign(10002, 1_700_000_000, tags, "");
+
+        let entry = |url: &str, read: bool, write: bool| RelayListEntry {
+            url: url.to_owned(),
+            read,
+            write,
+        };
+        assert_eq!(
+            parse_relay_list(&event),
+            [
+                entry("wss://both.example", true, true),
+                entry("wss://read.example", true, false),
+                entry("wss://write.example", false, true),
+                entry("wss://split.example", true, true),
+            ]
+        );
+    }
+}
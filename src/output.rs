@@ -0,0 +1,365 @@
+//! How fetch, count and dump tools print what they get, chosen with environment
+//! variables so every tool supports the same formats:
+//!
+//! - `NOSTR_PROBE_OUTPUT`: `jsonl` (the default, one event per line), `json` (a
+//!   pretty-printed array), `csv`, `envelope` (one JSON object per line with the relay
+//!   and subscription the event arrived on) or `pretty` (see [`render::pretty`]).
+//! - `NOSTR_PROBE_COLUMNS`: the CSV columns, comma separated, from `id`, `pubkey`,
+//!   `kind`, `created_at`, `content`, `tags` (the number of tags) and `tags:<name>`
+//!   (the number of tags with that name). Defaults to `id,pubkey,kind,created_at,content`.
+//! - `NOSTR_PROBE_ARCHIVE`: an archive directory (see [`Store`]). Every event printed
+//!   that verifies is also added to it.
+//!
+//! Tools must finish the output when done, which closes the JSON array, saves the
+//! names learned for `pretty` and syncs the archive. Holding the guard from
+//! [`finish_on_drop`] does this even when the tool fails part way.
+
+use crate::filter::tag_fields;
+use crate::render::{self, Names};
+use crate::store::Store;
+use lazy_static::lazy_static;
+use nostr_types::Event;
+use serde_json::{json, Value};
+use std::io::IsTerminal;
+use std::str::FromStr;
+use std::sync::{Mutex, MutexGuard};
+
+pub const OUTPUT_ENV_VAR: &str = "NOSTR_PROBE_OUTPUT";
+pub const COLUMNS_ENV_VAR: &str = "NOSTR_PROBE_COLUMNS";
+pub const ARCHIVE_ENV_VAR: &str = "NOSTR_PROBE_ARCHIVE";
+
+const DEFAULT_COLUMNS: &str = "id,pubkey,kind,created_at,content";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Jsonl,
+    Json,
+    Csv,
+    Envelope,
+    Pretty,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "jsonl" => Ok(Format::Jsonl),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            "envelope" => Ok(Format::Envelope),
+            "pretty" => Ok(Format::Pretty),
+            _ => Err(format!(
+                "Unknown output format {}, expected jsonl, json, csv, envelope or pretty",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Column {
+    Id,
+    Pubkey,
+    Kind,
+    CreatedAt,
+    Content,
+    Tags,
+    TagCount(String),
+}
+
+impl FromStr for Column {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Column, String> {
+        match s {
+            "id" => Ok(Column::Id),
+            "pubkey" => Ok(Column::Pubkey),
+            "kind" => Ok(Column::Kind),
+            "created_at" => Ok(Column::CreatedAt),
+            "content" => Ok(Column::Content),
+            "tags" => Ok(Column::Tags),
+            _ => match s.strip_prefix("tags:") {
+                Some(name) if !name.is_empty() => Ok(Column::TagCount(name.to_owned())),
+                _ => Err(format!("Unknown CSV column {}", s)),
+            },
+        }
+    }
+}
+
+impl Column {
+    fn header(&self) -> String {
+        match self {
+            Column::Id => "id".to_owned(),
+            Column::Pubkey => "pubkey".to_owned(),
+            Column::Kind => "kind".to_owned(),
+            Column::CreatedAt => "created_at".to_owned(),
+            Column::Content => "content".to_owned(),
+            Column::Tags => "tags".to_owned(),
+            Column::TagCount(name) => format!("tags:{}", name),
+        }
+    }
+
+    fn value(&self, event: &Event, tags: &[Vec<String>]) -> String {
+        match self {
+            Column::Id => event.id.as_hex_string(),
+            Column::Pubkey => event.pubkey.as_hex_string(),
+            Column::Kind => {
+                let kind: u32 = event.kind.into();
+                kind.to_string()
+            }
+            Column::CreatedAt => event.created_at.0.to_string(),
+            Column::Content => event.content.clone(),
+            Column::Tags => tags.len().to_string(),
+            Column::TagCount(name) => tags
+                .iter()
+                .filter(|t| t.first() == Some(name))
+                .count()
+                .to_string(),
+        }
+    }
+}
+
+/// Where a printed event or count came from, for the envelope format
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Source<'a> {
+    pub relay: Option<&'a str>,
+    pub subscription: Option<&'a str>,
+}
+
+impl<'a> Source<'a> {
+    pub fn new(relay: &'a str, subscription: &'a str) -> Source<'a> {
+        Source {
+            relay: Some(relay),
+            subscription: Some(subscription),
+        }
+    }
+
+    pub fn relay(relay: &'a str) -> Source<'a> {
+        Source {
+            relay: Some(relay),
+            subscription: None,
+        }
+    }
+}
+
+/// Quote a CSV field if it needs it (RFC 4180)
+pub fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+pub struct Output {
+    format: Format,
+    columns: Vec<Column>,
+    /// Whether anything has been printed yet (so the array is open or the header out)
+    started: bool,
+    finished: bool,
+    names: Option<Names>,
+    archive: Option<Store>,
+}
+
+impl Output {
+    pub fn new(format: Format, columns: Vec<Column>) -> Output {
+        Output {
+            format,
+            columns,
+            started: false,
+            finished: false,
+            names: None,
+            archive: None,
+        }
+    }
+
+    /// Also add every event that verifies to an archive
+    pub fn archive_to(&mut self, store: Store) {
+        self.archive = Some(store);
+    }
+
+    /// The output the user asked for in the environment. Bad settings are reported
+    /// and the defaults used instead.
+    pub fn from_env() -> Output {
+        let format = match std::env::var(OUTPUT_ENV_VAR) {
+            Ok(s) => s.parse().unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                Format::Jsonl
+            }),
+            Err(_) => Format::Jsonl,
+        };
+        let columns = std::env::var(COLUMNS_ENV_VAR).unwrap_or(DEFAULT_COLUMNS.to_owned());
+        let columns = columns
+            .split(',')
+            .map(|c| c.trim())
+            .filter(|c| !c.is_empty())
+            .filter_map(|c| match c.parse() {
+                Ok(column) => Some(column),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    None
+                }
+            })
+            .collect();
+        let mut output = Output::new(format, columns);
+        if let Some(dir) = std::env::var_os(ARCHIVE_ENV_VAR) {
+            match Store::open(dir.as_ref()) {
+                Ok(store) => output.archive_to(store),
+                Err(e) => eprintln!("Could not open the archive: {}", e),
+            }
+        }
+        output
+    }
+
+    // Separate array elements and write the CSV header, before the first item
+    fn start(&mut self, csv_header: &str) {
+        match self.format {
+            Format::Json => print!("{}", if self.started { ",\n" } else { "[\n" }),
+            Format::Csv if !self.started => println!("{}", csv_header),
+            _ => {}
+        }
+        self.started = true;
+    }
+
+    pub fn event(
+        &mut self,
+        source: Source,
+        event: &Event,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(store) = &mut self.archive {
+            if event.verify(None).is_ok() {
+                store.add(event)?;
+            }
+        }
+        match self.format {
+            Format::Jsonl => println!("{}", serde_json::to_string(event)?),
+            Format::Json => {
+                self.start("");
+                print!("{}", serde_json::to_string_pretty(event)?);
+            }
+            Format::Csv => {
+                let header: Vec<String> = self.columns.iter().map(|c| c.header()).collect();
+                self.start(&header.join(","));
+                let tags = tag_fields(event);
+                let row: Vec<String> = self
+                    .columns
+                    .iter()
+                    .map(|c| csv_field(&c.value(event, &tags)))
+                    .collect();
+                println!("{}", row.join(","));
+            }
+            Format::Envelope => {
+                let envelope = json!({
+                    "relay": source.relay,
+                    "subscription": source.subscription,
+                    "event": event,
+                });
+                println!("{}", serde_json::to_string(&envelope)?);
+            }
+            Format::Pretty => {
+                // Pretty printing a metadata event remembers the author's name for later
+                let names = self.names.get_or_insert_with(Names::load);
+                names.learn(event);
+                let color = std::io::stdout().is_terminal();
+                println!("{}", render::pretty(event, names, color));
+            }
+        }
+        Ok(())
+    }
+
+    /// Print the result of a COUNT
+    pub fn count(
+        &mut self,
+        source: Source,
+        count: &Value,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match self.format {
+            Format::Jsonl => println!("{}", serde_json::to_string(count)?),
+            Format::Json => {
+                self.start("");
+                print!("{}", serde_json::to_string_pretty(count)?);
+            }
+            Format::Csv => {
+                self.start("count");
+                let n = count.get("count").unwrap_or(count);
+                println!("{}", csv_field(&n.to_string()));
+            }
+            Format::Envelope => {
+                let envelope = json!({
+                    "relay": source.relay,
+                    "subscription": source.subscription,
+                    "count": count,
+                });
+                println!("{}", serde_json::to_string(&envelope)?);
+            }
+            Format::Pretty => {
+                let n = count.get("count").unwrap_or(count);
+                println!("Count: {}", n);
+            }
+        }
+        Ok(())
+    }
+
+    /// Close anything left open (the JSON array), save the names learned and sync
+    /// the archive. Only the first call does anything.
+    pub fn finish(&mut self) {
+        if self.finished {
+            return;
+        }
+        self.finished = true;
+        if self.format == Format::Json {
+            println!("{}", if self.started { "\n]" } else { "[]" });
+        }
+        if let Some(names) = &mut self.names {
+            names.save();
+        }
+        if let Some(store) = &mut self.archive {
+            if let Err(e) = store.sync() {
+                eprintln!("Could not sync the archive: {}", e);
+            }
+        }
+    }
+}
+
+lazy_static! {
+    static ref OUTPUT: Mutex<Output> = Mutex::new(Output::from_env());
+}
+
+fn global() -> MutexGuard<'static, Output> {
+    match OUTPUT.lock() {
+        Ok(output) => output,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// Print an event in the format the user chose
+pub fn event(source: Source, event: &Event) -> Result<(), Box<dyn std::error::Error>> {
+    global().event(source, event)
+}
+
+/// Print a COUNT result in the format the user chose
+pub fn count(source: Source, count: &Value) -> Result<(), Box<dyn std::error::Error>> {
+    global().count(source, count)
+}
+
+/// Finish the output, after the last event
+pub fn finish() {
+    global().finish()
+}
+
+/// Finishes the output when dropped
+#[must_use = "the output is finished when this is dropped"]
+pub struct Finish;
+
+impl Drop for Finish {
+    fn drop(&mut self) {
+        finish()
+    }
+}
+
+/// A guard that finishes the output when it goes out of scope, so that an early
+/// return still leaves valid JSON. Take it at the start of `main`.
+pub fn finish_on_drop() -> Finish {
+    Finish
+}
@@ -0,0 +1,335 @@
+//! Recording of the frames a `Probe` sends and receives, and the pieces needed to
+//! replay them.
+//!
+//! A session file is JSONL, one `Frame` per line. Several connections (even from
+//! several processes) may append to the same file; each connection gets its own id.
+
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+/// If this is set in the environment, every `Probe` records to the file it names
+pub const RECORD_ENV_VAR: &str = "NOSTR_PROBE_RECORD";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Frame {
+    /// Milliseconds since the unix epoch
+    pub time_ms: u64,
+    pub connection: String,
+    pub relay: String,
+    pub direction: Direction,
+    pub text: String,
+}
+
+pub struct Recorder {
+    file: std::fs::File,
+    connection: String,
+    relay: String,
+}
+
+impl Recorder {
+    pub fn open(path: &Path, relay_url: &str) -> std::io::Result<Recorder> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Recorder {
+            file,
+            connection: hex::encode(rand::random::<[u8; 4]>()),
+            relay: relay_url.to_owned(),
+        })
+    }
+
+    pub fn record(&mut self, direction: Direction, text: &str) -> std::io::Result<()> {
+        let frame = Frame {
+            time_ms: now_ms(),
+            connection: self.connection.clone(),
+            relay: self.relay.clone(),
+            direction,
+            text: text.to_owned(),
+        };
+        // One write per line, so that concurrent appenders don't interleave
+        let mut line = serde_json::to_string(&frame)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())
+    }
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Load a session file. A process killed while appending leaves a partial last
+/// line, which is skipped with a warning; a bad line anywhere else is an error.
+pub fn load(path: &Path) -> Result<Vec<Frame>, Box<dyn std::error::Error>> {
+    let file = std::fs::File::open(path)?;
+    read_frames(BufReader::new(file), &path.display().to_string())
+}
+
+fn read_frames<R: BufRead>(
+    reader: R,
+    name: &str,
+) -> Result<Vec<Frame>, Box<dyn std::error::Error>> {
+    let mut frames: Vec<Frame> = Vec::new();
+    // Read bytes, since a partial line may end part way through a character
+    let mut lines = reader.split(b'\n').enumerate().peekable();
+    while let Some((n, line)) = lines.next() {
+        let line = line?;
+        if line.iter().all(|b| b.is_ascii_whitespace()) {
+            continue;
+        }
+        match serde_json::from_slice(&line) {
+            Ok(frame) => frames.push(frame),
+            Err(e) if lines.peek().is_none() => {
+                eprintln!("Ignoring incomplete last line {} of {}: {}", n + 1, name, e);
+            }
+            Err(e) => {
+                return Err(Box::new(std::io::Error::other(format!(
+                    "{} line {}: {}",
+                    name,
+                    n + 1,
+                    e
+                ))));
+            }
+        }
+    }
+    Ok(frames)
+}
+
+/// The connection ids in a session, in the order they first appear
+pub fn connections(frames: &[Frame]) -> Vec<String> {
+    let mut connections: Vec<String> = Vec::new();
+    for frame in frames {
+        if !connections.contains(&frame.connection) {
+            connections.push(frame.connection.clone());
+        }
+    }
+    connections
+}
+
+/// A frame we sent and everything received after it, up to the next frame we sent
+#[derive(Debug, Clone)]
+pub struct Exchange {
+    /// None for what the relay said before we sent anything
+    pub request: Option<String>,
+    /// Each response, with its delay in milliseconds after the request
+    pub responses: Vec<(u64, String)>,
+}
+
+/// Split one connection's frames into exchanges
+pub fn exchanges(frames: &[Frame], connection: &str) -> Vec<Exchange> {
+    let mut exchanges: Vec<Exchange> = Vec::new();
+    let mut start: u64 = 0;
+    for frame in frames.iter().filter(|f| f.connection == connection) {
+        match frame.direction {
+            Direction::Sent => {
+                start = frame.time_ms;
+                exchanges.push(Exchange {
+                    request: Some(frame.text.clone()),
+                    responses: Vec::new(),
+                });
+            }
+            Direction::Received => {
+                if exchanges.is_empty() {
+                    start = frame.time_ms;
+                    exchanges.push(Exchange {
+                        request: None,
+                        responses: Vec::new(),
+                    });
+                }
+                let delay = frame.time_ms.saturating_sub(start);
+                if let Some(exchange) = exchanges.last_mut() {
+                    exchange.responses.push((delay, frame.text.clone()));
+                }
+            }
+        }
+    }
+    exchanges
+}
+
+// Verbs whose second element is a subscription id
+const SUBSCRIPTION_VERBS: &[&str] = &[
+    "REQ",
+    "COUNT",
+    "CLOSE",
+    "EOSE",
+    "CLOSED",
+    "EVENT",
+    "NEG-OPEN",
+    "NEG-MSG",
+    "NEG-ERR",
+    "NEG-CLOSE",
+];
+
+/// The subscription id of a message, if it has one
+pub fn sub_id(text: &str) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(text).ok()?;
+    let array = value.as_array()?;
+    if !SUBSCRIPTION_VERBS.contains(&array.first()?.as_str()?) {
+        return None;
+    }
+    array.get(1)?.as_str().map(|s| s.to_owned())
+}
+
+/// A message with its subscription id removed, so that the same request made with a
+/// different subscription id compares equal
+pub fn without_sub_id(text: &str) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(text).ok()?;
+    let mut array = value.as_array()?.clone();
+    sub_id(text)?;
+    array.remove(1);
+    Some(serde_json::Value::Array(array).to_string())
+}
+
+/// Replace subscription id `from` with `to` in a message
+pub fn rewrite_sub_id(text: &str, from: &str, to: &str) -> String {
+    if sub_id(text).as_deref() != Some(from) {
+        return text.to_owned();
+    }
+    let mut value: serde_json::Value = match serde_json::from_str(text) {
+        Ok(v) => v,
+        Err(_) => return text.to_owned(),
+    };
+    value[1] = serde_json::Value::String(to.to_owned());
+    value.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(connection: &str, time_ms: u64, direction: Direction, text: &str) -> Frame {
+        Frame {
+            time_ms,
+            connection: connection.to_owned(),
+            relay: "wss://relay.example".to_owned(),
+            direction,
+            text: text.to_owned(),
+        }
+    }
+
+    fn sent(connection: &str, time_ms: u64, text: &str) -> Frame {
+        frame(connection, time_ms, Direction::Sent, text)
+    }
+
+    fn received(connection: &str, time_ms: u64, text: &str) -> Frame {
+        frame(connection, time_ms, Direction::Received, text)
+    }
+
+    #[test]
+    fn the_relay_may_speak_first() {
+        let frames = vec![
+            received("a", 100, r#"["AUTH","challenge"]"#),
+            received("a", 120, r#"["NOTICE","hello"]"#),
+            sent("a", 200, r#"["REQ","s1",{}]"#),
+            received("a", 250, r#"["EOSE","s1"]"#),
+        ];
+        let exchanges = exchanges(&frames, "a");
+        assert_eq!(exchanges.len(), 2);
+        assert_eq!(exchanges[0].request, None);
+        assert_eq!(
+            exchanges[0].responses,
+            [
+                (0, r#"["AUTH","challenge"]"#.to_owned()),
+                (20, r#"["NOTICE","hello"]"#.to_owned())
+            ]
+        );
+        assert_eq!(exchanges[1].request.as_deref(), Some(r#"["REQ","s1",{}]"#));
+        assert_eq!(
+            exchanges[1].responses,
+            [(50, r#"["EOSE","s1"]"#.to_owned())]
+        );
+    }
+
+    #[test]
+    fn connections_are_kept_apart() {
+        let frames = vec![
+            sent("a", 100, r#"["REQ","a1",{}]"#),
+            sent("b", 110, r#"["REQ","b1",{}]"#),
+            received("b", 130, r#"["EOSE","b1"]"#),
+            received("a", 150, r#"["EOSE","a1"]"#),
+            sent("b", 200, r#"["CLOSE","b1"]"#),
+        ];
+        assert_eq!(connections(&frames), ["a", "b"]);
+
+        let a = exchanges(&frames, "a");
+        assert_eq!(a.len(), 1);
+        assert_eq!(a[0].responses, [(50, r#"["EOSE","a1"]"#.to_owned())]);
+
+        let b = exchanges(&frames, "b");
+        assert_eq!(b.len(), 2);
+        assert_eq!(b[0].responses, [(20, r#"["EOSE","b1"]"#.to_owned())]);
+        assert_eq!(b[1].request.as_deref(), Some(r#"["CLOSE","b1"]"#));
+        assert!(b[1].responses.is_empty());
+
+        assert!(exchanges(&frames, "c").is_empty());
+    }
+
+    #[test]
+    fn subscription_ids() {
+        assert_eq!(sub_id(r#"["REQ","s1",{}]"#).as_deref(), Some("s1"));
+        assert_eq!(
+            sub_id(r#"["EVENT","s1",{"id":"x"}]"#).as_deref(),
+            Some("s1")
+        );
+        assert_eq!(sub_id(r#"["EVENT",{"id":"x"}]"#), None);
+        assert_eq!(sub_id(r#"["OK","x",true,""]"#), None);
+        assert_eq!(sub_id("not json"), None);
+
+        assert_eq!(
+            without_sub_id(r#"["REQ","s1",{"kinds":[1]}]"#),
+            without_sub_id(r#"["REQ","other",{"kinds":[1]}]"#)
+        );
+        assert_eq!(without_sub_id(r#"["NOTICE","x"]"#), None);
+    }
+
+    #[test]
+    fn rewriting_subscription_ids() {
+        let rewrite = |text: &str| rewrite_sub_id(text, "old", "new");
+        assert_eq!(
+            rewrite(r#"["EVENT","old",{"id":"x"}]"#),
+            r#"["EVENT","new",{"id":"x"}]"#
+        );
+        assert_eq!(rewrite(r#"["EOSE","old"]"#), r#"["EOSE","new"]"#);
+        assert_eq!(
+            rewrite(r#"["CLOSED","old","error: x"]"#),
+            r#"["CLOSED","new","error: x"]"#
+        );
+
+        // Other subscriptions and messages without one are left exactly as they were
+        for text in [
+            r#"["EOSE", "other"]"#,
+            r#"["OK","old",true,""]"#,
+            "not json",
+        ] {
+            assert_eq!(rewrite(text), text);
+        }
+    }
+
+    #[test]
+    fn a_partial_last_line_is_skipped() {
+        let good = serde_json::to_string(&sent("a", 1, r#"["REQ","s1",{}]"#)).unwrap();
+        let partial = &good[..good.len() / 2];
+
+        let file = format!("{}\n\n{}\n{}", good, good, partial);
+        let frames = read_frames(file.as_bytes(), "session").unwrap();
+        assert_eq!(frames.len(), 2);
+
+        let file = format!("{}\n{}\n{}\n", good, partial, good);
+        assert!(read_frames(file.as_bytes(), "session").is_err());
+
+        let mut bytes = format!("{}\n", good).into_bytes();
+        bytes.extend_from_slice(&[b'{', 0xe2, 0x82]);
+        assert_eq!(read_frames(&bytes[..], "session").unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,483 @@
+//! A readable rendering of events for people rather than programs: a header with
+//! the kind, author and time, the content wrapped with NIP-19 references decoded,
+//! and the tags as a table.
+
+use crate::filter::tag_fields;
+use crate::paint;
+use colorful::Color;
+use nostr_types::{Event, EventKind, NostrBech32, PublicKey, Unixtime};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+const WIDTH: usize = 80;
+const MAX_CELL: usize = 64;
+const BECH32_CHARS: &str = "qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const PREFIXES: &[&str] = &["npub1", "nprofile1", "note1", "nevent1", "naddr1"];
+
+/// Display names of authors, learned from the metadata (kind 0) events we have
+/// printed and kept between runs
+#[derive(Debug, Default)]
+pub struct Names {
+    path: Option<PathBuf>,
+    names: HashMap<String, String>,
+    /// Whether names have been learned since the cache was loaded or saved
+    changed: bool,
+}
+
+impl Names {
+    /// Load the cache from the data directory. A missing or broken cache is empty.
+    pub fn load() -> Names {
+        let path = dirs::data_dir().map(|mut p| {
+            p.push("nostr-probe");
+            p.push("names.json");
+            p
+        });
+        let names: HashMap<String, String> = path
+            .as_ref()
+            .and_then(|p| std::fs::read(p).ok())
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        // Caches written before names were cleaned up may hold control characters
+        let names = names
+            .into_iter()
+            .map(|(pubkey, name)| (pubkey, printable(&name)))
+            .collect();
+        Names {
+            path,
+            names,
+            changed: false,
+        }
+    }
+
+    pub fn get(&self, pubkey: &PublicKey) -> Option<&str> {
+        self.names.get(&pubkey.as_hex_string()).map(|n| n.as_str())
+    }
+
+    /// Remember the name in a metadata event. Call [`Names::save`] to keep it.
+    pub fn learn(&mut self, event: &Event) {
+        if event.kind != EventKind::Metadata {
+            return;
+        }
+        let metadata: serde_json::Value = match serde_json::from_str(&event.content) {
+            Ok(m) => m,
+            Err(_) => return,
+        };
+        let name = ["display_name", "name"]
+            .iter()
+            .filter_map(|field| metadata.get(*field).and_then(|n| n.as_str()))
+            .map(|n| n.trim())
+            .find(|n| !n.is_empty());
+        let name = match name {
+            Some(n) => printable(n),
+            None => return,
+        };
+        let pubkey = event.pubkey.as_hex_string();
+        if self.names.get(&pubkey) == Some(&name) {
+            return;
+        }
+        self.names.insert(pubkey, name);
+        self.changed = true;
+    }
+
+    /// Write the cache, if anything was learned since it was last written
+    pub fn save(&mut self) {
+        if !self.changed {
+            return;
+        }
+        if let Some(path) = &self.path {
+            if let Some(dir) = path.parent() {
+                let _ = std::fs::create_dir_all(dir);
+            }
+            if let Ok(json) = serde_json::to_vec(&self.names) {
+                let _ = std::fs::write(path, json);
+            }
+        }
+        self.changed = false;
+    }
+}
+
+/// Replace control characters, which could move the cursor or restyle the
+/// terminal, with visible stand-ins: `^[` for escape and the like for the rest of
+/// ASCII, `\u{FFFD}` for others. Newlines and tabs are kept.
+pub fn printable(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\n' | '\t' => out.push(c),
+            '\x00'..='\x1f' => {
+                out.push('^');
+                out.push((c as u8 + 0x40) as char);
+            }
+            '\x7f' => out.push_str("^?"),
+            c if c.is_control() => out.push('\u{FFFD}'),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// The kind's name, as nostr-types knows it, and number
+pub fn kind_name(kind: EventKind) -> String {
+    let number: u32 = kind.into();
+    let name = format!("{:?}", kind);
+    if name.starts_with("Other") {
+        format!("Kind {}", number)
+    } else {
+        format!("{} ({})", name, number)
+    }
+}
+
+/// How long ago (or how far in the future) a time is, roughly
+pub fn relative_age(then: i64, now: i64) -> String {
+    let secs = now - then;
+    let magnitude = secs.unsigned_abs();
+    let amount = match magnitude {
+        0..=59 => format!("{}s", magnitude),
+        60..=3599 => format!("{}m", magnitude / 60),
+        3600..=86399 => format!("{}h", magnitude / 3600),
+        86400..=31_535_999 => format!("{}d", magnitude / 86400),
+        _ => format!("{}y", magnitude / 31_536_000),
+    };
+    if secs < 0 {
+        format!("in {}", amount)
+    } else {
+        format!("{} ago", amount)
+    }
+}
+
+/// A time in the local time zone, with UTC alongside if that is different
+pub fn local_time(secs: i64) -> String {
+    let utc = crate::format_unixtime(secs);
+    match crate::localtime::offset_at(secs) {
+        Some((offset, zone)) if offset != 0 => format!(
+            "{} {} / {} UTC",
+            crate::format_unixtime(secs + offset),
+            zone,
+            utc
+        ),
+        _ => format!("{} UTC", utc),
+    }
+}
+
+fn author(pubkey: &PublicKey, names: &Names) -> String {
+    let npub = pubkey.as_bech32_string();
+    match names.get(pubkey) {
+        Some(name) => format!("{} ({})", name, npub),
+        None => npub,
+    }
+}
+
+fn short_npub(pubkey: &PublicKey, names: &Names) -> String {
+    match names.get(pubkey) {
+        Some(name) => format!("@{}", name),
+        None => {
+            let npub = pubkey.as_bech32_string();
+            format!("@{}…", &npub[..npub.len().min(14)])
+        }
+    }
+}
+
+// What a bech32 reference found in content stands for
+fn decode_reference(reference: &str, names: &Names) -> Option<String> {
+    Some(match NostrBech32::try_from_string(reference)? {
+        NostrBech32::Pubkey(pubkey) => short_npub(&pubkey, names),
+        NostrBech32::Profile(profile) => short_npub(&profile.pubkey, names),
+        NostrBech32::Id(id) => format!("note:{}", &id.as_hex_string()[..8]),
+        NostrBech32::NEvent(nevent) => format!("note:{}", &nevent.id.as_hex_string()[..8]),
+        NostrBech32::NAddr(naddr) => {
+            let kind: u32 = naddr.kind.into();
+            format!("naddr:{}:{}", kind, printable(&naddr.d))
+        }
+        _ => return None,
+    })
+}
+
+/// Replace `npub`, `nprofile`, `note`, `nevent` and `naddr` references (bare or as
+/// `nostr:` URIs) with what they refer to
+pub fn decode_references(text: &str, names: &Names, color: bool) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    loop {
+        let found = PREFIXES
+            .iter()
+            .filter_map(|p| rest.find(p).map(|i| (i, *p)))
+            .min_by_key(|(i, _)| *i);
+        let (start, prefix) = match found {
+            Some(f) => f,
+            None => break,
+        };
+        let end = start
+            + prefix.len()
+            + rest[start + prefix.len()..]
+                .find(|c: char| !BECH32_CHARS.contains(c))
+                .unwrap_or(rest.len() - start - prefix.len());
+        let uri_start = if rest[..start].ends_with("nostr:") {
+            start - 6
+        } else {
+            start
+        };
+        match decode_reference(&rest[start..end], names) {
+            Some(decoded) => {
+                out.push_str(&rest[..uri_start]);
+                out.push_str(&paint(&decoded, Color::Cyan, color));
+            }
+            None => out.push_str(&rest[..end]),
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+// Width on screen, not counting color escape sequences
+fn visible_width(s: &str) -> usize {
+    let mut width = 0;
+    let mut in_escape = false;
+    for c in s.chars() {
+        if in_escape {
+            in_escape = c != 'm';
+        } else if c == '\x1b' {
+            in_escape = true;
+        } else {
+            width += 1;
+        }
+    }
+    width
+}
+
+/// Wrap text to `width` columns, keeping the author's line breaks
+pub fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        let mut line_width = 0;
+        for word in paragraph.split_whitespace() {
+            let word_width = visible_width(word);
+            if line_width > 0 && line_width + 1 + word_width > width {
+                lines.push(std::mem::take(&mut line));
+                line_width = 0;
+            }
+            if line_width > 0 {
+                line.push(' ');
+                line_width += 1;
+            }
+            line.push_str(word);
+            line_width += word_width;
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+// Tags with each column padded to the widest cell in it
+fn tag_table(tags: &[Vec<String>]) -> Vec<String> {
+    let cell = |s: &String| -> String {
+        let s = printable(s);
+        if s.chars().count() > MAX_CELL {
+            let mut c: String = s.chars().take(MAX_CELL - 1).collect();
+            c.push('…');
+            c
+        } else {
+            s
+        }
+    };
+    let cells: Vec<Vec<String>> = tags.iter().map(|t| t.iter().map(cell).collect()).collect();
+    let columns = cells.iter().map(|r| r.len()).max().unwrap_or(0);
+    let widths: Vec<usize> = (0..columns)
+        .map(|c| {
+            cells
+                .iter()
+                .filter_map(|r| r.get(c))
+                .map(|s| s.chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    cells
+        .iter()
+        .map(|row| {
+            row.iter()
+                .enumerate()
+                .map(|(c, s)| format!("{:width$}", s, width = widths[c]))
+                .collect::<Vec<String>>()
+                .join("  ")
+                .trim_end()
+                .to_owned()
+        })
+        .collect()
+}
+
+/// Render an event for reading in a terminal
+pub fn pretty(event: &Event, names: &Names, color: bool) -> String {
+    let mut out = String::new();
+
+    let header = format!(
+        "{}  {}  {} ({})",
+        paint(&kind_name(event.kind), Color::Yellow, color),
+        paint(&author(&event.pubkey, names), Color::Green, color),
+        local_time(event.created_at.0),
+        relative_age(event.created_at.0, Unixtime::now().0)
+    );
+    out.push_str(&header);
+    out.push('\n');
+    out.push_str(&paint(
+        &format!("id {}", event.id.as_hex_string()),
+        Color::DarkGray,
+        color,
+    ));
+    out.push('\n');
+
+    if !event.content.is_empty() {
+        out.push('\n');
+        let content = decode_references(&printable(&event.content), names, color);
+        for line in wrap(&content, WIDTH - 2) {
+            out.push_str("  ");
+            out.push_str(&line);
+            out.push('\n');
+        }
+    }
+
+    let tags = tag_fields(event);
+    if !tags.is_empty() {
+        out.push('\n');
+        for row in tag_table(&tags) {
+            out.push_str("  ");
+            out.push_str(&paint(&row, Color::Blue, color));
+            out.push('\n');
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conformance::Author;
+    use nostr_types::{NAddr, NEvent, Tag, UncheckedUrl};
+
+    #[test]
+    fn wrapping() {
+        assert_eq!(wrap("one two three", 7), vec!["one two", "three"]);
+        assert_eq!(wrap("one two three", 80), vec!["one two three"]);
+
+        // The author's line breaks, blank lines included, are kept
+        assert_eq!(wrap("one\n\ntwo", 80), vec!["one", "", "two"]);
+        assert!(wrap("", 80).is_empty());
+
+        // A word longer than the width gets a line of its own
+        assert_eq!(wrap("a abcdefghij b", 5), vec!["a", "abcdefghij", "b"]);
+
+        // Color escapes take no room
+        let red = paint("red", Color::Red, true);
+        assert_eq!(
+            wrap(&format!("{} fox", red), 7),
+            vec![format!("{} fox", red)]
+        );
+    }
+
+    #[test]
+    fn references() {
+        let author = Author::new();
+        let npub = author.pubkey.as_bech32_string();
+        let short = format!("@{}…", &npub[..14]);
+        let mut names = Names::default();
+
+        assert_eq!(
+            decode_references(&format!("hi {}!", npub), &names, false),
+            format!("hi {}!", short)
+        );
+        for prefix in ["nostr:", "NOSTR:"] {
+            assert_eq!(
+                decode_references(
+                    &format!("cc nostr:{} and {}{}", npub, prefix, npub),
+                    &names,
+                    false
+                )
+                .matches(&short)
+                .count(),
+                2
+            );
+        }
+        assert_eq!(
+            decode_references(&format!("cc nostr:{}", npub), &names, false),
+            format!("cc {}", short)
+        );
+
+        let metadata = author.sign(0, 1, vec![], r#"{"name":"alice"}"#);
+        names.learn(&metadata);
+        assert_eq!(
+            decode_references(&format!("hi {}", npub), &names, false),
+            "hi @alice"
+        );
+
+        let id = author.note("hello").id;
+        let short_id = format!("note:{}", &id.as_hex_string()[..8]);
+        let note = NostrBech32::Id(id).to_string();
+        let nevent = NostrBech32::NEvent(NEvent {
+            id,
+            relays: vec![UncheckedUrl::from_str("wss://relay.example.com")],
+            kind: None,
+            author: None,
+        })
+        .to_string();
+        assert_eq!(
+            decode_references(&format!("{} nostr:{}", note, nevent), &names, false),
+            format!("{} {}", short_id, short_id)
+        );
+
+        let naddr = NostrBech32::NAddr(NAddr {
+            d: "post\x1b[2J".to_owned(),
+            relays: vec![],
+            kind: 30023u32.into(),
+            author: author.pubkey,
+        })
+        .to_string();
+        assert_eq!(
+            decode_references(&naddr, &names, false),
+            "naddr:30023:post^[[2J"
+        );
+
+        // Things that only look like references are left as they are
+        for text in ["npub1qqqqqq", "nostr:note1xyz", "nevent1", "an npub1 here"] {
+            assert_eq!(decode_references(text, &names, false), text);
+        }
+    }
+
+    #[test]
+    fn ages() {
+        let now = 1_700_000_000;
+        assert_eq!(relative_age(now, now), "0s ago");
+        assert_eq!(relative_age(now - 59, now), "59s ago");
+        assert_eq!(relative_age(now - 60, now), "1m ago");
+        assert_eq!(relative_age(now - 3599, now), "59m ago");
+        assert_eq!(relative_age(now - 3600, now), "1h ago");
+        assert_eq!(relative_age(now - 86400 * 3, now), "3d ago");
+        assert_eq!(relative_age(now - 31_536_000 * 2, now), "2y ago");
+        assert_eq!(relative_age(now + 300, now), "in 5m");
+        assert_eq!(relative_age(now, now - 86400), "in 1d");
+    }
+
+    #[test]
+    fn control_characters_are_made_visible() {
+        assert_eq!(printable("plain\ttext\nok"), "plain\ttext\nok");
+        assert_eq!(printable("\x1b[2Jgone\r\x07\x7f"), "^[[2Jgone^M^G^?");
+        assert_eq!(printable("a\u{9b}31mb"), "a\u{FFFD}31mb");
+
+        let author = Author::new();
+        let mut names = Names::default();
+        names.learn(&author.sign(0, 1, vec![], r#"{"name":"\u001b[31mEve"}"#));
+        assert_eq!(names.get(&author.pubkey), Some("^[[31mEve"));
+
+        let event = author.sign(
+            1,
+            1,
+            vec![Tag::new(&["t", "\x1b]0;title\x07"])],
+            "\x1b[2J\x1b[Hhello",
+        );
+        let rendered = pretty(&event, &names, false);
+        assert!(!rendered.contains('\x1b'), "{:?}", rendered);
+        assert!(rendered.contains("^[[2J^[[Hhello"));
+        assert!(rendered.contains("^[]0;title^G"));
+    }
+}
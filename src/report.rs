@@ -0,0 +1,178 @@
+//! Machine-readable reports of relay test results, for dashboards and CI.
+
+use crate::conformance::{Outcome, TestResult};
+use colorful::{Color, Colorful};
+use std::fmt::Write;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+    Junit,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "junit" => Ok(Format::Junit),
+            other => Err(format!(
+                "Unknown report format {}, expected text, json or junit",
+                other
+            )),
+        }
+    }
+}
+
+fn counts(results: &[TestResult]) -> (usize, usize, usize) {
+    let mut passed = 0;
+    let mut failed = 0;
+    let mut skipped = 0;
+    for result in results {
+        match result.outcome {
+            Outcome::Pass => passed += 1,
+            Outcome::Fail(_) => failed += 1,
+            Outcome::Skip(_) => skipped += 1,
+        }
+    }
+    (passed, failed, skipped)
+}
+
+/// A JSON report
+pub fn json(suite: &str, relay_url: &str, results: &[TestResult]) -> serde_json::Value {
+    let (passed, failed, skipped) = counts(results);
+    let tests: Vec<serde_json::Value> = results
+        .iter()
+        .map(|r| {
+            let (outcome, message) = match &r.outcome {
+                Outcome::Pass => ("pass", None),
+                Outcome::Fail(m) => ("fail", Some(m)),
+                Outcome::Skip(m) => ("skip", Some(m)),
+            };
+            serde_json::json!({
+                "name": r.name,
+                "nip": r.nip,
+                "outcome": outcome,
+                "message": message,
+                "duration_ms": r.duration.as_millis() as u64,
+                "responses": r.responses,
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "suite": suite,
+        "relay": relay_url,
+        "passed": passed,
+        "failed": failed,
+        "skipped": skipped,
+        "tests": tests,
+    })
+}
+
+/// A JUnit XML report
+pub fn junit(suite: &str, relay_url: &str, results: &[TestResult]) -> String {
+    let (_, failed, skipped) = counts(results);
+    let total_secs: f64 = results.iter().map(|r| r.duration.as_secs_f64()).sum();
+
+    let mut xml = String::new();
+    let _ = writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(xml, "<testsuites>");
+    let _ = writeln!(
+        xml,
+        r#"  <testsuite name="{}" tests="{}" failures="{}" skipped="{}" time="{:.3}">"#,
+        escape(&format!("{} {}", suite, relay_url)),
+        results.len(),
+        failed,
+        skipped,
+        total_secs
+    );
+    for r in results {
+        let _ = writeln!(
+            xml,
+            r#"    <testcase classname="NIP-{:02}" name="{}" time="{:.3}">"#,
+            r.nip,
+            escape(r.name),
+            r.duration.as_secs_f64()
+        );
+        match &r.outcome {
+            Outcome::Pass => {}
+            Outcome::Fail(m) => {
+                let _ = writeln!(xml, r#"      <failure message="{}"/>"#, escape(m));
+            }
+            Outcome::Skip(m) => {
+                let _ = writeln!(xml, r#"      <skipped message="{}"/>"#, escape(m));
+            }
+        }
+        if !r.responses.is_empty() {
+            let _ = writeln!(
+                xml,
+                "      <system-out>{}</system-out>",
+                escape(&r.responses.join("\n"))
+            );
+        }
+        let _ = writeln!(xml, "    </testcase>");
+    }
+    let _ = writeln!(xml, "  </testsuite>");
+    let _ = writeln!(xml, "</testsuites>");
+    xml
+}
+
+/// Print a coloured human readable report
+pub fn print_text(results: &[TestResult]) {
+    for r in results {
+        let (status, detail) = match &r.outcome {
+            Outcome::Pass => ("PASS".color(Color::Green).to_string(), String::new()),
+            Outcome::Fail(m) => ("FAIL".color(Color::Red).to_string(), format!(": {}", m)),
+            Outcome::Skip(m) => ("SKIP".color(Color::Yellow).to_string(), format!(": {}", m)),
+        };
+        println!(
+            "{} NIP-{:02} {} ({} ms){}",
+            status,
+            r.nip,
+            r.name,
+            r.duration.as_millis(),
+            detail
+        );
+    }
+    let (_, failed, _) = counts(results);
+    println!("{} checks, {} failed", results.len(), failed);
+}
+
+/// Print a report in the given format
+pub fn print(format: Format, suite: &str, relay_url: &str, results: &[TestResult]) {
+    match format {
+        Format::Text => print_text(results),
+        Format::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&json(suite, relay_url, results)).unwrap_or_default()
+        ),
+        Format::Junit => print!("{}", junit(suite, relay_url, results)),
+    }
+}
+
+/// Whether any test failed
+pub fn any_failed(results: &[TestResult]) -> bool {
+    counts(results).1 > 0
+}
+
+// Escape text for use in XML attributes and content, dropping characters XML 1.0 forbids
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\n' | '\r' | '\t' => out.push(c),
+            c if (c as u32) < 0x20 => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,641 @@
+//! A local on-disk archive of events.
+//!
+//! Events are kept in an append-only JSONL log. Indexes by id, author, kind, single
+//! letter tag and created_at are held in memory and rebuilt by replaying the log when
+//! the store is opened. Replaying applies the same rules a relay does: ephemeral events
+//! are not stored, newer replaceable events supersede older ones, and NIP-09 deletions
+//! hide the events they delete.
+
+use crate::filter::{tag_fields, FilterMatcher};
+use nostr_types::{Event, Filter, Id};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const LOG_FILE: &str = "events.jsonl";
+
+/// What happened when an event was added to the store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Added {
+    Stored,
+    Duplicate,
+    /// A newer version of this replaceable event is already stored
+    Superseded,
+    /// It was deleted by its author
+    Deleted,
+    /// Ephemeral events are not stored
+    Ephemeral,
+}
+
+// Where an event lives in the log. Slots are indexes into Store::entries.
+struct Entry {
+    id: [u8; 32],
+    offset: u64,
+    len: usize,
+    created_at: i64,
+    author: String,
+    kind: u32,
+    tags: Vec<(String, String)>,
+    hidden: bool,
+}
+
+type Slots = BTreeSet<(i64, usize)>;
+
+pub struct Store {
+    file: File,
+    end: u64,
+    entries: Vec<Entry>,
+    by_id: HashMap<[u8; 32], usize>,
+    by_author: HashMap<String, Slots>,
+    by_kind: HashMap<u32, Slots>,
+    by_tag: HashMap<(String, String), Slots>,
+    by_time: Slots,
+    // replaceable address -> slot of the current version
+    by_address: HashMap<String, usize>,
+    // (author, deleted id or address) -> created_at of the deletion
+    deletions: HashMap<(String, String), i64>,
+}
+
+impl Store {
+    /// The default archive directory
+    pub fn default_path() -> PathBuf {
+        let mut path = match dirs::data_dir() {
+            Some(d) => d,
+            None => panic!("No data_dir defined for your operating system"),
+        };
+        path.push("nostr-probe");
+        path.push("archive");
+        path
+    }
+
+    /// Open (or create) the store in `dir`, rebuilding indexes from the log
+    pub fn open(dir: &Path) -> Result<Store, Box<dyn std::error::Error>> {
+        std::fs::create_dir_all(dir)?;
+        let mut path = dir.to_path_buf();
+        path.push(LOG_FILE);
+
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)?;
+
+        let mut store = Store {
+            file: file.try_clone()?,
+            end: 0,
+            entries: Vec::new(),
+            by_id: HashMap::new(),
+            by_author: HashMap::new(),
+            by_kind: HashMap::new(),
+            by_tag: HashMap::new(),
+            by_time: BTreeSet::new(),
+            by_address: HashMap::new(),
+            deletions: HashMap::new(),
+        };
+
+        // Read bytes rather than strings so that a corrupt line fails by itself
+        let mut reader = BufReader::new(file);
+        let mut line: Vec<u8> = Vec::new();
+        let mut offset: u64 = 0;
+        loop {
+            line.clear();
+            let len = reader.read_until(b'\n', &mut line)?;
+            if len == 0 {
+                break;
+            }
+            if line.ends_with(b"\n") {
+                match serde_json::from_slice::<Event>(&line) {
+                    Ok(event) => {
+                        store.index(&event, offset, len);
+                    }
+                    Err(e) => eprintln!("Skipping bad archive line at byte {}: {}", offset, e),
+                }
+            } else {
+                // A partial write from an interrupted append; it will be overwritten
+                eprintln!("Ignoring incomplete archive line at byte {}", offset);
+                break;
+            }
+            offset += len as u64;
+        }
+        store.end = offset;
+        store.file.set_len(offset)?;
+
+        Ok(store)
+    }
+
+    /// The number of events stored and visible
+    pub fn len(&self) -> usize {
+        self.by_time.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_time.is_empty()
+    }
+
+    /// Whether we have this event, even if it has since been superseded or deleted
+    pub fn contains(&self, id: Id) -> bool {
+        self.by_id.contains_key(&id.0)
+    }
+
+    /// Add an event. It is not verified here; callers should verify first.
+    pub fn add(&mut self, event: &Event) -> Result<Added, Box<dyn std::error::Error>> {
+        if self.by_id.contains_key(&event.id.0) {
+            return Ok(Added::Duplicate);
+        }
+        if is_ephemeral(event.kind.into()) {
+            return Ok(Added::Ephemeral);
+        }
+
+        let mut line = serde_json::to_string(event)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        let offset = self.end;
+        self.end += line.len() as u64;
+
+        Ok(self.index(event, offset, line.len()))
+    }
+
+    /// Flush appended events to disk
+    pub fn sync(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Get an event by id, if it is stored and visible
+    pub fn get(&self, id: Id) -> Result<Option<Event>, Box<dyn std::error::Error>> {
+        match self.by_id.get(&id.0) {
+            Some(&slot) if !self.entries[slot].hidden => Ok(Some(self.read(slot)?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Run filters the way a relay does: events matching any filter, newest first,
+    /// with each filter's limit keeping only its newest matches
+    pub fn query(&self, filters: &[Filter]) -> Result<Vec<Event>, Box<dyn std::error::Error>> {
+        let mut slots: HashSet<usize> = HashSet::new();
+        let mut results: Vec<Event> = Vec::new();
+        for filter in filters {
+            let matcher = FilterMatcher::new(filter);
+            let mut count: usize = 0;
+            for slot in self.candidates(&matcher) {
+                if matcher.limit.map(|l| count >= l).unwrap_or(false) {
+                    break;
+                }
+                let event = self.read(slot)?;
+                if matcher.matches(&event) {
+                    count += 1;
+                    if slots.insert(slot) {
+                        results.push(event);
+                    }
+                }
+            }
+        }
+        results.sort_by(|a, b| {
+            b.created_at
+                .0
+                .cmp(&a.created_at.0)
+                .then_with(|| a.id.0.cmp(&b.id.0))
+        });
+        Ok(results)
+    }
+
+    // Visible slots that might match, newest first, from the narrowest index we have
+    fn candidates(&self, matcher: &FilterMatcher) -> Vec<usize> {
+        let since = matcher.since.unwrap_or(i64::MIN);
+        let until = matcher.until.unwrap_or(i64::MAX);
+        if since > until {
+            return Vec::new();
+        }
+
+        if let Some(ids) = &matcher.ids {
+            let mut found: Vec<(i64, usize)> = Vec::new();
+            for hex in ids {
+                if let Ok(bytes) = hex::decode(hex) {
+                    if let Ok(id) = <[u8; 32]>::try_from(bytes) {
+                        if let Some(&slot) = self.by_id.get(&id) {
+                            if !self.entries[slot].hidden {
+                                found.push((self.entries[slot].created_at, slot));
+                            }
+                        }
+                        continue;
+                    }
+                }
+                // A prefix, so we have to scan
+                return self.range(&self.by_time, since, until);
+            }
+            found.sort_by(|a, b| b.cmp(a));
+            return found.into_iter().map(|(_, s)| s).collect();
+        }
+
+        let mut sets: Vec<Vec<&Slots>> = Vec::new();
+        if let Some(authors) = &matcher.authors {
+            if authors.iter().all(|a| a.len() == 64) {
+                sets.push(
+                    authors
+                        .iter()
+                        .filter_map(|a| self.by_author.get(a))
+                        .collect(),
+                );
+            }
+        }
+        if let Some(kinds) = &matcher.kinds {
+            sets.push(kinds.iter().filter_map(|k| self.by_kind.get(k)).collect());
+        }
+        for (name, values) in &matcher.tags {
+            sets.push(
+                values
+                    .iter()
+                    .filter_map(|v| self.by_tag.get(&(name.clone(), v.clone())))
+                    .collect(),
+            );
+        }
+
+        // Use whichever field narrows things the most
+        let narrowest = sets
+            .into_iter()
+            .min_by_key(|set| set.iter().map(|s| s.len()).sum::<usize>());
+        match narrowest {
+            Some(set) => {
+                let mut merged: BTreeSet<(i64, usize)> = BTreeSet::new();
+                for slots in set {
+                    merged.extend(slots.range((since, 0)..=(until, usize::MAX)));
+                }
+                merged.into_iter().rev().map(|(_, s)| s).collect()
+            }
+            None => self.range(&self.by_time, since, until),
+        }
+    }
+
+    fn range(&self, slots: &Slots, since: i64, until: i64) -> Vec<usize> {
+        slots
+            .range((since, 0)..=(until, usize::MAX))
+            .rev()
+            .map(|(_, s)| *s)
+            .collect()
+    }
+
+    fn read(&self, slot: usize) -> Result<Event, Box<dyn std::error::Error>> {
+        let entry = &self.entries[slot];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(entry.offset))?;
+        let mut buffer = vec![0u8; entry.len];
+        file.read_exact(&mut buffer)?;
+        Ok(serde_json::from_slice(&buffer)?)
+    }
+
+    // Index an event that is at `offset` in the log
+    fn index(&mut self, event: &Event, offset: u64, len: usize) -> Added {
+        let slot = self.entries.len();
+        let created_at = event.created_at.0;
+        let author = event.pubkey.as_hex_string();
+        let kind: u32 = event.kind.into();
+        let tags = tag_fields(event);
+
+        // Relays only index single letter tags
+        let indexed_tags: Vec<(String, String)> = tags
+            .iter()
+            .filter(|t| t.len() >= 2 && t[0].chars().count() == 1)
+            .map(|t| (t[0].clone(), t[1].clone()))
+            .collect();
+
+        self.entries.push(Entry {
+            id: event.id.0,
+            offset,
+            len,
+            created_at,
+            author: author.clone(),
+            kind,
+            tags: indexed_tags,
+            hidden: true,
+        });
+        self.by_id.insert(event.id.0, slot);
+
+        // Has this already been deleted?
+        let address = replaceable_address(event);
+        let deleted = self
+            .deletions
+            .contains_key(&(author.clone(), event.id.as_hex_string()))
+            || address
+                .as_ref()
+                .and_then(|a| self.deletions.get(&(author.clone(), a.clone())))
+                .map(|&t| t >= created_at)
+                .unwrap_or(false);
+        if deleted {
+            return Added::Deleted;
+        }
+
+        // Is there a newer version of this replaceable event? Of two versions with the
+        // same created_at, NIP-01 keeps the one with the lowest id.
+        if let Some(address) = &address {
+            if let Some(&current) = self.by_address.get(address) {
+                let entry = &self.entries[current];
+                if (entry.created_at, std::cmp::Reverse(entry.id))
+                    > (created_at, std::cmp::Reverse(event.id.0))
+                {
+                    return Added::Superseded;
+                }
+                self.hide(current);
+            }
+            self.by_address.insert(address.clone(), slot);
+        }
+
+        // Apply deletions
+        if kind == 5 {
+            for tag in &tags {
+                if tag.len() < 2 || (tag[0] != "e" && tag[0] != "a") {
+                    continue;
+                }
+                self.deletions
+                    .insert((author.clone(), tag[1].clone()), created_at);
+                let target = if tag[0] == "e" {
+                    hex::decode(&tag[1])
+                        .ok()
+                        .and_then(|b| <[u8; 32]>::try_from(b).ok())
+                        .and_then(|id| self.by_id.get(&id).copied())
+                } else {
+                    self.by_address
+                        .get(&tag[1])
+                        .copied()
+                        .filter(|&t| self.entries[t].created_at <= created_at)
+                };
+                // Only the author may delete their events
+                if let Some(target) = target {
+                    if self.entries[target].author == author {
+                        self.hide(target);
+                    }
+                }
+            }
+        }
+
+        self.show(slot);
+        Added::Stored
+    }
+
+    // Add a slot to the query indexes
+    fn show(&mut self, slot: usize) {
+        let entry = &mut self.entries[slot];
+        entry.hidden = false;
+        let key = (entry.created_at, slot);
+        self.by_time.insert(key);
+        self.by_author
+            .entry(entry.author.clone())
+            .or_default()
+            .insert(key);
+        self.by_kind.entry(entry.kind).or_default().insert(key);
+        for tag in &entry.tags {
+            self.by_tag.entry(tag.clone()).or_default().insert(key);
+        }
+    }
+
+    // Remove a slot from the query indexes
+    fn hide(&mut self, slot: usize) {
+        let entry = &mut self.entries[slot];
+        if entry.hidden {
+            return;
+        }
+        entry.hidden = true;
+        let key = (entry.created_at, slot);
+        self.by_time.remove(&key);
+        if let Some(slots) = self.by_author.get_mut(&entry.author) {
+            slots.remove(&key);
+        }
+        if let Some(slots) = self.by_kind.get_mut(&entry.kind) {
+            slots.remove(&key);
+        }
+        for tag in &entry.tags {
+            if let Some(slots) = self.by_tag.get_mut(tag) {
+                slots.remove(&key);
+            }
+        }
+    }
+}
+
+/// Kinds 20000-29999 are ephemeral
+pub fn is_ephemeral(kind: u32) -> bool {
+    (20000..30000).contains(&kind)
+}
+
+/// Kinds 0, 3 and 10000-19999 are replaceable
+pub fn is_replaceable(kind: u32) -> bool {
+    kind == 0 || kind == 3 || (10000..20000).contains(&kind)
+}
+
+/// Kinds 30000-39999 are parameterized replaceable (addressable)
+pub fn is_parameterized_replaceable(kind: u32) -> bool {
+    (30000..40000).contains(&kind)
+}
+
+/// The `kind:pubkey:d` address of a replaceable event, which later versions share
+pub fn replaceable_address(event: &Event) -> Option<String> {
+    let kind: u32 = event.kind.into();
+    if is_replaceable(kind) {
+        Some(format!("{}:{}:", kind, event.pubkey.as_hex_string()))
+    } else if is_parameterized_replaceable(kind) {
+        let d = tag_fields(event)
+            .into_iter()
+            .find(|t| t.first().map(|n| n == "d").unwrap_or(false))
+            .and_then(|t| t.into_iter().nth(1))
+            .unwrap_or_default();
+        Some(format!("{}:{}:{}", kind, event.pubkey.as_hex_string(), d))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conformance::Author;
+    use nostr_types::Tag;
+
+    // A store in a fresh directory under the system temp directory
+    fn temp_store() -> (Store, PathBuf) {
+        let mut dir = std::env::temp_dir();
+        dir.push(format!(
+            "nostr-probe-store-test-{}",
+            hex::encode(rand::random::<[u8; 8]>())
+        ));
+        (Store::open(&dir).unwrap(), dir)
+    }
+
+    fn filter(json: &str) -> Filter {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn contents(events: &[Event]) -> Vec<&str> {
+        events.iter().map(|e| e.content.as_str()).collect()
+    }
+
+    #[test]
+    fn limit_keeps_the_newest_and_ranges_are_inclusive() {
+        let (mut store, dir) = temp_store();
+        let author = Author::new();
+        for (t, content) in [(30, "c"), (10, "a"), (50, "e"), (20, "b"), (40, "d")] {
+            store.add(&author.sign(1, t, vec![], content)).unwrap();
+        }
+        store.add(&author.sign(7, 60, vec![], "+")).unwrap();
+
+        let newest = store
+            .query(&[filter(r#"{"kinds":[1],"limit":2}"#)])
+            .unwrap();
+        assert_eq!(contents(&newest), ["e", "d"]);
+
+        let range = store
+            .query(&[filter(r#"{"kinds":[1],"since":20,"until":40}"#)])
+            .unwrap();
+        assert_eq!(contents(&range), ["d", "c", "b"]);
+
+        let none = store
+            .query(&[filter(r#"{"since":41,"until":40}"#)])
+            .unwrap();
+        assert!(none.is_empty());
+
+        // Each filter's limit applies separately, and results are merged newest first
+        let both = store
+            .query(&[
+                filter(r#"{"kinds":[1],"limit":1}"#),
+                filter(r#"{"kinds":[7]}"#),
+            ])
+            .unwrap();
+        assert_eq!(contents(&both), ["+", "e"]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn newer_replaceable_versions_supersede_older_ones() {
+        let (mut store, dir) = temp_store();
+        let author = Author::new();
+        let old = author.sign(0, 10, vec![], "old");
+        let new = author.sign(0, 20, vec![], "new");
+        assert_eq!(store.add(&new).unwrap(), Added::Stored);
+        assert_eq!(store.add(&old).unwrap(), Added::Superseded);
+
+        let found = store.query(&[filter(r#"{"kinds":[0]}"#)]).unwrap();
+        assert_eq!(contents(&found), ["new"]);
+        assert!(store.get(old.id).unwrap().is_none());
+
+        // Addressable events are replaced per d tag
+        store
+            .add(&author.sign(30023, 10, vec![Tag::new(&["d", "x"])], "x1"))
+            .unwrap();
+        store
+            .add(&author.sign(30023, 20, vec![Tag::new(&["d", "x"])], "x2"))
+            .unwrap();
+        store
+            .add(&author.sign(30023, 15, vec![Tag::new(&["d", "y"])], "y1"))
+            .unwrap();
+        let found = store.query(&[filter(r#"{"kinds":[30023]}"#)]).unwrap();
+        assert_eq!(contents(&found), ["x2", "y1"]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn replaceable_ties_keep_the_lowest_id() {
+        let author = Author::new();
+        let a = author.sign(10002, 10, vec![], "a");
+        let b = author.sign(10002, 10, vec![], "b");
+        let lowest = if a.id.0 < b.id.0 { "a" } else { "b" };
+
+        // Whichever order they arrive in
+        for order in [[&a, &b], [&b, &a]] {
+            let (mut store, dir) = temp_store();
+            for event in order {
+                store.add(event).unwrap();
+            }
+            let found = store.query(&[filter(r#"{"kinds":[10002]}"#)]).unwrap();
+            assert_eq!(contents(&found), [lowest]);
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn deletions_hide_events_and_survive_reopening() {
+        let (mut store, dir) = temp_store();
+        let author = Author::new();
+        let other = Author::new();
+        let note = author.sign(1, 10, vec![], "note");
+        let theirs = other.sign(1, 11, vec![], "theirs");
+        store.add(&note).unwrap();
+        store.add(&theirs).unwrap();
+
+        // Only the author's own events are deleted
+        let note_id = note.id.as_hex_string();
+        let theirs_id = theirs.id.as_hex_string();
+        let deletion = author.sign(
+            5,
+            20,
+            vec![Tag::new(&["e", &note_id]), Tag::new(&["e", &theirs_id])],
+            "",
+        );
+        store.add(&deletion).unwrap();
+
+        let notes = store.query(&[filter(r#"{"kinds":[1]}"#)]).unwrap();
+        assert_eq!(contents(&notes), ["theirs"]);
+
+        drop(store);
+        let store = Store::open(&dir).unwrap();
+        let notes = store.query(&[filter(r#"{"kinds":[1]}"#)]).unwrap();
+        assert_eq!(contents(&notes), ["theirs"]);
+        assert!(store.contains(note.id));
+        assert_eq!(store.len(), 2);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn queries_by_author_and_tag() {
+        let (mut store, dir) = temp_store();
+        let author = Author::new();
+        let other = Author::new();
+        let pubkey = other.pubkey.as_hex_string();
+        store
+            .add(&author.sign(1, 10, vec![Tag::new(&["p", &pubkey])], "mention"))
+            .unwrap();
+        store
+            .add(&author.sign(1, 20, vec![Tag::new(&["t", "nostr"])], "hashtag"))
+            .unwrap();
+        store.add(&other.sign(1, 30, vec![], "other")).unwrap();
+
+        let json = format!(r#"{{"authors":["{}"]}}"#, pubkey);
+        assert_eq!(contents(&store.query(&[filter(&json)]).unwrap()), ["other"]);
+        let json = format!(r##"{{"#p":["{}"]}}"##, pubkey);
+        assert_eq!(
+            contents(&store.query(&[filter(&json)]).unwrap()),
+            ["mention"]
+        );
+        let tagged = store.query(&[filter(r##"{"#t":["nostr"]}"##)]).unwrap();
+        assert_eq!(contents(&tagged), ["hashtag"]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_lines_are_skipped() {
+        let (mut store, dir) = temp_store();
+        let author = Author::new();
+        store.add(&author.sign(1, 10, vec![], "before")).unwrap();
+        store.sync().unwrap();
+        drop(store);
+
+        let mut log = dir.clone();
+        log.push(LOG_FILE);
+        let mut file = OpenOptions::new().append(true).open(&log).unwrap();
+        file.write_all(b"{\"id\":\"\xff\xfe\"}\nnot json\n")
+            .unwrap();
+        drop(file);
+
+        let mut store = Store::open(&dir).unwrap();
+        store.add(&author.sign(1, 20, vec![], "after")).unwrap();
+        store.sync().unwrap();
+        drop(store);
+
+        let store = Store::open(&dir).unwrap();
+        let notes = store.query(&[filter(r#"{"kinds":[1]}"#)]).unwrap();
+        assert_eq!(contents(&notes), ["after", "before"]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
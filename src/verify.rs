@@ -0,0 +1,152 @@
+use nostr_types::{Event, Id, Unixtime};
+use secp256k1::hashes::Hash;
+use serde_json::Value;
+use std::fmt;
+
+/// Why an event failed verification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Failure {
+    /// The line was not JSON, or not shaped like an event
+    BadJson(String),
+
+    /// The id does not match the hash of the event
+    IdMismatch { claimed: String, computed: String },
+
+    /// The signature does not verify against the pubkey
+    BadSignature,
+
+    /// The event is dated further into the future than we allow
+    FutureTimestamp { created_at: i64, now: i64 },
+
+    /// A tag is not a non-empty array of strings, or a well-known tag
+    /// has a malformed value
+    InvalidTag { index: usize, reason: String },
+}
+
+impl Failure {
+    /// A short stable name for this kind of failure, used for summaries
+    pub fn class(&self) -> &'static str {
+        match self {
+            Failure::BadJson(_) => "bad-json",
+            Failure::IdMismatch { .. } => "id-mismatch",
+            Failure::BadSignature => "bad-signature",
+            Failure::FutureTimestamp { .. } => "future-timestamp",
+            Failure::InvalidTag { .. } => "invalid-tag",
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::BadJson(e) => write!(f, "bad-json: {}", e),
+            Failure::IdMismatch { claimed, computed } => {
+                write!(f, "id-mismatch: claimed {} computed {}", claimed, computed)
+            }
+            Failure::BadSignature => write!(f, "bad-signature"),
+            Failure::FutureTimestamp { created_at, now } => write!(
+                f,
+                "future-timestamp: created_at {} is {}s ahead",
+                created_at,
+                created_at - now
+            ),
+            Failure::InvalidTag { index, reason } => {
+                write!(f, "invalid-tag: tag {}: {}", index, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// Compute the NIP-01 id of an event from its contents (ignoring its `id` field)
+pub fn compute_id(event: &Event) -> Id {
+    let serial = serde_json::json!([
+        0,
+        event.pubkey.as_hex_string(),
+        event.created_at.0,
+        Into::<u32>::into(event.kind),
+        event.tags,
+        event.content,
+    ]);
+    let hash = secp256k1::hashes::sha256::Hash::hash(serial.to_string().as_bytes());
+    Id(hash.to_byte_array())
+}
+
+/// Parse and fully check a single event given as JSON.
+///
+/// Events with `created_at` more than `max_future_secs` ahead of now are rejected.
+pub fn check_event_json(json: &str, max_future_secs: i64) -> Result<Event, Failure> {
+    let value: Value = serde_json::from_str(json).map_err(|e| Failure::BadJson(e.to_string()))?;
+
+    // Check tag shapes before typed parsing so that these are not reported as bad json
+    if let Some(tags) = value.get("tags").and_then(|t| t.as_array()) {
+        for (index, tag) in tags.iter().enumerate() {
+            check_tag_shape(tag).map_err(|reason| Failure::InvalidTag { index, reason })?;
+        }
+    }
+
+    let event: Event =
+        serde_json::from_value(value).map_err(|e| Failure::BadJson(e.to_string()))?;
+
+    let computed = compute_id(&event);
+    if computed != event.id {
+        return Err(Failure::IdMismatch {
+            claimed: event.id.as_hex_string(),
+            computed: computed.as_hex_string(),
+        });
+    }
+
+    // The id is right, so any remaining verify failure is the signature
+    if event.verify(None).is_err() {
+        return Err(Failure::BadSignature);
+    }
+
+    let now = Unixtime::now().0;
+    if event.created_at.0 > now + max_future_secs {
+        return Err(Failure::FutureTimestamp {
+            created_at: event.created_at.0,
+            now,
+        });
+    }
+
+    Ok(event)
+}
+
+fn check_tag_shape(tag: &Value) -> Result<(), String> {
+    let fields = match tag.as_array() {
+        Some(a) => a,
+        None => return Err("not an array".to_owned()),
+    };
+    if fields.is_empty() {
+        return Err("empty".to_owned());
+    }
+    let mut strings: Vec<&str> = Vec::with_capacity(fields.len());
+    for field in fields {
+        match field.as_str() {
+            Some(s) => strings.push(s),
+            None => return Err(format!("non-string field {}", field)),
+        }
+    }
+    match strings[0] {
+        "" => Err("empty tag name".to_owned()),
+        "e" | "p" => match strings.get(1) {
+            Some(v) if is_hex64(v) => Ok(()),
+            Some(v) => Err(format!(
+                "{} value is not 64 hex characters: {}",
+                strings[0], v
+            )),
+            None => Err(format!("{} tag has no value", strings[0])),
+        },
+        "a" => match strings.get(1) {
+            Some(v) if v.splitn(3, ':').count() == 3 => Ok(()),
+            Some(v) => Err(format!("a value is not kind:pubkey:d: {}", v)),
+            None => Err("a tag has no value".to_owned()),
+        },
+        _ => Ok(()),
+    }
+}
+
+fn is_hex64(s: &str) -> bool {
+    s.len() == 64 && s.bytes().all(|b| b.is_ascii_hexdigit())
+}